@@ -0,0 +1,151 @@
+use std::{fs, io, path::Path};
+
+use rusqlite::Connection;
+
+use crate::{
+    database::operations,
+    utils::{
+        core::collect_media_files,
+        devices::find_device,
+        duplicates::{calculate_full_hash, calculate_hash},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics audit <folder>
+
+Checks every photo and video under <folder> (e.g. an SD card) against the catalog of every
+disk scanned so far, without those disks being plugged in. Each file is reported as backed
+up on two or more devices, backed up once only, or new. A copy counts when it matches on
+size and the first 128 KB, unless `scrub` recorded a different full SHA-256 for it; copies
+`scrub` never verified are listed as such. Nothing is written to the catalog.
+Written to ./csv_exports/audit.csv";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let [folder] = args else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    let source = Path::new(folder);
+    let source_device = find_device(conn, source)?.map(|device| device.id);
+
+    let mut files = Vec::new();
+    collect_media_files(source, &mut files)?;
+
+    let mut wrt = csv::Writer::from_path("./csv_exports/audit.csv").map_err(io::Error::other)?;
+    wrt.write_record(["Path", "Status", "Devices", "Unverified Devices"])
+        .map_err(io::Error::other)?;
+
+    let (mut backed_up, mut once_only, mut new) = (0, 0, 0);
+
+    for path in files {
+        let copies = match devices_with_copy(conn, &path, source_device) {
+            Ok(copies) => copies,
+            Err(e) => {
+                println!(
+                    "Error hashing file; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let devices = copies
+            .iter()
+            .map(|copy| copy.label.as_str())
+            .collect::<Vec<_>>();
+        let unverified_devices = copies
+            .iter()
+            .filter(|copy| !copy.verified)
+            .map(|copy| copy.label.as_str())
+            .collect::<Vec<_>>();
+
+        let (status, message) = match devices.len() {
+            0 => {
+                new += 1;
+                ("new", "new".to_string())
+            }
+            1 => {
+                once_only += 1;
+                ("once_only", format!("backed up once only ({})", devices[0]))
+            }
+            _ => {
+                backed_up += 1;
+                (
+                    "backed_up",
+                    format!("already backed up on {}", devices.join(", ")),
+                )
+            }
+        };
+
+        println!("{}\t{}", path.display(), message);
+
+        wrt.write_record([
+            path.to_str().unwrap_or(""),
+            status,
+            &devices.join(";"),
+            &unverified_devices.join(";"),
+        ])
+        .map_err(io::Error::other)?;
+    }
+
+    wrt.flush()?;
+
+    println!(
+        "{} backed up on 2+ devices, {} backed up once only, {} new",
+        backed_up, once_only, new
+    );
+
+    Ok(())
+}
+
+/// A device holding a copy of the file being audited
+struct BackupCopy {
+    label: String,
+    /// `scrub` read the copy back whole and it hashed to the same full SHA-256
+    verified: bool,
+}
+
+/// Devices holding a copy that matches the file on size and partial hash, one entry per
+/// device. Copies `scrub` recorded a different full SHA-256 for don't count. The file is only
+/// read whole when one of the candidates has a recorded full hash to compare with.
+fn devices_with_copy(
+    conn: &Connection,
+    path: &Path,
+    exclude_device_id: Option<i64>,
+) -> io::Result<Vec<BackupCopy>> {
+    let hash = calculate_hash(path)?;
+    let file_size = fs::metadata(path)?.len();
+
+    let candidates =
+        operations::devices_with_hash(conn, &hash, file_size as i64, exclude_device_id)
+            .map_err(io::Error::other)?;
+
+    let full_hash = if candidates
+        .iter()
+        .any(|(_, _, content_hash)| content_hash.is_some())
+    {
+        Some(calculate_full_hash(path)?)
+    } else {
+        None
+    };
+
+    let mut copies: Vec<(i64, BackupCopy)> = Vec::new();
+
+    for (device_id, label, content_hash) in candidates {
+        let verified = match content_hash {
+            Some(content_hash) if Some(&content_hash) == full_hash.as_ref() => true,
+            Some(_) => continue,
+            None => false,
+        };
+
+        match copies.iter_mut().find(|(id, _)| *id == device_id) {
+            Some((_, copy)) => copy.verified |= verified,
+            None => copies.push((device_id, BackupCopy { label, verified })),
+        }
+    }
+
+    Ok(copies.into_iter().map(|(_, copy)| copy).collect())
+}
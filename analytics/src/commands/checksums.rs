@@ -0,0 +1,248 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    database::operations,
+    utils::{
+        checksums::{Algorithm, format_line, parse_line},
+        core::{collect_library_files, write_atomic},
+        devices::register_device,
+        storage::key_for,
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics checksums [--blake3] [--per-folder] <library>
+
+Writes a SHA256SUMS file to the root of <library> listing every photo, video and sidecar
+under it, in the format `sha256sum -c` checks, so the library can be verified on any
+machine without this tool. Hashes the catalog has verified for a file at its current size
+are reused, other files are read. --blake3 also writes B3SUMS, for `b3sum -c`.
+--per-folder writes them to every folder instead, listing the files directly in it, so a
+folder copied off on its own can still be checked.";
+
+const VERIFY_USAGE: &str = "Usage:
+    analytics verify-manifest <manifest or folder> [...]
+
+Checks files against SHA256SUMS and B3SUMS manifests (or `*.sha256` and `*.b3` files, in
+the GNU or BSD format), relative to the folder each manifest is in. A folder is searched
+for manifests, and photos, videos and sidecars under it that no manifest lists are
+reported as well. Fails if a listed file is missing or doesn't match.";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let mut algorithms = vec![Algorithm::Sha256];
+    let mut per_folder = false;
+    let mut library = None;
+
+    for arg in args {
+        match arg.as_str() {
+            "--blake3" => algorithms.push(Algorithm::Blake3),
+            "--per-folder" => per_folder = true,
+            _ if library.is_none() && !arg.starts_with("--") => library = Some(Path::new(arg)),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let Some(library) = library else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    if !library.is_dir() {
+        println!("Not a folder: {:?}", library);
+        return Ok(());
+    }
+
+    let mut files = Vec::new();
+    collect_library_files(library, &mut files)?;
+
+    // Manifest folder -> files it lists
+    let mut manifests = BTreeMap::<PathBuf, Vec<PathBuf>>::new();
+    for path in files {
+        let folder = match path.parent() {
+            Some(parent) if per_folder => parent.to_path_buf(),
+            _ => library.to_path_buf(),
+        };
+        manifests.entry(folder).or_default().push(path);
+    }
+
+    let device = register_device(conn, library)?;
+    let (mut from_catalog, mut read) = (0, 0);
+
+    for (folder, mut paths) in manifests {
+        paths.sort();
+
+        let mut contents = vec![String::new(); algorithms.len()];
+
+        for path in &paths {
+            let key = key_for(path.strip_prefix(&folder).unwrap_or(path));
+            let file_size = fs::metadata(path)?.len();
+
+            let verified = operations::verified_content_hash(
+                conn,
+                device.id,
+                &device.relative_path(path),
+                file_size as i64,
+            )
+            .map_err(io::Error::other)?;
+
+            for (algorithm, contents) in algorithms.iter().zip(&mut contents) {
+                let hash = match (algorithm, &verified) {
+                    (Algorithm::Sha256, Some(content_hash)) => {
+                        from_catalog += 1;
+                        content_hash.clone()
+                    }
+                    _ => {
+                        read += 1;
+                        algorithm.hash_file(path)?
+                    }
+                };
+
+                contents.push_str(&format_line(&hash, &key));
+            }
+        }
+
+        for (algorithm, contents) in algorithms.iter().zip(contents) {
+            let manifest = folder.join(algorithm.manifest_name());
+            write_atomic(&manifest, &mut contents.as_bytes())?;
+            println!("Wrote {:?} ({} file(s))", manifest, paths.len());
+        }
+    }
+
+    println!(
+        "{} hash(es) taken from the catalog, {} read from disk",
+        from_catalog, read
+    );
+
+    Ok(())
+}
+
+pub fn run_verify(args: &[String]) -> io::Result<()> {
+    if args.is_empty() || args.iter().any(|arg| arg.starts_with("--")) {
+        println!("{}", VERIFY_USAGE);
+        return Ok(());
+    }
+
+    let (mut ok, mut failed, mut missing, mut unlisted) = (0, 0, 0, 0);
+
+    for arg in args {
+        let target = Path::new(arg);
+
+        let mut manifests = Vec::new();
+        if target.is_dir() {
+            find_manifests(target, &mut manifests)?;
+        } else {
+            manifests.push(target.to_path_buf());
+        }
+
+        if manifests.is_empty() {
+            println!("No SHA256SUMS or B3SUMS under {:?}", target);
+            continue;
+        }
+
+        let mut listed = HashSet::new();
+
+        for manifest in &manifests {
+            let Some(algorithm) = Algorithm::from_manifest_name(manifest) else {
+                println!(
+                    "Not a manifest name, expected SHA256SUMS, B3SUMS, *.sha256 or *.b3: {:?}",
+                    manifest
+                );
+                failed += 1;
+                continue;
+            };
+
+            let folder = manifest.parent().unwrap_or(Path::new("."));
+
+            for line in fs::read_to_string(manifest)?.lines() {
+                if line.trim().is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let Some((hash, relative_path)) = parse_line(line) else {
+                    println!("Unreadable line in {:?}: {:?}", manifest, line);
+                    failed += 1;
+                    continue;
+                };
+
+                let path = normalize(&folder.join(&relative_path));
+                listed.insert(path.clone());
+
+                if !path.is_file() {
+                    println!("MISSING: {:?}", path);
+                    missing += 1;
+                    continue;
+                }
+
+                match algorithm.hash_file(&path) {
+                    Ok(read) if read == hash => ok += 1,
+                    Ok(_) => {
+                        println!("FAILED: {:?}", path);
+                        failed += 1;
+                    }
+                    Err(e) => {
+                        println!("FAILED: {:?} can't be read; Error : {:?}", path, e);
+                        failed += 1;
+                    }
+                }
+            }
+        }
+
+        if target.is_dir() {
+            let mut files = Vec::new();
+            collect_library_files(target, &mut files)?;
+
+            for path in files {
+                if !listed.contains(&normalize(&path)) {
+                    println!("NOT LISTED: {:?}", path);
+                    unlisted += 1;
+                }
+            }
+        }
+    }
+
+    println!(
+        "{} ok, {} failed, {} missing, {} not listed",
+        ok, failed, missing, unlisted
+    );
+
+    if failed + missing > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} file(s) failed verification", failed + missing),
+        ));
+    }
+
+    Ok(())
+}
+
+fn find_manifests(folder: &Path, manifests: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            find_manifests(&path, manifests)?;
+        } else if Algorithm::from_manifest_name(&path).is_some() {
+            manifests.push(path);
+        }
+    }
+
+    manifests.sort();
+
+    Ok(())
+}
+
+/// Drops `.` components, so `./a.jpg` in a manifest matches `a.jpg` found on disk
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
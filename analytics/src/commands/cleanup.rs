@@ -0,0 +1,423 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    ROOT_PROJECT_PATH,
+    config::{CleanupConfig, DuplicatesConfig},
+    database::operations,
+    format_size,
+    utils::{
+        core::{Media, scan_directory_skipping},
+        duplicates::calculate_full_hash,
+        quarantine::{move_file, quarantine_path_for},
+        ranking::rank_copies,
+    },
+};
+
+const CLEANUP_USAGE: &str = "Usage:
+    analytics cleanup [--dry-run] [<folder>]
+
+Scans <folder> (the library root by default) and, for every group of identical files, keeps
+the copy picked by the [duplicates] ranking and moves the others (and their XMP sidecars) to
+a quarantine folder for this session. Copies are compared on their full SHA-256 first. A
+sidecar another file still uses, like the `name.xmp` of a RAW + JPEG pair, stays.
+The library export (<root>/final_export) and the quarantine folder are never scanned, so
+backed up files are not taken for extra copies. Nothing is deleted: see `undo` and `purge`.";
+
+const UNDO_USAGE: &str = "Usage:
+    analytics undo <cleanup session id>
+
+Moves every file of a cleanup session back to where it was, sidecars included.";
+
+const PURGE_USAGE: &str = "Usage:
+    analytics purge [--older-than <days>]
+
+Deletes the quarantined files of cleanup sessions older than the grace period
+([cleanup] grace_period_days, 30 by default).";
+
+pub fn run(
+    conn: &Connection,
+    args: &[String],
+    config: &CleanupConfig,
+    ranking: &DuplicatesConfig,
+) -> io::Result<()> {
+    let (dry_run, source) = match args {
+        [] => (false, ROOT_PROJECT_PATH),
+        [flag] if flag == "--dry-run" => (true, ROOT_PROJECT_PATH),
+        [flag, folder] if flag == "--dry-run" => (true, folder.as_str()),
+        [folder] if !folder.starts_with("--") => (false, folder.as_str()),
+        _ => {
+            println!("{}", CLEANUP_USAGE);
+            return Ok(());
+        }
+    };
+
+    // The library export is a backup of what is scanned, not extra copies of it, and the
+    // quarantine holds what earlier sessions already moved
+    let skipped = [
+        PathBuf::from(format!("{}/final_export", ROOT_PROJECT_PATH)),
+        PathBuf::from(&config.quarantine_folder),
+    ];
+
+    let media_items = scan_directory_skipping(conn, Path::new(source), &skipped)?;
+
+    // A `name.xmp` can belong to several files (RAW + JPEG), so it only goes once all of them do
+    let mut sidecar_users: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for media in &media_items {
+        if let Some(sidecar) = &media.sidecar {
+            sidecar_users
+                .entry(sidecar.path.clone())
+                .or_default()
+                .push(media.file_path.clone());
+        }
+    }
+
+    let mut groups: HashMap<String, Vec<Media>> = HashMap::new();
+    for media in media_items {
+        groups.entry(media.hash.clone()).or_default().push(media);
+    }
+    groups.retain(|_, copies| copies.len() > 1);
+
+    let session = if dry_run {
+        None
+    } else {
+        let session_id = operations::new_cleanup_session(conn, source, &config.quarantine_folder)
+            .map_err(io::Error::other)?;
+        Some((
+            session_id,
+            Path::new(&config.quarantine_folder).join(session_id.to_string()),
+        ))
+    };
+
+    let mut files_moved = 0;
+    let mut bytes_moved = 0;
+
+    // Copies moved (or that would be), with the hash and kept path their sidecars are
+    // recorded under
+    let mut moved: HashMap<PathBuf, (String, PathBuf)> = HashMap::new();
+    let mut sidecars = Vec::new();
+
+    for (hash, mut copies) in groups {
+        let keep_reason = rank_copies(&mut copies, ranking);
+        let (kept, extra_copies) = copies.split_first().unwrap();
+
+        let kept_full_hash = match calculate_full_hash(&kept.file_path) {
+            Ok(full_hash) => full_hash,
+            Err(e) => {
+                println!(
+                    "Error hashing kept copy, skipping group; Path : {:?}; Error : {:?}",
+                    kept.file_path.to_str(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        for copy in extra_copies {
+            // The partial hash matched, make sure the whole file does too
+            match calculate_full_hash(&copy.file_path) {
+                Ok(full_hash) if full_hash == kept_full_hash => {}
+                Ok(_) => {
+                    println!(
+                        "Skipping {:?}: only the first 128 KB match {:?}",
+                        copy.file_path, kept.file_path
+                    );
+                    continue;
+                }
+                Err(e) => {
+                    println!(
+                        "Error hashing copy; Path : {:?}; Error : {:?}",
+                        copy.file_path.to_str(),
+                        e
+                    );
+                    continue;
+                }
+            }
+
+            match &session {
+                None => {
+                    println!(
+                        "Would move {:?} (keeping {:?}: {})",
+                        copy.file_path, kept.file_path, keep_reason
+                    );
+                    files_moved += 1;
+                    bytes_moved += copy.file_size;
+                }
+                Some((session_id, session_folder)) => match quarantine(
+                    conn,
+                    *session_id,
+                    &hash,
+                    &copy.file_path,
+                    &quarantine_path_for(session_folder, &copy.file_path),
+                    &kept.file_path,
+                ) {
+                    Ok(size) => {
+                        files_moved += 1;
+                        bytes_moved += size;
+                    }
+                    Err(e) => {
+                        println!(
+                            "Error moving file to quarantine; Path : {:?}; Error : {:?}",
+                            copy.file_path.to_str(),
+                            e
+                        );
+                        continue;
+                    }
+                },
+            }
+
+            moved.insert(
+                copy.file_path.clone(),
+                (hash.clone(), kept.file_path.clone()),
+            );
+            if let Some(sidecar) = &copy.sidecar {
+                sidecars.push((sidecar.clone(), copy.file_path.clone()));
+            }
+        }
+    }
+
+    // Sidecars follow their media file, once it is in quarantine and nothing left behind
+    // still uses them
+    sidecars.sort_by(|a, b| (&a.0.path, &a.1).cmp(&(&b.0.path, &b.1)));
+    sidecars.dedup_by(|a, b| a.0.path == b.0.path);
+
+    for (sidecar, media_path) in sidecars {
+        let sidecar_path = &sidecar.path;
+        let users = sidecar_users
+            .get(sidecar_path)
+            .map_or(&[][..], |u| u.as_slice());
+
+        if let Some(user) = users.iter().find(|user| !moved.contains_key(*user)) {
+            println!("Keeping {:?}, {:?} still uses it", sidecar_path, user);
+            continue;
+        }
+
+        let Some((session_id, session_folder)) = &session else {
+            println!("Would move {:?}", sidecar_path);
+            files_moved += 1;
+            bytes_moved += fs::metadata(sidecar_path).map_or(0, |m| m.len());
+            continue;
+        };
+
+        let (hash, kept_path) = &moved[&media_path];
+
+        // Named after its media file the way an export names it, next to it in quarantine
+        let destination = sidecar.final_path_for(
+            &media_path,
+            &quarantine_path_for(session_folder, &media_path),
+        );
+
+        match quarantine(
+            conn,
+            *session_id,
+            hash,
+            sidecar_path,
+            &destination,
+            kept_path,
+        ) {
+            Ok(size) => {
+                files_moved += 1;
+                bytes_moved += size;
+            }
+            Err(e) => println!(
+                "Error moving file to quarantine; Path : {:?}; Error : {:?}",
+                sidecar_path.to_str(),
+                e
+            ),
+        }
+    }
+
+    match session {
+        Some((session_id, session_folder)) => {
+            operations::update_cleanup_session_completed(
+                conn,
+                session_id,
+                files_moved,
+                bytes_moved as i64,
+            )
+            .map_err(io::Error::other)?;
+
+            println!(
+                "Cleanup session {}: moved {} file(s), {} to {:?}. Undo with `undo {}`",
+                session_id,
+                files_moved,
+                format_size(bytes_moved),
+                session_folder,
+                session_id
+            );
+        }
+        None => println!(
+            "Dry run: would move {} file(s), {}",
+            files_moved,
+            format_size(bytes_moved)
+        ),
+    }
+
+    Ok(())
+}
+
+/// Moves one file to `destination` in the session folder and records it; the move is
+/// reverted if it can't be recorded, so nothing ends up in quarantine without a way back
+fn quarantine(
+    conn: &Connection,
+    session_id: i64,
+    hash: &str,
+    path: &Path,
+    destination: &Path,
+    kept_path: &Path,
+) -> io::Result<u64> {
+    let size = fs::metadata(path)?.len();
+
+    move_file(path, destination)?;
+
+    let recorded = operations::insert_quarantine_move(
+        conn,
+        session_id,
+        hash,
+        path.to_str().unwrap_or(""),
+        destination.to_str().unwrap_or(""),
+        kept_path.to_str().unwrap_or(""),
+        size as i64,
+    );
+
+    if let Err(e) = recorded {
+        move_file(destination, path)?;
+        return Err(io::Error::other(e));
+    }
+
+    Ok(size)
+}
+
+pub fn run_undo(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let Some(session_id) = args.first().and_then(|a| a.parse::<i64>().ok()) else {
+        println!("{}", UNDO_USAGE);
+        return Ok(());
+    };
+
+    let Some(session) = operations::cleanup_session(conn, session_id).map_err(io::Error::other)?
+    else {
+        println!("No cleanup session {}", session_id);
+        return Ok(());
+    };
+
+    if session.status == "purged" {
+        println!("Cleanup session {} was already purged", session_id);
+        return Ok(());
+    }
+
+    let moves = operations::quarantined_moves(conn, session_id).map_err(io::Error::other)?;
+    let mut not_restored = 0;
+
+    for quarantined in &moves {
+        let original = Path::new(&quarantined.original_path);
+
+        if original.exists() {
+            println!(
+                "Not restoring {:?}: a file already exists there",
+                quarantined.original_path
+            );
+            not_restored += 1;
+            continue;
+        }
+
+        match move_file(Path::new(&quarantined.quarantine_path), original) {
+            Ok(()) => operations::mark_quarantine_move_restored(conn, quarantined.id)
+                .map_err(io::Error::other)?,
+            Err(e) => {
+                println!(
+                    "Error restoring file; Path : {:?}; Error : {:?}",
+                    quarantined.original_path, e
+                );
+                not_restored += 1;
+            }
+        }
+    }
+
+    if not_restored == 0 {
+        operations::update_cleanup_session_status(conn, session_id, "undone")
+            .map_err(io::Error::other)?;
+    }
+
+    println!(
+        "Restored {} of {} file(s) from cleanup session {}",
+        moves.len() - not_restored,
+        moves.len(),
+        session_id
+    );
+
+    Ok(())
+}
+
+pub fn run_purge(conn: &Connection, args: &[String], config: &CleanupConfig) -> io::Result<()> {
+    let grace_period_days = match args {
+        [] => config.grace_period_days,
+        [flag, days] if flag == "--older-than" => match days.parse() {
+            Ok(days) => days,
+            Err(_) => {
+                println!("{}", PURGE_USAGE);
+                return Ok(());
+            }
+        },
+        _ => {
+            println!("{}", PURGE_USAGE);
+            return Ok(());
+        }
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let completed_before =
+        now.saturating_sub(grace_period_days.saturating_mul(24 * 60 * 60)) as i64;
+
+    let sessions =
+        operations::cleanup_sessions_to_purge(conn, completed_before).map_err(io::Error::other)?;
+    let mut bytes_freed = 0;
+
+    for session in sessions {
+        let moves = operations::quarantined_moves(conn, session.id).map_err(io::Error::other)?;
+
+        for quarantined in moves {
+            match fs::remove_file(&quarantined.quarantine_path) {
+                Ok(()) => bytes_freed += quarantined.file_size_bytes as u64,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    println!(
+                        "Error deleting quarantined file; Path : {:?}; Error : {:?}",
+                        quarantined.quarantine_path, e
+                    );
+                    continue;
+                }
+            }
+
+            operations::mark_quarantine_move_purged(conn, quarantined.id)
+                .map_err(io::Error::other)?;
+        }
+
+        let session_folder: PathBuf =
+            Path::new(&session.quarantine_path).join(session.id.to_string());
+        fs::remove_dir_all(&session_folder).unwrap_or_else(|e| {
+            println!(
+                "Error removing quarantine folder; Path : {:?}; Error : {:?}",
+                session_folder.to_str(),
+                e
+            );
+        });
+
+        operations::update_cleanup_session_status(conn, session.id, "purged")
+            .map_err(io::Error::other)?;
+
+        println!("Purged cleanup session {}", session.id);
+    }
+
+    println!("Freed {}", format_size(bytes_freed));
+
+    Ok(())
+}
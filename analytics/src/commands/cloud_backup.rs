@@ -0,0 +1,504 @@
+use std::{collections::BTreeSet, fs, io, path::Path, time::UNIX_EPOCH};
+
+use chrono::Utc;
+use rusqlite::Connection;
+
+use crate::{
+    config::Config,
+    database::operations,
+    format_size,
+    utils::{
+        core::{collect_library_files, set_modified_at},
+        devices::{Device, register_device},
+        duplicates::{calculate_full_hash, calculate_hash},
+        storage::{
+            StorageBackend, key_for, key_under, object_key, open_backend, put_verified, temp_path,
+        },
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics cloud-backup [--dry-run] [--verify] [--to <storage>] <folder>
+
+Uploads every photo, video and sidecar under <folder> to the [s3] bucket, or to the
+[storage.<name>] backend given with --to. Objects are named after the SHA-256 of their
+content, so a file goes up once however many copies and names it has, and only what the
+target is missing is uploaded. Files bigger than multipart_threshold_mb go up to S3 in
+parts. --verify checks every upload's SHA-256, through the bucket's own checksum on S3
+and by reading it back elsewhere. --dry-run only prints what would be uploaded.
+
+Each run also uploads a manifest of the folder (path, size, mtime and hash of every file)
+to manifests/, which is what cloud-restore rebuilds it from. With an encrypted storage the
+manifest is encrypted like everything else.";
+
+const RESTORE_USAGE: &str = "Usage:
+    analytics cloud-restore [--from <storage>] [--manifest <key>] <folder>
+
+Rebuilds a backed up folder into <folder> from the newest manifest of the folder with the
+same name on the [s3] bucket, or on the storage given with --from (`analytics storage
+<storage> list manifests/` lists them). When no manifest has that name and more than one
+folder is backed up there, --manifest picks the one to restore. Every file is checked
+against the hash it was backed up with; files already in <folder> with the right content
+are skipped, other files there are left alone.";
+
+const MANIFESTS_FOLDER: &str = "manifests";
+
+pub fn run(conn: &Connection, args: &[String], config: &Config) -> io::Result<()> {
+    let mut dry_run = false;
+    let mut verify = false;
+    let mut storage = "s3";
+    let mut folder = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--verify" => verify = true,
+            "--to" if !args.as_slice().is_empty() => storage = args.next().unwrap(),
+            _ if folder.is_none() && !arg.starts_with("--") => folder = Some(Path::new(arg)),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let Some(folder) = folder else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    if !folder.is_dir() {
+        println!("Not a folder: {:?}", folder);
+        return Ok(());
+    }
+
+    let target = match open_backend(storage, config) {
+        Ok(target) => target,
+        Err(e) => {
+            println!("Cloud backup isn't set up; Error : {}", e);
+            return Ok(());
+        }
+    };
+
+    let options = BackupOptions { dry_run, verify };
+
+    backup_folder(conn, target.as_ref(), folder, &options)
+}
+
+struct BackupOptions {
+    dry_run: bool,
+    verify: bool,
+}
+
+enum Outcome {
+    Uploaded(u64),
+    AlreadyThere,
+}
+
+/// A backed up file, as listed in a manifest
+struct ManifestEntry {
+    content_hash: String,
+    file_size: u64,
+    modified_at: i64,
+    /// From the backed up folder, `/` separated
+    relative_path: String,
+}
+
+fn backup_folder(
+    conn: &Connection,
+    target: &dyn StorageBackend,
+    folder: &Path,
+    options: &BackupOptions,
+) -> io::Result<()> {
+    let device = register_device(conn, folder)?;
+
+    let mut files = Vec::new();
+    collect_library_files(folder, &mut files)?;
+
+    let (mut uploaded, mut bytes_uploaded, mut already_there, mut failed) = (0, 0, 0, 0);
+    let mut manifest = Vec::new();
+
+    for path in &files {
+        match backup_file(conn, target, &device, path, options) {
+            Ok((outcome, mut entry)) => {
+                match outcome {
+                    Outcome::Uploaded(bytes) => {
+                        uploaded += 1;
+                        bytes_uploaded += bytes;
+                    }
+                    Outcome::AlreadyThere => already_there += 1,
+                }
+
+                entry.relative_path = key_for(path.strip_prefix(folder).unwrap_or(path));
+                manifest.push(entry);
+            }
+            Err(e) => {
+                println!(
+                    "Error backing up file; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    if !options.dry_run {
+        let key = upload_manifest(target, folder, &manifest)?;
+        println!("Manifest of {} file(s) uploaded to {}", manifest.len(), key);
+    }
+
+    println!(
+        "{}: {} uploaded ({}), {} already there, {} failed",
+        target.name(),
+        uploaded,
+        format_size(bytes_uploaded),
+        already_there,
+        failed
+    );
+
+    Ok(())
+}
+
+/// Uploads a file unless the target already has its content. Files unchanged since
+/// their last backup aren't even read.
+fn backup_file(
+    conn: &Connection,
+    target: &dyn StorageBackend,
+    device: &Device,
+    path: &Path,
+    options: &BackupOptions,
+) -> io::Result<(Outcome, ManifestEntry)> {
+    let target_name = target.name();
+    let relative_path = device.relative_path(path);
+
+    let metadata = fs::metadata(path)?;
+    let file_size = metadata.len();
+    let modified_at = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let entry = |content_hash: String| ManifestEntry {
+        content_hash,
+        file_size,
+        modified_at,
+        relative_path: String::new(),
+    };
+
+    let unchanged = operations::remote_source_current(
+        conn,
+        &target_name,
+        device.id,
+        &relative_path,
+        file_size as i64,
+        modified_at,
+    )
+    .map_err(io::Error::other)?;
+
+    if let Some(content_hash) = unchanged {
+        return Ok((Outcome::AlreadyThere, entry(content_hash)));
+    }
+
+    let content_hash = calculate_full_hash(path)?;
+    let key = object_key(&content_hash);
+
+    let recorded =
+        operations::remote_object_exists(conn, &target_name, &key).map_err(io::Error::other)?;
+
+    let existed = recorded || target.exists(&key)?;
+
+    if options.dry_run {
+        if !existed {
+            println!("Would upload {:?} to {}", path, key);
+            return Ok((Outcome::Uploaded(file_size), entry(content_hash)));
+        }
+        return Ok((Outcome::AlreadyThere, entry(content_hash)));
+    }
+
+    if !existed {
+        if options.verify {
+            put_verified(target, &key, path, &content_hash)?;
+        } else {
+            target.put(&key, path)?;
+        }
+        println!("Uploaded {:?} to {}", path, key);
+    }
+
+    operations::insert_remote_object(
+        conn,
+        &target_name,
+        &key,
+        &content_hash,
+        &calculate_hash(path)?,
+        file_size as i64,
+    )
+    .map_err(io::Error::other)?;
+
+    operations::upsert_remote_source(
+        conn,
+        &target_name,
+        device.id,
+        &relative_path,
+        file_size as i64,
+        modified_at,
+        &key,
+    )
+    .map_err(io::Error::other)?;
+
+    let outcome = if existed {
+        Outcome::AlreadyThere
+    } else {
+        Outcome::Uploaded(file_size)
+    };
+
+    Ok((outcome, entry(content_hash)))
+}
+
+/// `manifests/<UTC time>-<folder name>.tsv`, one `hash, size, mtime, path` line per file
+fn upload_manifest(
+    target: &dyn StorageBackend,
+    folder: &Path,
+    manifest: &[ManifestEntry],
+) -> io::Result<String> {
+    let folder_name = folder
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string());
+
+    let key = format!(
+        "{}/{}-{}.tsv",
+        MANIFESTS_FOLDER,
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        folder_name
+    );
+
+    let contents = manifest
+        .iter()
+        .map(|entry| {
+            format!(
+                "{}\t{}\t{}\t{}\n",
+                entry.content_hash, entry.file_size, entry.modified_at, entry.relative_path
+            )
+        })
+        .collect::<String>();
+
+    let path = temp_path("manifest");
+    fs::write(&path, &contents)?;
+    let uploaded = put_verified(target, &key, &path, &calculate_full_hash(&path)?);
+    let _ = fs::remove_file(&path);
+    uploaded?;
+
+    Ok(key)
+}
+
+pub fn run_restore(args: &[String], config: &Config) -> io::Result<()> {
+    let mut storage = "s3";
+    let mut manifest_key = None;
+    let mut folder = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--from" if !args.as_slice().is_empty() => storage = args.next().unwrap(),
+            "--manifest" if !args.as_slice().is_empty() => manifest_key = args.next().cloned(),
+            _ if folder.is_none() && !arg.starts_with("--") => folder = Some(Path::new(arg)),
+            _ => {
+                println!("{}", RESTORE_USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let Some(folder) = folder else {
+        println!("{}", RESTORE_USAGE);
+        return Ok(());
+    };
+
+    let source = match open_backend(storage, config) {
+        Ok(source) => source,
+        Err(e) => {
+            println!("Can't open {}; Error : {}", storage, e);
+            return Ok(());
+        }
+    };
+
+    let manifest_key = match manifest_key {
+        Some(key) => key,
+        None => match default_manifest(source.as_ref(), folder)? {
+            Some(key) => key,
+            None => return Ok(()),
+        },
+    };
+
+    println!("Restoring {} from {}", manifest_key, source.name());
+
+    let manifest = read_manifest(source.as_ref(), &manifest_key)?;
+    let (mut restored, mut bytes_restored, mut skipped, mut failed) = (0, 0, 0, 0);
+
+    for entry in &manifest {
+        let destination = folder.join(&entry.relative_path);
+
+        // A path with `..` or a root in it would be written outside <folder>
+        if let Err(e) = key_under(folder, &destination) {
+            println!(
+                "Not restoring {:?}: not a path inside the folder; Error : {:?}",
+                entry.relative_path, e
+            );
+            failed += 1;
+            continue;
+        }
+
+        if destination.exists() {
+            match calculate_full_hash(&destination) {
+                Ok(full_hash) if full_hash == entry.content_hash => skipped += 1,
+                Ok(_) => {
+                    println!(
+                        "Not restoring {:?}: a different file already exists there",
+                        destination
+                    );
+                    failed += 1;
+                }
+                Err(e) => {
+                    println!(
+                        "Error hashing file; Path : {:?}; Error : {:?}",
+                        destination.to_str(),
+                        e
+                    );
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+
+        match restore_file(source.as_ref(), entry, &destination) {
+            Ok(()) => {
+                restored += 1;
+                bytes_restored += entry.file_size;
+            }
+            Err(e) => {
+                println!(
+                    "Error restoring file; Path : {:?}; Error : {:?}",
+                    destination.to_str(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} restored ({}), {} already there, {} failed",
+        restored,
+        format_size(bytes_restored),
+        skipped,
+        failed
+    );
+
+    Ok(())
+}
+
+/// The newest manifest of a folder named like `folder`, or the newest manifest when they are
+/// all of one folder. Says why when there is none to pick.
+fn default_manifest(source: &dyn StorageBackend, folder: &Path) -> io::Result<Option<String>> {
+    let keys = source.list(&format!("{}/", MANIFESTS_FOLDER))?;
+
+    let folder_name = folder
+        .canonicalize()
+        .unwrap_or_else(|_| folder.to_path_buf())
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string());
+
+    if let Some(key) = keys
+        .iter()
+        .rev()
+        .find(|key| manifest_folder_name(key) == Some(&folder_name))
+    {
+        return Ok(Some(key.clone()));
+    }
+
+    let folder_names = keys
+        .iter()
+        .filter_map(|key| manifest_folder_name(key))
+        .collect::<BTreeSet<_>>();
+
+    if folder_names.len() > 1 {
+        println!(
+            "No manifest of a folder named {:?} on {}, there are manifests of {}. Pick one with --manifest",
+            folder_name,
+            source.name(),
+            folder_names.into_iter().collect::<Vec<_>>().join(", ")
+        );
+        return Ok(None);
+    }
+
+    if keys.is_empty() {
+        println!("No manifests on {}", source.name());
+    }
+
+    Ok(keys.last().cloned())
+}
+
+/// Name of the folder a manifest was uploaded for, from its `<time>-<folder name>.tsv` key
+fn manifest_folder_name(key: &str) -> Option<&str> {
+    let file_name = key.rsplit('/').next()?.strip_suffix(".tsv")?;
+
+    file_name
+        .split_once('-')
+        .map(|(_, folder_name)| folder_name)
+}
+
+fn read_manifest(source: &dyn StorageBackend, key: &str) -> io::Result<Vec<ManifestEntry>> {
+    let path = temp_path("manifest");
+    let contents = source
+        .get(key, &path)
+        .and_then(|_| fs::read_to_string(&path));
+    let _ = fs::remove_file(&path);
+
+    Ok(contents?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+
+            Some(ManifestEntry {
+                content_hash: fields.next()?.to_string(),
+                file_size: fields.next()?.parse().ok()?,
+                modified_at: fields.next()?.parse().ok()?,
+                relative_path: fields.next()?.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Downloads a file, checks it hashes to what was backed up, and gives it back its mtime
+fn restore_file(
+    source: &dyn StorageBackend,
+    entry: &ManifestEntry,
+    destination: &Path,
+) -> io::Result<()> {
+    source.get(&object_key(&entry.content_hash), destination)?;
+
+    let restored_hash = calculate_full_hash(destination)?;
+
+    if restored_hash != entry.content_hash {
+        fs::remove_file(destination)?;
+
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Restored copy has hash {}, expected {}",
+                restored_hash, entry.content_hash
+            ),
+        ));
+    }
+
+    set_modified_at(destination, entry.modified_at)?;
+
+    println!("Restored {:?}", destination);
+
+    Ok(())
+}
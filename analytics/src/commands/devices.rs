@@ -0,0 +1,67 @@
+use std::{io, path::Path};
+
+use chrono::DateTime;
+use rusqlite::Connection;
+
+use crate::{
+    database::operations,
+    utils::devices::{MARKER_FILE, is_mounted, register_device_with_marker},
+};
+
+const USAGE: &str = "Usage:
+    analytics devices
+    analytics devices register <folder>
+
+Lists the volumes the catalog has files on, how each one is recognised (the marker file
+written to its root, or its filesystem UUID), and whether it is plugged in right now.
+
+Removable volumes (SD cards, USB disks) get a marker the first time they are seen; other
+disks are only written to once registered, which writes a marker to the root of the
+volume <folder> is on, so it is recognised on any host and mount point.";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    match args {
+        [] => {}
+        [command, folder] if command == "register" => {
+            let device = register_device_with_marker(conn, Path::new(folder))?;
+            println!(
+                "Registered {:?} as device {}, marked by {:?}",
+                device.mount_point,
+                device.id,
+                device.mount_point.join(MARKER_FILE)
+            );
+            return Ok(());
+        }
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    }
+
+    for device in operations::list_devices(conn).map_err(io::Error::other)? {
+        let identity = match (&device.marker_id, &device.volume_uuid) {
+            (Some(marker_id), _) => format!("marker {}", marker_id),
+            (None, Some(volume_uuid)) => format!("uuid {}", volume_uuid),
+            (None, None) => "label only".to_string(),
+        };
+
+        let last_seen = DateTime::from_timestamp(device.last_seen_at, 0)
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+
+        let status = match (&device.mount_point, is_mounted(&device)) {
+            (Some(mount_point), true) => format!("mounted at {}", mount_point),
+            (Some(mount_point), false) => {
+                format!("offline, last seen at {} on {}", mount_point, last_seen)
+            }
+            (None, _) => "offline".to_string(),
+        };
+
+        println!(
+            "{}\t{}\t{}\t{} file(s)\t{}",
+            device.id, device.label, identity, device.file_count, status
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::io;
+
+use rusqlite::Connection;
+
+use crate::{database::operations, format_size};
+
+const USAGE: &str = "Usage:
+    analytics duplicates [--min-copies <n>] [--session <id>]
+    analytics duplicates folders [--session <id>]
+
+Reports the duplicate groups stored by a backup session (the latest one by default).
+The first form lists every group with at least <n> copies (2 by default), kept copy first.
+`folders` totals the space taken by the copies that are not kept, per folder.";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let (folders, mut rest) = match args.first().map(|a| a.as_str()) {
+        Some("folders") => (true, &args[1..]),
+        _ => (false, args),
+    };
+
+    let mut min_copies = 2;
+    let mut session_id = None;
+
+    while let [flag, value, tail @ ..] = rest {
+        match (flag.as_str(), value.parse::<i64>()) {
+            ("--min-copies", Ok(value)) if !folders => min_copies = value,
+            ("--session", Ok(value)) => session_id = Some(value),
+            _ => break,
+        }
+        rest = tail;
+    }
+
+    if !rest.is_empty() {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    let session_id = match session_id {
+        Some(session_id) => session_id,
+        None => match operations::latest_duplicate_session(conn).map_err(io::Error::other)? {
+            Some(session_id) => session_id,
+            None => {
+                println!("No duplicate groups stored yet, run a backup first");
+                return Ok(());
+            }
+        },
+    };
+
+    if folders {
+        report_folders(conn, session_id)
+    } else {
+        report_groups(conn, session_id, min_copies)
+    }
+}
+
+fn report_groups(conn: &Connection, session_id: i64, min_copies: i64) -> io::Result<()> {
+    let groups = operations::duplicate_groups_with_copies(conn, session_id, min_copies)
+        .map_err(io::Error::other)?;
+
+    for group in &groups {
+        println!(
+            "{}\t{} copies\t{} wasted\tkept {} ({})",
+            group.hash,
+            group.total_copies,
+            format_size(group.wasted_space_bytes as u64),
+            group.kept_path,
+            group.keep_reason
+        );
+
+        for path in &group.paths {
+            println!("\t{}", path);
+        }
+    }
+
+    let wasted = groups.iter().map(|g| g.wasted_space_bytes as u64).sum();
+
+    println!(
+        "Session {}: {} group(s) with {}+ copies, {} wasted",
+        session_id,
+        groups.len(),
+        min_copies,
+        format_size(wasted)
+    );
+
+    Ok(())
+}
+
+fn report_folders(conn: &Connection, session_id: i64) -> io::Result<()> {
+    let folders =
+        operations::wasted_bytes_per_folder(conn, session_id).map_err(io::Error::other)?;
+
+    for folder in folders {
+        println!(
+            "{}\t{} extra cop{}\t{}",
+            format_size(folder.wasted_space_bytes as u64),
+            folder.extra_copies,
+            if folder.extra_copies == 1 { "y" } else { "ies" },
+            folder.folder
+        );
+    }
+
+    Ok(())
+}
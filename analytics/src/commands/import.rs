@@ -0,0 +1,409 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use chrono::NaiveDateTime;
+use rusqlite::Connection;
+
+use crate::{
+    config::LayoutConfig,
+    database::{models::ImportedFileRow, operations},
+    format_size,
+    utils::{
+        core::{Media, collect_media_files, copy_file_atomic, library_copy},
+        devices::register_device,
+        duplicates::{calculate_full_hash, final_path_for_media},
+        volume::{LOCAL_DEVICE, device_label, volume_uuid},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics import <card> [<library>]
+
+Copies the photos and videos on a camera card (<card> is where it is mounted) into the
+library, <root>/final_export by default, laid out like a backup. The card is recognised
+by its volume UUID, or by its label and DCIM folders, and files imported from it before
+are skipped, so a card can be imported again after more shots. Files already in the
+library are skipped too. Recorded as a backup session.";
+
+/// The card a folder belongs to, as far as `import` can tell
+struct Card {
+    id: i64,
+    volume_uuid: Option<String>,
+    dcim_folders: Vec<String>,
+}
+
+enum Outcome {
+    Copied(u64),
+    Skipped(&'static str),
+}
+
+pub fn run(conn: &Connection, args: &[String], layout: &LayoutConfig) -> io::Result<()> {
+    let (card_root, library) = match args {
+        [card] => (card, format!("{}/final_export", crate::ROOT_PROJECT_PATH)),
+        [card, library] => (card, library.clone()),
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let card_root = Path::new(card_root);
+
+    if !card_root.is_dir() {
+        println!("Not a folder: {:?}", card_root);
+        return Ok(());
+    }
+
+    import_card(conn, card_root, &library, layout, &Mutex::new(None))?;
+
+    Ok(())
+}
+
+/// Imports a card into `library` as one backup session, returns the session's id. The
+/// session is in `running` while the import runs, for a Ctrl-C handler to mark cancelled.
+pub fn import_card(
+    conn: &Connection,
+    card_root: &Path,
+    library: &str,
+    layout: &LayoutConfig,
+    running: &Mutex<Option<i64>>,
+) -> io::Result<i64> {
+    let card = identify_card(conn, card_root)?;
+
+    let session_id =
+        operations::new_backup_session(conn, card_root.to_str().unwrap_or(""), library)
+            .map_err(io::Error::other)?;
+
+    *running.lock().unwrap() = Some(session_id);
+
+    let mut files = Vec::new();
+    if let Err(e) = collect_media_files(card_root, &mut files) {
+        let recorded = operations::update_backup_session_counts(
+            conn,
+            session_id,
+            0,
+            0,
+            0,
+            0,
+            Some(&e.to_string()),
+        )
+        .and_then(|()| operations::update_backup_session_completed(conn, session_id));
+        *running.lock().unwrap() = None;
+        recorded.map_err(io::Error::other)?;
+
+        return Err(e);
+    }
+
+    let (mut copied, mut skipped, mut failed, mut bytes_copied) = (0, 0, 0, 0);
+
+    for path in &files {
+        match import_file(conn, &card, session_id, card_root, path, library, layout) {
+            Ok(Outcome::Copied(bytes)) => {
+                println!("Imported {:?}", path);
+                copied += 1;
+                bytes_copied += bytes;
+            }
+            Ok(Outcome::Skipped(reason)) => {
+                println!("Skipped {:?}, {}", path, reason);
+                skipped += 1;
+            }
+            Err(e) => {
+                println!(
+                    "Error importing file; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    let error_message = (failed > 0).then(|| format!("{} file(s) could not be imported", failed));
+
+    operations::update_backup_session_counts(
+        conn,
+        session_id,
+        files.len() as i64,
+        copied,
+        skipped,
+        bytes_copied as i64,
+        error_message.as_deref(),
+    )
+    .map_err(io::Error::other)?;
+    operations::update_backup_session_completed(conn, session_id).map_err(io::Error::other)?;
+
+    *running.lock().unwrap() = None;
+
+    operations::update_sd_card_imported(
+        conn,
+        card.id,
+        card.volume_uuid.as_deref(),
+        &card.dcim_folders.join(";"),
+    )
+    .map_err(io::Error::other)?;
+
+    println!(
+        "Session {}: {} file(s) on the card, {} copied ({}), {} skipped, {} failed",
+        session_id,
+        files.len(),
+        copied,
+        format_size(bytes_copied),
+        skipped,
+        failed
+    );
+
+    Ok(session_id)
+}
+
+/// Finds the card in the catalog, or records it as a new one. With a volume UUID on
+/// both sides that decides it; otherwise the label must match and the first DCIM folder
+/// seen last time must still be there, which stops being true once the card is formatted.
+fn identify_card(conn: &Connection, card_root: &Path) -> io::Result<Card> {
+    // A folder on the system disk (a card copied off earlier) has no volume of its own
+    let device = device_label(card_root);
+    let (volume_uuid, label) = if device == LOCAL_DEVICE {
+        let name = card_root.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        (None, name.to_string())
+    } else {
+        (volume_uuid(card_root), device)
+    };
+
+    let dcim_folders = dcim_folders(card_root);
+
+    if dcim_folders.is_empty() {
+        println!("No DCIM folder on {:?}, importing it anyway", card_root);
+    }
+
+    let known = operations::sd_cards_matching(conn, volume_uuid.as_deref(), &label)
+        .map_err(io::Error::other)?;
+
+    let matched = known
+        .into_iter()
+        .find(|card| match (&volume_uuid, &card.volume_uuid) {
+            (Some(uuid), Some(known_uuid)) => uuid == known_uuid,
+            _ => match card.dcim_folders.split(';').find(|f| !f.is_empty()) {
+                Some(first) => dcim_folders.iter().any(|f| f == first),
+                None => dcim_folders.is_empty(),
+            },
+        });
+
+    let id = match matched {
+        Some(card) => {
+            println!("Card {} seen before (card {})", label, card.id);
+            card.id
+        }
+        None => {
+            println!("New card {}", label);
+            operations::insert_sd_card(
+                conn,
+                volume_uuid.as_deref(),
+                &label,
+                &dcim_folders.join(";"),
+            )
+            .map_err(io::Error::other)?
+        }
+    };
+
+    Ok(Card {
+        id,
+        volume_uuid,
+        dcim_folders,
+    })
+}
+
+/// Camera folders under DCIM (100MSDCF, 101_PANA, ...), sorted
+fn dcim_folders(card_root: &Path) -> Vec<String> {
+    let mut folders = fs::read_dir(card_root.join("DCIM"))
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .filter_map(|e| e.file_name().to_str().map(|n| n.to_string()))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    folders.sort();
+    folders
+}
+
+fn import_file(
+    conn: &Connection,
+    card: &Card,
+    session_id: i64,
+    card_root: &Path,
+    path: &Path,
+    library: &str,
+    layout: &LayoutConfig,
+) -> io::Result<Outcome> {
+    let metadata = fs::metadata(path)?;
+    let relative_path = path
+        .strip_prefix(card_root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned();
+    let file_size = metadata.len();
+    let modified_at = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    let already_imported =
+        operations::is_file_imported(conn, card.id, &relative_path, file_size as i64, modified_at)
+            .map_err(io::Error::other)?;
+
+    if already_imported {
+        return Ok(Outcome::Skipped("already imported from this card"));
+    }
+
+    let media = Media::new(path)?;
+
+    let has_date = media
+        .exif_data
+        .as_ref()
+        .and_then(|e| e.date_taken.as_deref())
+        .is_some_and(|d| NaiveDateTime::parse_from_str(d, "%Y-%m-%d %H:%M:%S").is_ok());
+
+    if !has_date {
+        return Err(io::Error::other(
+            "No date taken, can't place it in the library",
+        ));
+    }
+
+    let final_path = PathBuf::from(final_path_for_media(media.clone(), library, layout));
+
+    let mut imported = ImportedFileRow {
+        card_id: card.id,
+        session_id,
+        relative_path,
+        file_size_bytes: file_size as i64,
+        modified_at,
+        hash: media.hash.clone(),
+        destination_path: String::new(),
+    };
+
+    let existing = match library_copy(conn, path, &media.hash, Path::new(library))? {
+        Some(existing) => Err(existing),
+        None => free_destination(path, &final_path)?,
+    };
+
+    let destination = match existing {
+        Ok(destination) => destination,
+        Err(existing) => {
+            imported.destination_path = existing.to_string_lossy().into_owned();
+            operations::insert_imported_file(conn, &imported).map_err(io::Error::other)?;
+            return Ok(Outcome::Skipped("already in the library"));
+        }
+    };
+
+    copy_file_atomic(path, &destination)?;
+
+    // Sidecars travel with their media file and follow its rename
+    if let Some(sidecar) = &media.sidecar {
+        let sidecar_destination = sidecar.final_path_for(path, &destination);
+
+        if !sidecar_destination.exists() {
+            copy_file_atomic(&sidecar.path, &sidecar_destination)?;
+        }
+    }
+
+    let library_copy = Media {
+        file_path: destination.clone(),
+        file_name: destination
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        ..media
+    };
+
+    operations::insert_media_file(conn, &library_copy).unwrap_or_else(|e| {
+        println!(
+            "Error inserting media to database; Path : {:?}; Error : {:?}",
+            destination.to_str(),
+            e
+        );
+    });
+
+    let device = register_device(conn, &destination)?;
+    let relative_path = device.relative_path(&destination);
+
+    operations::set_media_file_device(
+        conn,
+        &library_copy.hash,
+        destination.to_str().unwrap_or(""),
+        device.id,
+        &relative_path,
+    )
+    .and_then(|_| {
+        operations::upsert_file_location(
+            conn,
+            &library_copy.hash,
+            device.id,
+            &relative_path,
+            file_size as i64,
+        )
+    })
+    .unwrap_or_else(|e| {
+        println!(
+            "Error inserting file location to database; Path : {:?}; Error : {:?}",
+            destination.to_str(),
+            e
+        );
+    });
+
+    operations::insert_media_keywords(conn, &library_copy).unwrap_or_else(|e| {
+        println!(
+            "Error inserting keywords to database; Path : {:?}; Error : {:?}",
+            destination.to_str(),
+            e
+        );
+    });
+
+    imported.destination_path = destination.to_string_lossy().into_owned();
+    operations::insert_imported_file(conn, &imported).map_err(io::Error::other)?;
+
+    Ok(Outcome::Copied(file_size))
+}
+
+/// Where the file should be copied to: `final_path`, or `name_1.ext`, `name_2.ext`, ...
+/// when another file already has that name (two cameras both at DSC00001 the same day).
+/// `Err` with the existing path when the same file is already in the library.
+fn free_destination(path: &Path, final_path: &Path) -> io::Result<Result<PathBuf, PathBuf>> {
+    let stem = final_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("");
+    let extension = final_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+
+    let mut source_hash = None;
+
+    for n in 0.. {
+        let candidate = match n {
+            0 => final_path.to_path_buf(),
+            _ => final_path.with_file_name(format!("{}_{}.{}", stem, n, extension)),
+        };
+
+        if !candidate.exists() {
+            return Ok(Ok(candidate));
+        }
+
+        if source_hash.is_none() {
+            source_hash = Some(calculate_full_hash(path)?);
+        }
+
+        if source_hash == Some(calculate_full_hash(&candidate)?) {
+            return Ok(Err(candidate));
+        }
+    }
+
+    unreachable!()
+}
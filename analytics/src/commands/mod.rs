@@ -0,0 +1,17 @@
+pub mod audit;
+pub mod checksums;
+pub mod cleanup;
+pub mod cloud_backup;
+pub mod devices;
+pub mod duplicates;
+pub mod import;
+pub mod near_duplicates;
+pub mod parity;
+pub mod relations;
+pub mod restore;
+pub mod scrub;
+pub mod stacks;
+pub mod storage;
+pub mod sync;
+pub mod tags;
+pub mod watch;
@@ -0,0 +1,231 @@
+use std::{io, path::Path};
+
+use rusqlite::Connection;
+
+use crate::{
+    config::{DuplicatesConfig, NearDuplicatesConfig},
+    database::{models::SimilarityMemberRow, operations},
+    format_size,
+    utils::{
+        perceptual::{cluster_by_hash, image_dhash},
+        ranking::rank_copies,
+        video_fingerprint::{VideoFingerprint, cluster_videos},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics near-dups [images|videos] [--threshold <bits>]
+
+images (default): hashes every cataloged image that has no perceptual hash yet, then groups
+images whose hashes differ by at most <bits> of 64. Written to ./csv_exports/near_duplicates.csv
+
+videos: fingerprints every cataloged video (frames sampled with ffmpeg + duration), then groups
+videos of matching duration whose frames differ by at most <bits> on average.
+Written to ./csv_exports/video_near_duplicates.csv
+
+The copy of each cluster the [duplicates] ranking would keep (the RAW, the largest, the
+one with the most metadata, ...) is marked in the Keep column, with the reason.";
+
+pub fn run(
+    conn: &Connection,
+    args: &[String],
+    config: &NearDuplicatesConfig,
+    ranking: &DuplicatesConfig,
+) -> io::Result<()> {
+    let (videos, rest) = match args.first().map(|a| a.as_str()) {
+        Some("videos") => (true, &args[1..]),
+        Some("images") => (false, &args[1..]),
+        _ => (false, args),
+    };
+
+    let default_threshold = if videos {
+        config.video_threshold
+    } else {
+        config.image_threshold
+    };
+
+    let threshold = match rest {
+        [] => default_threshold,
+        [flag, value] if flag == "--threshold" => match value.parse() {
+            Ok(threshold) => threshold,
+            Err(_) => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        },
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    if videos {
+        find_near_duplicate_videos(conn, threshold, ranking)
+    } else {
+        find_near_duplicate_images(conn, threshold, ranking)
+    }
+}
+
+fn find_near_duplicate_images(
+    conn: &Connection,
+    threshold: u32,
+    ranking: &DuplicatesConfig,
+) -> io::Result<()> {
+    compute_missing_phashes(conn)?;
+
+    let phashes = operations::media_phashes(conn).map_err(io::Error::other)?;
+    let clusters = cluster_by_hash(&phashes, threshold);
+
+    println!(
+        "Found {} near duplicate image cluster(s) within {} bits",
+        clusters.len(),
+        threshold
+    );
+
+    operations::replace_similarity_groups(conn, "image_phash", threshold, &clusters)
+        .map_err(io::Error::other)?;
+
+    export_clusters_to_csv(
+        conn,
+        "image_phash",
+        "./csv_exports/near_duplicates.csv",
+        ranking,
+    )
+}
+
+fn find_near_duplicate_videos(
+    conn: &Connection,
+    threshold: u32,
+    ranking: &DuplicatesConfig,
+) -> io::Result<()> {
+    compute_missing_fingerprints(conn)?;
+
+    let fingerprints = operations::video_fingerprints(conn).map_err(io::Error::other)?;
+    let clusters = cluster_videos(&fingerprints, threshold);
+
+    println!(
+        "Found {} near duplicate video cluster(s) within {} bits",
+        clusters.len(),
+        threshold
+    );
+
+    operations::replace_similarity_groups(conn, "video_fingerprint", threshold, &clusters)
+        .map_err(io::Error::other)?;
+
+    export_clusters_to_csv(
+        conn,
+        "video_fingerprint",
+        "./csv_exports/video_near_duplicates.csv",
+        ranking,
+    )
+}
+
+fn compute_missing_fingerprints(conn: &Connection) -> io::Result<()> {
+    let videos = operations::videos_missing_fingerprint(conn).map_err(io::Error::other)?;
+
+    println!("Fingerprinting {} video(s)", videos.len());
+
+    for (media_file_id, path) in videos {
+        match VideoFingerprint::from_file(Path::new(&path)) {
+            Ok(fingerprint) => {
+                operations::insert_video_fingerprint(conn, media_file_id, &fingerprint)
+                    .map_err(io::Error::other)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("{}", e);
+                return Ok(());
+            }
+            Err(e) => println!(
+                "Could not fingerprint video; Path : {:?}; Error : {:?}",
+                path, e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+fn compute_missing_phashes(conn: &Connection) -> io::Result<()> {
+    let images = operations::images_missing_phash(conn).map_err(io::Error::other)?;
+
+    println!("Computing perceptual hashes for {} image(s)", images.len());
+
+    for (media_file_id, path) in images {
+        match image_dhash(Path::new(&path)) {
+            Some(phash) => {
+                operations::update_phash(conn, media_file_id, phash).map_err(io::Error::other)?
+            }
+            None => println!("Could not hash image; Path : {:?}", path),
+        }
+    }
+
+    Ok(())
+}
+
+/// One CSV row per cluster member, the copy the ranking keeps marked in each cluster
+pub fn export_clusters_to_csv(
+    conn: &Connection,
+    kind: &str,
+    output_path: &str,
+    ranking: &DuplicatesConfig,
+) -> io::Result<()> {
+    let members = operations::similarity_group_members(conn, kind).map_err(io::Error::other)?;
+
+    let mut wrt = csv::Writer::from_path(output_path).map_err(io::Error::other)?;
+
+    wrt.write_record([
+        "Group",
+        "Distance",
+        "File Path",
+        "File Size (Bytes)",
+        "File Size (Human)",
+        "Keep",
+        "Keep Reason",
+    ])
+    .map_err(io::Error::other)?;
+
+    for cluster in members.chunk_by(|a, b| a.group_id == b.group_id) {
+        let (kept, keep_reason) = pick_kept_copy(cluster, ranking);
+
+        for member in cluster {
+            let keep = kept.as_deref() == Some(member.path.as_str());
+
+            wrt.write_record([
+                member.group_id.to_string(),
+                member.distance.to_string(),
+                member.path.clone(),
+                member.file_size_bytes.to_string(),
+                format_size(member.file_size_bytes as u64),
+                if keep { "yes" } else { "" }.to_string(),
+                if keep {
+                    keep_reason.clone()
+                } else {
+                    String::new()
+                },
+            ])
+            .map_err(io::Error::other)?;
+        }
+    }
+
+    wrt.flush()?;
+
+    println!("✅ Exported to {}", output_path);
+
+    Ok(())
+}
+
+/// Ranks the members of a cluster like the copies of a duplicate group, from what the
+/// catalog holds about them. Unlike those, they differ in format, resolution and metadata,
+/// so every rule can decide here.
+fn pick_kept_copy(
+    cluster: &[SimilarityMemberRow],
+    ranking: &DuplicatesConfig,
+) -> (Option<String>, String) {
+    let mut copies = cluster.to_vec();
+    let keep_reason = rank_copies(&mut copies, ranking);
+
+    (
+        copies.first().map(|member| member.path.clone()),
+        keep_reason,
+    )
+}
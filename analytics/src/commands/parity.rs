@@ -0,0 +1,217 @@
+use std::{fs, io, path::Path};
+
+use rusqlite::Connection;
+
+use crate::{
+    config::Config,
+    database::operations,
+    utils::{
+        core::collect_library_files,
+        devices::register_device,
+        parity::{PARITY_FILE, write_parity},
+        storage::key_for,
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics parity [--redundancy <percent>] [--year <year>] [--force] <library>
+
+Writes Reed-Solomon recovery data to every year folder of <library>, as
+.photo_app_rs.parity, so scrub can repair a damaged photo or video there even when no
+other copy of it exists. Up to <percent> (10 by default, see [parity] in config.toml) of
+the data of a folder can be rebuilt, as long as the damage is spread out. Folders whose
+files haven't changed since their recovery data was written are skipped, unless --force
+or another --redundancy is given. --year only writes the folder of <year>.
+
+Files are checked against what the catalog last verified them as while being read, so
+recovery data is never written for a damaged file; repair it with scrub first if one is
+reported.";
+
+pub fn run(conn: &Connection, args: &[String], config: &Config) -> io::Result<()> {
+    let mut redundancy_percent = config.parity.redundancy_percent;
+    let mut year = None;
+    let mut force = false;
+    let mut library = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--force" => force = true,
+            "--year" if !args.as_slice().is_empty() => year = args.next(),
+            "--redundancy" if !args.as_slice().is_empty() => {
+                match args.next().unwrap().trim_end_matches('%').parse() {
+                    Ok(percent @ 1..=100) => redundancy_percent = percent,
+                    _ => {
+                        println!("{}", USAGE);
+                        return Ok(());
+                    }
+                }
+            }
+            _ if library.is_none() && !arg.starts_with("--") => library = Some(Path::new(arg)),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let Some(library) = library else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    if !library.is_dir() {
+        println!("Not a folder: {:?}", library);
+        return Ok(());
+    }
+
+    let mut folders = Vec::new();
+    for entry in fs::read_dir(library)? {
+        let path = entry?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+
+        if path.is_dir()
+            && name.len() == 4
+            && name.bytes().all(|b| b.is_ascii_digit())
+            && year.is_none_or(|year| year == name)
+        {
+            folders.push(path);
+        }
+    }
+    folders.sort();
+
+    if folders.is_empty() {
+        println!("No year folders in {:?}", library);
+        return Ok(());
+    }
+
+    let device = register_device(conn, library)?;
+    let slice_size = config.parity.slice_size_kb.max(1) * 1024;
+
+    for folder in folders {
+        let folder_path = device.relative_path(&folder);
+
+        let mut paths = Vec::new();
+        collect_library_files(&folder, &mut paths)?;
+
+        let mut files = paths
+            .iter()
+            .map(|path| {
+                let size = fs::metadata(path)?.len();
+                Ok((key_for(path.strip_prefix(&folder).unwrap_or(path)), size))
+            })
+            .collect::<io::Result<Vec<(String, u64)>>>()?;
+        files.sort();
+
+        if files.is_empty() {
+            continue;
+        }
+
+        let current = files
+            .iter()
+            .map(|(path, size)| (join_key(&folder_path, path), *size as i64))
+            .collect::<Vec<_>>();
+
+        let recorded = operations::parity_set_files(conn, device.id, &folder_path)
+            .map_err(io::Error::other)?;
+
+        if !force
+            && folder.join(PARITY_FILE).is_file()
+            && recorded.is_some_and(|recorded| {
+                recorded.redundancy_percent == redundancy_percent as i64
+                    && recorded.files == current
+            })
+        {
+            println!("Up to date: {:?}", folder);
+            continue;
+        }
+
+        // The old recovery data stays until the new one is known to be good
+        let parity_file = folder.join(PARITY_FILE);
+        let partial = folder.join(format!("{}.part", PARITY_FILE));
+
+        let index = match write_parity(&folder, &files, slice_size, redundancy_percent, &partial) {
+            Ok(index) => index,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                println!(
+                    "Error writing recovery data; Path : {:?}; Error : {:?}",
+                    folder.to_str(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let mut covered = Vec::new();
+        let mut mismatched = Vec::new();
+
+        for file in &index.files {
+            let relative_path = join_key(&folder_path, &file.path);
+
+            let verified = operations::verified_content_hash(
+                conn,
+                device.id,
+                &relative_path,
+                file.size as i64,
+            )
+            .map_err(io::Error::other)?;
+
+            let status = operations::location_status(conn, device.id, &relative_path)
+                .map_err(io::Error::other)?;
+
+            if verified.is_some_and(|verified| verified != file.content_hash)
+                || status.as_deref() == Some("damaged")
+            {
+                mismatched.push(folder.join(&file.path));
+            }
+
+            covered.push((relative_path, file.size as i64, file.content_hash.clone()));
+        }
+
+        if !mismatched.is_empty() {
+            fs::remove_file(&partial)?;
+
+            println!(
+                "Not writing recovery data for {:?}, these files don't match what they were verified as (repair them with scrub):",
+                folder
+            );
+            for path in mismatched {
+                println!("    {:?}", path);
+            }
+            continue;
+        }
+
+        fs::rename(&partial, &parity_file)?;
+        let parity_bytes = fs::metadata(&parity_file)?.len();
+
+        operations::replace_parity_set(
+            conn,
+            device.id,
+            &folder_path,
+            parity_bytes as i64,
+            slice_size as i64,
+            redundancy_percent as i64,
+            &covered,
+        )
+        .map_err(io::Error::other)?;
+
+        println!(
+            "Wrote {:?} ({} file(s), {:.1} MB of recovery data)",
+            parity_file,
+            covered.len(),
+            parity_bytes as f64 / (1024.0 * 1024.0)
+        );
+    }
+
+    Ok(())
+}
+
+/// A file's path from the device root, given its folder's and its path in the folder
+fn join_key(folder: &str, path: &str) -> String {
+    if folder.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", folder, path)
+    }
+}
@@ -0,0 +1,19 @@
+use std::io;
+
+use rusqlite::Connection;
+
+use crate::database::operations;
+
+/// Lists every edited export with the original it was derived from
+pub fn run(conn: &Connection) -> io::Result<()> {
+    let relations = operations::list_media_relations(conn).map_err(io::Error::other)?;
+
+    for relation in relations {
+        println!(
+            "{}\t{} ({})\t{}",
+            relation.path, relation.relation, relation.matched_by, relation.related_path
+        );
+    }
+
+    Ok(())
+}
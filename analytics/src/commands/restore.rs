@@ -0,0 +1,583 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use chrono::{Days, NaiveDate};
+use rusqlite::Connection;
+
+use crate::{
+    config::Config,
+    database::{models::LibraryFileRow, operations},
+    format_size,
+    utils::{
+        core::set_modified_at,
+        devices::{Device, find_device, register_device},
+        duplicates::{calculate_full_hash, calculate_hash},
+        sidecar::{Sidecar, is_sidecar},
+        storage::{
+            LocalBackend, StorageBackend, key_for, key_under, object_key, open_backend,
+            put_verified,
+        },
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics restore [--from <storage>]... [--dates <from>..<until>] [--tag <tag>]
+                      [--folder <folder>] [--dry-run] <library> <destination>
+
+Rebuilds <library>, or part of it, into <destination>. <library> is a library folder as
+it was mounted the last time the catalog saw it; the volume can be gone. Every file is
+copied from a copy the catalog knows of: a mounted volume holding the same file first,
+then the storages given with --from (s3, a [storage.<name>] backend, or a folder
+cloud-backup went to). Each restored file is checked against the full SHA-256 the catalog
+recorded for it (by cloud-backup or scrub); a copy that doesn't match is passed over for
+the next one. Files with no recorded full hash only have their size and first 128 KB
+checked, and are reported as unverified.
+
+--dates takes YYYY-MM-DD..YYYY-MM-DD (either end can be left out) and matches the date
+taken, --tag matches a tag and its children, --folder a folder inside the library.
+Sidecars come along with their photo: from the folder of the copy it is restored from,
+or from a backup that has them. Files already in <destination> with the right content are
+skipped, other files there are left alone. --dry-run only prints what would be
+restored and from where.";
+
+/// Which files of the library to restore
+#[derive(Default)]
+struct Selection {
+    taken_from: Option<String>,
+    taken_until: Option<String>,
+    tag: Option<String>,
+    folder: Option<String>,
+}
+
+/// A file to restore and what the catalog knows about it
+struct RestoreFile {
+    /// From the library root, `/` separated
+    library_path: String,
+    /// Where it was on the library's device, under the mount point it had last
+    original_path: PathBuf,
+    catalog: LibraryFileRow,
+}
+
+/// How a file compares with what the catalog knows about it
+#[derive(PartialEq)]
+enum Check {
+    /// Its full hash matches the recorded one
+    Intact,
+    /// Size and first 128 KB match; no full hash was ever recorded to compare with
+    Unverified,
+    Damaged,
+}
+
+/// Somewhere a file can be restored from
+enum Copy<'a> {
+    Local(PathBuf),
+    Remote(&'a dyn StorageBackend, String),
+}
+
+impl Copy<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Copy::Local(path) => path.display().to_string(),
+            Copy::Remote(storage, key) => format!("{} {}", storage.name(), key),
+        }
+    }
+}
+
+struct Restore<'a> {
+    conn: &'a Connection,
+    storages: Vec<Box<dyn StorageBackend>>,
+    destination: &'a Path,
+    destination_device: Option<Device>,
+    dry_run: bool,
+    /// Library paths being restored, sidecars among them come from their own entry
+    selected: HashSet<String>,
+}
+
+pub fn run(conn: &Connection, args: &[String], config: &Config) -> io::Result<()> {
+    let mut storages = Vec::new();
+    let mut selection = Selection::default();
+    let mut dry_run = false;
+    let mut paths = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--from" if !args.as_slice().is_empty() => storages.push(args.next().unwrap()),
+            "--tag" if !args.as_slice().is_empty() => selection.tag = args.next().cloned(),
+            "--folder" if !args.as_slice().is_empty() => {
+                selection.folder = args
+                    .next()
+                    .map(|folder| folder.trim_matches('/').to_string())
+            }
+            "--dates" if !args.as_slice().is_empty() => {
+                let Some((from, until)) = parse_dates(args.next().unwrap()) else {
+                    println!("{}", USAGE);
+                    return Ok(());
+                };
+                selection.taken_from = from;
+                selection.taken_until = until;
+            }
+            _ if !arg.starts_with("--") => paths.push(Path::new(arg)),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let [library, destination] = paths[..] else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    // Copies are found by absolute path
+    let library = &fs::canonicalize(library).unwrap_or_else(|_| library.to_path_buf());
+
+    let Some((device_id, library_folder)) = find_library(conn, library)? else {
+        println!("The catalog has no files under {:?}", library);
+        return Ok(());
+    };
+
+    let storages = storages
+        .into_iter()
+        .map(|storage| open_backend(storage, config))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let files = select_files(conn, device_id, &library_folder, library, &selection)?;
+
+    if files.is_empty() {
+        println!("Nothing to restore");
+        return Ok(());
+    }
+
+    let destination_device = if dry_run {
+        None
+    } else {
+        fs::create_dir_all(destination)?;
+        Some(register_device(conn, destination)?)
+    };
+
+    let destination = &fs::canonicalize(destination).unwrap_or_else(|_| destination.to_path_buf());
+
+    let restore = Restore {
+        conn,
+        storages,
+        destination,
+        destination_device,
+        dry_run,
+        selected: files.iter().map(|file| file.library_path.clone()).collect(),
+    };
+
+    let (mut restored, mut bytes_restored, mut already_there, mut failed) = (0, 0, 0, 0);
+    let mut unverified = 0;
+
+    for file in &files {
+        let path = destination.join(&file.library_path);
+
+        if path.exists() {
+            match check(&path, &file.catalog)? {
+                Check::Intact => already_there += 1,
+                Check::Unverified => {
+                    println!("{:?} is already there, unverified", path);
+                    already_there += 1;
+                    unverified += 1;
+                }
+                Check::Damaged => {
+                    println!(
+                        "Not restoring {:?}: a different file already exists there",
+                        path
+                    );
+                    failed += 1;
+                }
+            }
+            continue;
+        }
+
+        match restore_file(&restore, file, &path) {
+            Ok(check) => {
+                restored += 1;
+                bytes_restored += file.catalog.file_size_bytes as u64;
+                if check == Check::Unverified {
+                    unverified += 1;
+                }
+            }
+            Err(e) => {
+                println!(
+                    "Error restoring file; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "{}{} restored ({}), {} already there, {} failed, {} unverified (no full hash recorded)",
+        if dry_run { "Dry run: " } else { "" },
+        restored,
+        format_size(bytes_restored),
+        already_there,
+        failed,
+        unverified
+    );
+
+    Ok(())
+}
+
+/// `2024-01-01..2024-12-31`, either end optional, as the `[from, until)` range of
+/// `date_taken` values it covers
+fn parse_dates(dates: &str) -> Option<(Option<String>, Option<String>)> {
+    let (from, until) = dates.split_once("..")?;
+
+    let parse = |date: &str| match date {
+        "" => Some(None),
+        date => NaiveDate::parse_from_str(date, "%Y-%m-%d").ok().map(Some),
+    };
+
+    let from = parse(from)?;
+    let until = parse(until)?.and_then(|until| until.checked_add_days(Days::new(1)));
+
+    Some((
+        from.map(|d| d.format("%Y-%m-%d").to_string()),
+        until.map(|d| d.format("%Y-%m-%d").to_string()),
+    ))
+}
+
+/// The device a library folder is on and the folder's path from the device root. A
+/// folder that is gone is looked up by where its device was mounted last.
+fn find_library(conn: &Connection, library: &Path) -> io::Result<Option<(i64, String)>> {
+    if library.is_dir() {
+        return Ok(find_device(conn, library)?.map(|device| {
+            let folder = device.relative_path(library);
+            (device.id, folder)
+        }));
+    }
+
+    let devices = operations::list_devices(conn).map_err(io::Error::other)?;
+
+    Ok(devices
+        .into_iter()
+        .filter_map(|device| {
+            let mount_point = PathBuf::from(device.mount_point?);
+            let folder = library.strip_prefix(&mount_point).ok()?;
+
+            Some((
+                mount_point.as_os_str().len(),
+                device.id,
+                folder.to_path_buf(),
+            ))
+        })
+        .max_by_key(|(mount_point_length, _, _)| *mount_point_length)
+        .map(|(_, device_id, folder)| (device_id, folder.to_string_lossy().into_owned())))
+}
+
+fn select_files(
+    conn: &Connection,
+    device_id: i64,
+    library_folder: &str,
+    library: &Path,
+    selection: &Selection,
+) -> io::Result<Vec<RestoreFile>> {
+    let rows =
+        operations::library_files(conn, device_id, library_folder).map_err(io::Error::other)?;
+
+    // file_locations rows come first and win; a backup of the same file only adds what
+    // it knows on top
+    let mut files = BTreeMap::<String, RestoreFile>::new();
+
+    for row in rows {
+        let library_path = Path::new(&row.relative_path)
+            .strip_prefix(library_folder)
+            .map(key_for)
+            .unwrap_or_else(|_| row.relative_path.clone());
+
+        match files.get_mut(&library_path) {
+            Some(file) if file.catalog.hash == row.hash => {
+                if file.catalog.content_hash.is_none() {
+                    file.catalog.content_hash = row.content_hash;
+                }
+                if file.catalog.modified_at.is_none() {
+                    file.catalog.modified_at = row.modified_at;
+                }
+            }
+            Some(_) => {}
+            None => {
+                let original_path = library.join(&library_path);
+                files.insert(
+                    library_path.clone(),
+                    RestoreFile {
+                        library_path,
+                        original_path,
+                        catalog: row,
+                    },
+                );
+            }
+        }
+    }
+
+    if let Some(folder) = &selection.folder {
+        let prefix = format!("{}/", folder);
+        files.retain(|path, _| path.starts_with(&prefix));
+    }
+
+    if selection.taken_from.is_none() && selection.taken_until.is_none() && selection.tag.is_none()
+    {
+        return Ok(files.into_values().collect());
+    }
+
+    let hashes = operations::media_hashes_matching(
+        conn,
+        selection.taken_from.as_deref(),
+        selection.taken_until.as_deref(),
+        selection.tag.as_deref(),
+    )
+    .map_err(io::Error::other)?;
+
+    let selected = files
+        .iter()
+        .filter(|(_, file)| hashes.contains(&file.catalog.hash))
+        .map(|(path, _)| path.clone())
+        .collect::<HashSet<_>>();
+
+    // Sidecars have no catalog entry of their own, they go with `IMG_1.ARW` for
+    // `IMG_1.ARW.xmp` or `IMG_1.xmp`
+    let stems = selected
+        .iter()
+        .map(|path| Path::new(path).with_extension(""))
+        .collect::<HashSet<_>>();
+
+    files.retain(|path, _| {
+        selected.contains(path)
+            || (is_sidecar(Path::new(path)) && {
+                let media_path = Path::new(path).with_extension("");
+                selected.contains(media_path.to_string_lossy().as_ref())
+                    || stems.contains(&media_path)
+            })
+    });
+
+    Ok(files.into_values().collect())
+}
+
+/// Whether the file at `path` is the one the catalog describes
+fn check(path: &Path, catalog: &LibraryFileRow) -> io::Result<Check> {
+    if fs::metadata(path)?.len() != catalog.file_size_bytes as u64
+        || calculate_hash(path)? != catalog.hash
+    {
+        return Ok(Check::Damaged);
+    }
+
+    Ok(match &catalog.content_hash {
+        Some(content_hash) if &calculate_full_hash(path)? == content_hash => Check::Intact,
+        Some(_) => Check::Damaged,
+        None => Check::Unverified,
+    })
+}
+
+/// Every copy of the file the catalog knows of, best first: the library's own copy if it
+/// is still there, other mounted copies, then the storages it was backed up to
+fn copies<'a>(restore: &'a Restore, file: &RestoreFile) -> io::Result<Vec<Copy<'a>>> {
+    let mut local = operations::paths_with_hash(restore.conn, &file.catalog.hash)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .filter(|path| !path.starts_with(restore.destination))
+        .filter(|path| {
+            fs::metadata(path)
+                .is_ok_and(|metadata| metadata.len() == file.catalog.file_size_bytes as u64)
+        })
+        .collect::<Vec<_>>();
+
+    local.sort_by_key(|path| *path != file.original_path);
+
+    let mut copies = local.into_iter().map(Copy::Local).collect::<Vec<_>>();
+
+    // Backups are addressed by content hash, unknown until the file is backed up or scrubbed
+    if let Some(content_hash) = &file.catalog.content_hash {
+        let key = object_key(content_hash);
+
+        for storage in &restore.storages {
+            let recorded = operations::remote_object_exists(restore.conn, &storage.name(), &key)
+                .map_err(io::Error::other)?;
+
+            if recorded || storage.exists(&key)? {
+                copies.push(Copy::Remote(storage.as_ref(), key.clone()));
+            }
+        }
+    }
+
+    Ok(copies)
+}
+
+/// Restores from the first copy that checks out against the catalog, and how well it did
+fn restore_file(restore: &Restore, file: &RestoreFile, path: &Path) -> io::Result<Check> {
+    let copies = copies(restore, file)?;
+
+    if restore.dry_run {
+        return match copies.first() {
+            Some(copy) => {
+                println!("Would restore {:?} from {}", path, copy.describe());
+                if let Copy::Local(source) = copy {
+                    restore_sidecar(restore, source, path)?;
+                }
+                Ok(Check::Intact)
+            }
+            None => Err(no_copy_error()),
+        };
+    }
+
+    for copy in &copies {
+        match restore_copy(restore, file, copy, path) {
+            Ok(content_hash) => {
+                let check = match content_hash {
+                    Some(_) => Check::Intact,
+                    None => Check::Unverified,
+                };
+
+                println!(
+                    "Restored {:?} from {}{}",
+                    path,
+                    copy.describe(),
+                    if check == Check::Unverified {
+                        ", unverified: the catalog has no full hash for it"
+                    } else {
+                        ""
+                    }
+                );
+
+                if let Some(device) = &restore.destination_device {
+                    operations::upsert_file_location(
+                        restore.conn,
+                        &file.catalog.hash,
+                        device.id,
+                        &device.relative_path(path),
+                        file.catalog.file_size_bytes,
+                    )
+                    .map_err(io::Error::other)?;
+
+                    // Only a copy checked against a recorded hash counts as verified
+                    if let Some(content_hash) = &content_hash {
+                        operations::record_location_check(
+                            restore.conn,
+                            device.id,
+                            &device.relative_path(path),
+                            Some(content_hash),
+                            "ok",
+                        )
+                        .map_err(io::Error::other)?;
+                    }
+                }
+
+                if let Copy::Local(source) = copy {
+                    restore_sidecar(restore, source, path)?;
+                }
+
+                return Ok(check);
+            }
+            Err(e) => println!(
+                "Not restoring from this copy; Copy : {}; Error : {:?}",
+                copy.describe(),
+                e
+            ),
+        }
+    }
+
+    Err(no_copy_error())
+}
+
+/// Restores from one copy, returning the recorded content hash the restored file was
+/// checked against, None when there is none
+fn restore_copy(
+    restore: &Restore,
+    file: &RestoreFile,
+    copy: &Copy,
+    path: &Path,
+) -> io::Result<Option<String>> {
+    let catalog = &file.catalog;
+
+    match copy {
+        Copy::Local(source) => {
+            if check(source, catalog)? == Check::Damaged {
+                return Err(damaged_error(source.display().to_string()));
+            }
+
+            let content_hash = calculate_full_hash(source)?;
+            let destination = LocalBackend::new(restore.destination);
+            put_verified(&destination, &file.library_path, source, &content_hash)?;
+
+            let modified_at = fs::metadata(source)?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+
+            set_modified_at(path, catalog.modified_at.unwrap_or(modified_at))?;
+
+            Ok(catalog.content_hash.clone())
+        }
+        Copy::Remote(storage, key) => {
+            storage.get(key, path)?;
+
+            if check(path, catalog)? != Check::Intact {
+                fs::remove_file(path)?;
+                return Err(damaged_error(copy.describe()));
+            }
+
+            if let Some(modified_at) = catalog.modified_at {
+                set_modified_at(path, modified_at)?;
+            }
+
+            Ok(catalog.content_hash.clone())
+        }
+    }
+}
+
+/// Copies the sidecar next to `source` along with it, unless the sidecar is restored from
+/// an entry of its own. The catalog keeps no hash of sidecars, they change with every edit.
+fn restore_sidecar(restore: &Restore, source: &Path, path: &Path) -> io::Result<()> {
+    let Some(sidecar) = Sidecar::from_media_path(source) else {
+        return Ok(());
+    };
+
+    let sidecar_path = sidecar.final_path_for(source, path);
+    let key = key_under(restore.destination, &sidecar_path)?;
+
+    if restore.selected.contains(&key) || sidecar_path.exists() {
+        return Ok(());
+    }
+
+    if restore.dry_run {
+        println!("Would restore {:?} from {:?}", sidecar_path, sidecar.path);
+        return Ok(());
+    }
+
+    let destination = LocalBackend::new(restore.destination);
+    put_verified(
+        &destination,
+        &key,
+        &sidecar.path,
+        &calculate_full_hash(&sidecar.path)?,
+    )?;
+
+    println!("Restored {:?} from {:?}", sidecar_path, sidecar.path);
+
+    Ok(())
+}
+
+fn damaged_error(copy: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} doesn't match the catalog", copy),
+    )
+}
+
+fn no_copy_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "No copy the catalog knows of is available and intact",
+    )
+}
@@ -0,0 +1,465 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    config::Config,
+    database::{models::LocationRow, operations},
+    utils::{
+        devices::is_mounted,
+        duplicates::calculate_full_hash,
+        parity::repair_from_parity,
+        storage::{LocalBackend, StorageBackend, object_key, open_backend, put_verified},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics scrub [--repair] [--from <storage>]... [<folder> ...]
+
+Reads back every file the catalog has on the volumes plugged in right now, or only the
+files under <folder> (and their copies on other volumes), and checks its whole content.
+A file is compared with the full SHA-256 it was verified as before. A file never verified
+has to match what a copy of it was backed up or verified as; the first time any of them is
+scrubbed, the copies are compared with each other. Files that no longer match are reported
+as damaged, files that are gone as missing, and never verified files that match nothing as
+undecided: they may be different files that only share their size and first 128 KB.
+Every copy that checks out gets its last verified time updated.
+
+A damaged file with an intact copy on another volume, or on a storage given with --from,
+can be repaired from it, and failing that from the recovery data `parity` wrote to its
+year folder. scrub asks before each repair; --repair repairs without asking, except
+undecided files, which are only ever replaced when you say so.";
+
+/// Whether damaged files are replaced from a good copy
+#[derive(PartialEq)]
+enum Repair {
+    Ask,
+    Always,
+    /// Nobody to ask
+    Never,
+}
+
+/// A copy of a file on a mounted volume and what reading it back found
+struct Checked {
+    location: LocationRow,
+    path: PathBuf,
+    /// `None` when the file is gone
+    read: Option<io::Result<String>>,
+}
+
+struct Scrub<'a> {
+    conn: &'a Connection,
+    storages: Vec<Box<dyn StorageBackend>>,
+    repair: Repair,
+    ok: u64,
+    damaged: u64,
+    repaired: u64,
+    missing: u64,
+    undecided: u64,
+}
+
+pub fn run(conn: &Connection, args: &[String], config: &Config) -> io::Result<()> {
+    let mut repair = if io::stdin().is_terminal() {
+        Repair::Ask
+    } else {
+        Repair::Never
+    };
+    let mut storages = Vec::new();
+    let mut folders = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--repair" => repair = Repair::Always,
+            "--from" if !args.as_slice().is_empty() => storages.push(args.next().unwrap()),
+            _ if !arg.starts_with("--") => folders.push(fs::canonicalize(arg)?),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    let storages = storages
+        .into_iter()
+        .map(|storage| open_backend(storage, config))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mount_points = operations::list_devices(conn)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .filter(is_mounted)
+        .filter_map(|device| Some((device.id, PathBuf::from(device.mount_point?))))
+        .collect::<HashMap<_, _>>();
+
+    // Copies of one file are checked together, so they can be compared and one can
+    // repair another
+    let mut files = BTreeMap::<(String, i64), Vec<(LocationRow, PathBuf)>>::new();
+
+    for location in operations::all_locations(conn).map_err(io::Error::other)? {
+        let Some(mount_point) = mount_points.get(&location.device_id) else {
+            continue;
+        };
+
+        let path = mount_point.join(&location.relative_path);
+
+        files
+            .entry((location.hash.clone(), location.file_size_bytes))
+            .or_default()
+            .push((location, path));
+    }
+
+    if !folders.is_empty() {
+        files.retain(|_, copies| {
+            copies
+                .iter()
+                .any(|(_, path)| folders.iter().any(|folder| path.starts_with(folder)))
+        });
+    }
+
+    let mut scrub = Scrub {
+        conn,
+        storages,
+        repair,
+        ok: 0,
+        damaged: 0,
+        repaired: 0,
+        missing: 0,
+        undecided: 0,
+    };
+
+    for ((hash, file_size), copies) in files {
+        let checked = copies
+            .into_iter()
+            .map(|(location, path)| {
+                let read = path.exists().then(|| calculate_full_hash(&path));
+                Checked {
+                    location,
+                    path,
+                    read,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        scrub_file(&mut scrub, &hash, file_size, &checked)?;
+    }
+
+    println!(
+        "{} ok, {} damaged ({} repaired), {} missing, {} undecided",
+        scrub.ok, scrub.damaged, scrub.repaired, scrub.missing, scrub.undecided
+    );
+
+    Ok(())
+}
+
+/// Records what every copy of one file read back as, and repairs the damaged ones. Copies
+/// share a hash and size, not necessarily their content: each copy is held to the full
+/// hash it was verified as before, and one never verified only to a hash some copy or
+/// backup was.
+fn scrub_file(
+    scrub: &mut Scrub,
+    hash: &str,
+    file_size: i64,
+    checked: &[Checked],
+) -> io::Result<()> {
+    let recorded = operations::recorded_content_hashes(scrub.conn, hash, file_size)
+        .map_err(io::Error::other)?;
+
+    // The first time, copies can only be compared with each other
+    let majority = if recorded.is_empty() {
+        majority(checked)
+    } else {
+        None
+    };
+
+    if recorded.is_empty() && majority.is_none() && checked.iter().any(|copy| copy.read.is_some()) {
+        println!("Copies of {} disagree and none was verified before:", hash);
+        for copy in checked {
+            println!("    {:?}", copy.path);
+        }
+        scrub.undecided += checked.len() as u64;
+        return Ok(());
+    }
+
+    // What a copy that was never verified is taken to be when it matches nothing
+    let expected = match recorded.len() {
+        1 => recorded.iter().next().cloned(),
+        _ => majority.clone(),
+    };
+
+    let mut good = HashMap::<&str, Vec<&Path>>::new();
+    // (copy, what it should hash to, whether it was verified as that before)
+    let mut damaged = Vec::new();
+
+    for copy in checked {
+        let own = copy.location.content_hash.as_deref();
+
+        let (status, content_hash) = match (&copy.read, own) {
+            (None, _) => {
+                println!("Missing: {:?}", copy.path);
+                scrub.missing += 1;
+                ("missing", None)
+            }
+            (Some(Ok(read)), Some(own)) if read == own => ("ok", Some(read.as_str())),
+            (Some(Ok(read)), None)
+                if recorded.contains(read) || majority.as_ref() == Some(read) =>
+            {
+                ("ok", Some(read.as_str()))
+            }
+            (Some(read), Some(own)) => {
+                report_damaged(&copy.path, read, own);
+                scrub.damaged += 1;
+                damaged.push((copy, own, true));
+                ("damaged", Some(own))
+            }
+            (Some(read), None) => {
+                // Possibly another file with the same size and first 128 KB
+                match read {
+                    Ok(read) => println!(
+                        "Undecided: {:?} reads as {}, which no copy was verified as",
+                        copy.path, read
+                    ),
+                    Err(e) => println!("Undecided: {:?} can't be read; Error : {:?}", copy.path, e),
+                }
+                scrub.undecided += 1;
+                if let Some(expected) = &expected {
+                    damaged.push((copy, expected.as_str(), false));
+                }
+                continue;
+            }
+        };
+
+        if status == "ok" {
+            scrub.ok += 1;
+            good.entry(content_hash.unwrap_or_default())
+                .or_default()
+                .push(copy.path.as_path());
+        }
+
+        operations::record_location_check(
+            scrub.conn,
+            copy.location.device_id,
+            &copy.location.relative_path,
+            content_hash,
+            status,
+        )
+        .map_err(io::Error::other)?;
+    }
+
+    for (copy, content_hash, verified) in damaged {
+        let sources = good
+            .get(content_hash)
+            .map_or(&[][..], |paths| paths.as_slice());
+
+        if repair_file(scrub, copy, content_hash, verified, sources)? {
+            scrub.repaired += 1;
+        }
+    }
+
+    Ok(())
+}
+
+fn report_damaged(path: &Path, read: &io::Result<String>, expected: &str) {
+    match read {
+        Ok(read) => println!(
+            "Damaged: {:?} reads as {}, expected {}",
+            path, read, expected
+        ),
+        Err(e) => println!("Damaged: {:?} can't be read; Error : {:?}", path, e),
+    }
+}
+
+/// The content hash most copies read back as, if one has more copies than any other
+fn majority(checked: &[Checked]) -> Option<String> {
+    let mut counts = HashMap::<&str, usize>::new();
+
+    for copy in checked {
+        if let Some(Ok(read)) = &copy.read {
+            *counts.entry(read).or_default() += 1;
+        }
+    }
+
+    let top = counts.values().copied().max()?;
+    let mut winners = counts.into_iter().filter(|(_, count)| *count == top);
+
+    match (winners.next(), winners.next()) {
+        (Some((content_hash, _)), None) => Some(content_hash.to_string()),
+        _ => None,
+    }
+}
+
+/// Replaces a damaged copy from the first intact one, if the user agrees. A copy that
+/// was never `verified` as `content_hash` may be another file, so it is only replaced when
+/// the user says so each time. Returns whether it was repaired.
+fn repair_file(
+    scrub: &Scrub,
+    copy: &Checked,
+    content_hash: &str,
+    verified: bool,
+    good: &[&Path],
+) -> io::Result<bool> {
+    if scrub.repair == Repair::Never {
+        return Ok(false);
+    }
+
+    if !verified && scrub.repair == Repair::Always {
+        println!(
+            "Not repairing {:?} without asking, it was never verified and may be another file",
+            copy.path
+        );
+        return Ok(false);
+    }
+
+    let key = object_key(content_hash);
+    let mut backups = HashSet::new();
+
+    for storage in &scrub.storages {
+        if storage.exists(&key)? {
+            backups.insert(storage.name());
+        }
+    }
+
+    let parity = operations::parity_folder_for(
+        scrub.conn,
+        copy.location.device_id,
+        &copy.location.relative_path,
+    )
+    .map_err(io::Error::other)?;
+
+    let has_backups = !backups.is_empty();
+
+    let sources = good
+        .iter()
+        .map(|path| path.display().to_string())
+        .chain(backups)
+        .chain(
+            parity
+                .iter()
+                .map(|folder| format!("the recovery data of {}", folder)),
+        )
+        .collect::<Vec<_>>();
+
+    let Some(source) = sources.first() else {
+        println!("No intact copy to repair {:?} from", copy.path);
+        return Ok(false);
+    };
+
+    let question = if verified {
+        format!("Repair {:?} from {}? [y/N] ", copy.path, source)
+    } else {
+        format!(
+            "Replace {:?} with {}? It may be another file that only starts the same [y/N] ",
+            copy.path, source
+        )
+    };
+
+    if scrub.repair == Repair::Ask && !confirm(&question)? {
+        return Ok(false);
+    }
+
+    let (Some(folder), Some(file_name)) = (copy.path.parent(), copy.path.file_name()) else {
+        return Ok(false);
+    };
+
+    let repaired = match (good.first(), parity) {
+        (Some(path), _) => put_verified(
+            &LocalBackend::new(folder),
+            &file_name.to_string_lossy(),
+            path,
+            content_hash,
+        ),
+        (None, _) if has_backups => repair_from_backup(scrub, &key, &copy.path, content_hash),
+        (None, Some(parity_folder)) => repair_from_parity_set(copy, &parity_folder, content_hash),
+        (None, None) => return Ok(false),
+    };
+
+    match repaired {
+        Ok(()) => {
+            println!("Repaired {:?} from {}", copy.path, source);
+
+            operations::record_location_check(
+                scrub.conn,
+                copy.location.device_id,
+                &copy.location.relative_path,
+                Some(content_hash),
+                "ok",
+            )
+            .map_err(io::Error::other)?;
+
+            Ok(true)
+        }
+        Err(e) => {
+            println!(
+                "Error repairing file; Path : {:?}; Error : {:?}",
+                copy.path.to_str(),
+                e
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// Downloads the object next to the damaged file and only replaces it once the
+/// download checks out
+fn repair_from_backup(scrub: &Scrub, key: &str, path: &Path, content_hash: &str) -> io::Result<()> {
+    let download = path.with_file_name(format!(
+        "{}.repair",
+        path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+    ));
+
+    for storage in &scrub.storages {
+        if !storage.exists(key)? {
+            continue;
+        }
+
+        storage.get(key, &download)?;
+
+        if calculate_full_hash(&download)? == content_hash {
+            return fs::rename(&download, path);
+        }
+
+        fs::remove_file(&download)?;
+        println!("{} {} doesn't match either", storage.name(), key);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "No backup holds an intact copy",
+    ))
+}
+
+/// Rebuilds the file from the parity file of `parity_folder` (from the device root),
+/// found on the same volume as the file
+fn repair_from_parity_set(
+    copy: &Checked,
+    parity_folder: &str,
+    content_hash: &str,
+) -> io::Result<()> {
+    let path_in_folder = copy
+        .location
+        .relative_path
+        .strip_prefix(parity_folder)
+        .map(|path| path.trim_start_matches('/'))
+        .unwrap_or(&copy.location.relative_path);
+
+    let Some(folder) = copy.path.ancestors().nth(path_in_folder.split('/').count()) else {
+        return Err(io::Error::other("Recovery data folder not found"));
+    };
+
+    repair_from_parity(folder, path_in_folder, content_hash)
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{}", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
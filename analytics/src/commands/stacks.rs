@@ -0,0 +1,43 @@
+use std::io;
+
+use rusqlite::Connection;
+
+use crate::database::operations;
+
+const USAGE: &str = "Usage:
+    analytics stacks [burst|ae_bracket|focus_bracket|panorama]
+
+Lists the burst, bracket and panorama sequences found while scanning, frame by frame.
+The designated pick of each stack is marked with *.";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let kind = match args {
+        [] => None,
+        [kind] if ["burst", "ae_bracket", "focus_bracket", "panorama"].contains(&kind.as_str()) => {
+            Some(kind.as_str())
+        }
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let members = operations::stack_members(conn, kind).map_err(io::Error::other)?;
+    let mut last_stack = None;
+
+    for member in members {
+        if last_stack != Some(member.stack_id) {
+            println!("Stack {} ({})", member.stack_id, member.kind);
+            last_stack = Some(member.stack_id);
+        }
+
+        println!(
+            "{}\t{}\t{}",
+            if member.is_pick { "*" } else { "" },
+            member.position,
+            member.path
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::{io, path::Path};
+
+use crate::{
+    config::Config,
+    utils::{
+        duplicates::calculate_full_hash,
+        storage::{open_backend, put_verified},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics storage <storage> list [<prefix>]
+    analytics storage <storage> get <key> <file>
+    analytics storage <storage> put <file> <key>
+    analytics storage <storage> delete <key>
+
+Works on a storage backend directly, to check it is set up right. <storage> is s3, the
+name of a [storage.<name>] backend or a local folder. put reads the upload back and
+checks its hash.";
+
+pub fn run(args: &[String], config: &Config) -> io::Result<()> {
+    let Some((storage, command)) = args.split_first() else {
+        println!("{}", USAGE);
+        return Ok(());
+    };
+
+    let backend = match open_backend(storage, config) {
+        Ok(backend) => backend,
+        Err(e) => {
+            println!("Storage isn't set up; Error : {}", e);
+            return Ok(());
+        }
+    };
+
+    match command {
+        [command] if command == "list" => print_keys(&backend.list("")?),
+        [command, prefix] if command == "list" => print_keys(&backend.list(prefix)?),
+        [command, key, file] if command == "get" => {
+            backend.get(key, Path::new(file))?;
+            println!("Downloaded {} to {:?}", key, file);
+        }
+        [command, file, key] if command == "put" => {
+            let path = Path::new(file);
+            put_verified(backend.as_ref(), key, path, &calculate_full_hash(path)?)?;
+            println!("Uploaded {:?} to {} and checked its hash", file, key);
+        }
+        [command, key] if command == "delete" => {
+            backend.delete(key)?;
+            println!("Deleted {}", key);
+        }
+        _ => println!("{}", USAGE),
+    }
+
+    Ok(())
+}
+
+fn print_keys(keys: &[String]) {
+    for key in keys {
+        println!("{}", key);
+    }
+
+    println!("{} object(s)", keys.len());
+}
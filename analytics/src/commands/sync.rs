@@ -0,0 +1,533 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use rusqlite::Connection;
+
+use crate::{
+    database::operations,
+    format_size,
+    utils::{
+        core::collect_library_files,
+        devices::{Device, register_device},
+        duplicates::{calculate_full_hash, calculate_hash},
+        sidecar::is_sidecar,
+        storage::{LocalBackend, put_verified},
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics sync [--one-way] [--dry-run] <library> <library> [<library> ...]
+
+Makes library volumes (e.g. the primary and the travel SSD) hold the same files. Files are
+compared by hash and size, taken from the catalog when the size still matches, and confirmed
+on the full SHA-256 before a copy is skipped or two files are called the same. Whatever a
+library is missing is copied from another one, at the same path. With --one-way the first
+library is the source and the others are only added to. Nothing is ever deleted.
+
+A sidecar edited in one library since the last sync replaces the other copies. Edited in
+more than one, it is left alone and reported as a conflict, as are two different files at
+the same path. A file that can't be read or copied is reported and skipped, the next sync
+tries it again. --dry-run only prints what would be copied.";
+
+/// A library root and what is in it, by path from the root
+struct Library {
+    root: PathBuf,
+    storage: LocalBackend,
+    device: Device,
+    /// Hash and size of every photo and video
+    media: HashMap<String, (String, u64)>,
+    /// Paths of the photos and videos with each hash and size
+    by_hash: HashMap<(String, u64), Vec<String>>,
+    /// Full hash of every sidecar
+    sidecars: HashMap<String, String>,
+}
+
+/// Counts of a run, and the session they are recorded under (none for --dry-run)
+struct SyncRun<'a> {
+    conn: &'a Connection,
+    session_id: Option<i64>,
+    files_copied: i64,
+    bytes_copied: u64,
+    conflicts: i64,
+    /// Files that couldn't be read or copied, once per copy
+    failed: i64,
+    /// Full hashes read so far, by absolute path
+    full_hashes: HashMap<PathBuf, String>,
+}
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    let mut one_way = false;
+    let mut dry_run = false;
+    let mut roots = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "--one-way" => one_way = true,
+            "--dry-run" => dry_run = true,
+            _ => roots.push(PathBuf::from(arg)),
+        }
+    }
+
+    if roots.len() < 2 {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    if let Some(root) = roots.iter().find(|root| !root.is_dir()) {
+        println!("Not a folder: {:?}", root);
+        return Ok(());
+    }
+
+    let libraries = roots
+        .iter()
+        .map(|root| load_library(conn, root))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let session_id = if dry_run {
+        None
+    } else {
+        let roots = roots
+            .iter()
+            .map(|root| root.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(";");
+        let mode = if one_way { "one_way" } else { "two_way" };
+
+        Some(operations::new_sync_session(conn, &roots, mode).map_err(io::Error::other)?)
+    };
+
+    let mut sync = SyncRun {
+        conn,
+        session_id,
+        files_copied: 0,
+        bytes_copied: 0,
+        conflicts: 0,
+        failed: 0,
+        full_hashes: HashMap::new(),
+    };
+
+    sync_media(&mut sync, &libraries, one_way);
+    sync_sidecars(&mut sync, &libraries, one_way);
+
+    if let Some(session_id) = session_id {
+        operations::update_sync_session_completed(
+            conn,
+            session_id,
+            sync.files_copied,
+            sync.bytes_copied as i64,
+            sync.conflicts,
+            sync.failed,
+        )
+        .map_err(io::Error::other)?;
+    }
+
+    println!(
+        "{}{} file(s) copied ({}), {} conflict(s), {} failed",
+        session_id
+            .map(|id| format!("Session {}: ", id))
+            .unwrap_or_else(|| "Dry run: ".to_string()),
+        sync.files_copied,
+        format_size(sync.bytes_copied),
+        sync.conflicts,
+        sync.failed
+    );
+
+    Ok(())
+}
+
+/// Walks a library, hashing only the files the catalog doesn't know at their current size
+fn load_library(conn: &Connection, root: &Path) -> io::Result<Library> {
+    let device = register_device(conn, root)?;
+
+    let mut files = Vec::new();
+    collect_library_files(root, &mut files)?;
+
+    let mut media = HashMap::new();
+    let mut by_hash: HashMap<_, Vec<_>> = HashMap::new();
+    let mut sidecars = HashMap::new();
+
+    for path in files {
+        let relative_path = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+
+        if is_sidecar(&path) {
+            sidecars.insert(relative_path, calculate_full_hash(&path)?);
+            continue;
+        }
+
+        let file_size = fs::metadata(&path)?.len();
+        let path_on_device = device.relative_path(&path);
+
+        let cataloged =
+            operations::location_hash(conn, device.id, &path_on_device, file_size as i64)
+                .map_err(io::Error::other)?;
+
+        let hash = match cataloged {
+            Some(hash) => hash,
+            None => {
+                let hash = calculate_hash(&path)?;
+                operations::upsert_file_location(
+                    conn,
+                    &hash,
+                    device.id,
+                    &path_on_device,
+                    file_size as i64,
+                )
+                .map_err(io::Error::other)?;
+                hash
+            }
+        };
+
+        by_hash
+            .entry((hash.clone(), file_size))
+            .or_default()
+            .push(relative_path.clone());
+        media.insert(relative_path, (hash, file_size));
+    }
+
+    Ok(Library {
+        root: root.to_path_buf(),
+        storage: LocalBackend::new(root),
+        device,
+        media,
+        by_hash,
+        sidecars,
+    })
+}
+
+/// Copies every photo and video to the libraries that have no file with its content.
+/// The partial hash and size only pick the candidates, the full hash decides.
+fn sync_media(sync: &mut SyncRun, libraries: &[Library], one_way: bool) {
+    let paths = libraries
+        .iter()
+        .flat_map(|library| library.media.keys())
+        .collect::<BTreeSet<_>>();
+
+    for relative_path in paths {
+        if let Err(e) = sync_media_file(sync, libraries, relative_path, one_way) {
+            sync.failed(relative_path, &e);
+        }
+    }
+}
+
+fn sync_media_file(
+    sync: &mut SyncRun,
+    libraries: &[Library],
+    relative_path: &str,
+    one_way: bool,
+) -> io::Result<()> {
+    let versions = libraries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, library)| library.media.get(relative_path).map(|v| (i, v)))
+        .collect::<Vec<_>>();
+
+    let distinct = versions.iter().map(|(_, v)| v).collect::<BTreeSet<_>>();
+
+    if distinct.len() > 1 {
+        let hashes = versions
+            .iter()
+            .map(|(i, (hash, _))| (*i, hash))
+            .collect::<Vec<_>>();
+        let details = describe_versions(libraries, &hashes);
+        return sync.conflict(relative_path, "media", &details);
+    }
+
+    if !sync.same_content(libraries, &versions, relative_path)? {
+        let details = format!(
+            "same size and first 128 KB, different content in {}",
+            versions
+                .iter()
+                .map(|(i, _)| libraries[*i].root.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        return sync.conflict(relative_path, "media", &details);
+    }
+
+    // One-way only copies what the source has
+    let Some(&(source, version)) = versions.iter().find(|(i, _)| !one_way || *i == 0) else {
+        return Ok(());
+    };
+
+    for target in libraries {
+        if target.media.contains_key(relative_path) {
+            continue;
+        }
+
+        // A copy that fails doesn't keep the other libraries from getting theirs
+        let copied = match sync.holds_copy(&libraries[source], relative_path, target, version) {
+            Ok(true) => Ok(()),
+            Ok(false) => sync.copy(&libraries[source], target, relative_path, Some(&version.0)),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = copied {
+            sync.failed(relative_path, &e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Spreads sidecar edits made since the last sync. The hash every library agreed on
+/// then tells which copies were edited: one edited version wins, more are a conflict.
+fn sync_sidecars(sync: &mut SyncRun, libraries: &[Library], one_way: bool) {
+    let sync_set = sync_set(libraries);
+
+    let paths = libraries
+        .iter()
+        .flat_map(|library| library.sidecars.keys())
+        .collect::<BTreeSet<_>>();
+
+    let synced = match operations::synced_sidecars(sync.conn, &sync_set) {
+        Ok(synced) => synced,
+        Err(e) => {
+            // Without the agreed versions no edit can be told apart from a stale copy
+            let e = io::Error::other(e);
+            for relative_path in paths {
+                sync.failed(relative_path, &e);
+            }
+            return;
+        }
+    };
+
+    for relative_path in paths {
+        let last_synced = synced.get(relative_path);
+
+        if let Err(e) = sync_sidecar_file(
+            sync,
+            libraries,
+            &sync_set,
+            last_synced,
+            relative_path,
+            one_way,
+        ) {
+            sync.failed(relative_path, &e);
+        }
+    }
+}
+
+fn sync_sidecar_file(
+    sync: &mut SyncRun,
+    libraries: &[Library],
+    sync_set: &str,
+    last_synced: Option<&String>,
+    relative_path: &str,
+    one_way: bool,
+) -> io::Result<()> {
+    let versions = libraries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, library)| library.sidecars.get(relative_path).map(|h| (i, h)))
+        .collect::<Vec<_>>();
+
+    // Never synced before, every version counts as an edit
+    let edited = versions
+        .iter()
+        .map(|(_, h)| *h)
+        .filter(|h| Some(*h) != last_synced)
+        .collect::<BTreeSet<_>>();
+
+    let winner = match (edited.len(), last_synced) {
+        (0, Some(last_synced)) => last_synced,
+        (1, _) => *edited.first().unwrap(),
+        _ => {
+            let details = describe_versions(libraries, &versions);
+            return sync.conflict(relative_path, "sidecar", &details);
+        }
+    };
+
+    let Some(&(source, _)) = versions.iter().find(|(_, h)| *h == winner) else {
+        return Ok(());
+    };
+
+    if one_way && source != 0 {
+        // Edited on a target only; one-way never copies back
+        if libraries[0].sidecars.contains_key(relative_path) {
+            let details = format!(
+                "edited in {} only, --one-way won't copy it back",
+                libraries[source].root.display()
+            );
+            sync.conflict(relative_path, "sidecar", &details)?;
+        }
+        return Ok(());
+    }
+
+    let mut all_copied = true;
+
+    for target in libraries {
+        if target.sidecars.get(relative_path) != Some(winner)
+            && let Err(e) = sync.copy(&libraries[source], target, relative_path, None)
+        {
+            sync.failed(relative_path, &e);
+            all_copied = false;
+        }
+    }
+
+    // Until every library has it, the libraries don't agree on this version
+    if all_copied && sync.session_id.is_some() {
+        operations::upsert_synced_sidecar(sync.conn, sync_set, relative_path, winner)
+            .map_err(io::Error::other)?;
+    }
+
+    Ok(())
+}
+
+/// Key of a set of libraries, the same whatever order they are given in
+fn sync_set(libraries: &[Library]) -> String {
+    let mut keys = libraries
+        .iter()
+        .map(|library| {
+            format!(
+                "{}:{}",
+                library.device.id,
+                library.device.relative_path(&library.root)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    keys.sort();
+    keys.join(";")
+}
+
+fn describe_versions(libraries: &[Library], versions: &[(usize, &String)]) -> String {
+    versions
+        .iter()
+        .map(|(i, hash)| format!("{} has {}", libraries[*i].root.display(), &hash[..12]))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl SyncRun<'_> {
+    /// Copies `relative_path` between libraries and checks the copy's hash, recording
+    /// where media files now are
+    fn copy(
+        &mut self,
+        from: &Library,
+        to: &Library,
+        relative_path: &str,
+        hash: Option<&String>,
+    ) -> io::Result<()> {
+        let source = from.root.join(relative_path);
+        let destination = to.root.join(relative_path);
+
+        if self.session_id.is_none() {
+            println!("Would copy {:?} to {:?}", source, destination);
+            self.files_copied += 1;
+            self.bytes_copied += fs::metadata(&source)?.len();
+            return Ok(());
+        }
+
+        put_verified(
+            &to.storage,
+            relative_path,
+            &source,
+            &self.full_hash(&source)?,
+        )?;
+
+        let file_size = fs::metadata(&destination)?.len();
+
+        if let Some(hash) = hash {
+            operations::upsert_file_location(
+                self.conn,
+                hash,
+                to.device.id,
+                &to.device.relative_path(&destination),
+                file_size as i64,
+            )
+            .map_err(io::Error::other)?;
+        }
+
+        println!("Copied {:?} to {:?}", source, destination);
+
+        self.files_copied += 1;
+        self.bytes_copied += file_size;
+
+        Ok(())
+    }
+
+    fn full_hash(&mut self, path: &Path) -> io::Result<String> {
+        if let Some(full_hash) = self.full_hashes.get(path) {
+            return Ok(full_hash.clone());
+        }
+
+        let full_hash = calculate_full_hash(path)?;
+        self.full_hashes
+            .insert(path.to_path_buf(), full_hash.clone());
+
+        Ok(full_hash)
+    }
+
+    /// Whether the files at `relative_path`, already known to share hash and size, are
+    /// identical all the way through
+    fn same_content(
+        &mut self,
+        libraries: &[Library],
+        versions: &[(usize, &(String, u64))],
+        relative_path: &str,
+    ) -> io::Result<bool> {
+        if versions.len() < 2 {
+            return Ok(true);
+        }
+
+        let mut full_hashes = BTreeSet::new();
+
+        for (i, _) in versions {
+            full_hashes.insert(self.full_hash(&libraries[*i].root.join(relative_path))?);
+        }
+
+        Ok(full_hashes.len() <= 1)
+    }
+
+    /// Whether `target` has a file, at any path, identical to `relative_path` in `source`
+    fn holds_copy(
+        &mut self,
+        source: &Library,
+        relative_path: &str,
+        target: &Library,
+        version: &(String, u64),
+    ) -> io::Result<bool> {
+        let Some(candidates) = target.by_hash.get(version) else {
+            return Ok(false);
+        };
+
+        let full_hash = self.full_hash(&source.root.join(relative_path))?;
+
+        for candidate in candidates {
+            if self.full_hash(&target.root.join(candidate))? == full_hash {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    fn failed(&mut self, relative_path: &str, e: &io::Error) {
+        println!(
+            "Error syncing file; Path : {:?}; Error : {:?}",
+            relative_path, e
+        );
+
+        self.failed += 1;
+    }
+
+    fn conflict(&mut self, relative_path: &str, kind: &str, details: &str) -> io::Result<()> {
+        println!("Conflict ({}) {}: {}", kind, relative_path, details);
+
+        if let Some(session_id) = self.session_id {
+            operations::insert_sync_conflict(self.conn, session_id, relative_path, kind, details)
+                .map_err(io::Error::other)?;
+        }
+
+        self.conflicts += 1;
+
+        Ok(())
+    }
+}
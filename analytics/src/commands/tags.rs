@@ -0,0 +1,94 @@
+use std::{fs, io};
+
+use rusqlite::Connection;
+
+use crate::{database::operations, utils::tags::normalize_tag};
+
+const USAGE: &str = "Usage:
+    analytics tag add <tag> <path>...
+    analytics tag remove <tag> <path>...
+    analytics tag list [prefix]
+    analytics tag show <path>
+
+Tags can be hierarchical, e.g. trips/2025/japan. A folder path applies to every media file below it.";
+
+pub fn run(conn: &Connection, args: &[String]) -> io::Result<()> {
+    // Checked here, so a name made of separators and spaces never reaches the database
+    if let [command, tag, ..] = args
+        && (command == "add" || command == "remove")
+        && normalize_tag(tag).is_none()
+    {
+        println!("Not a tag name: {:?}", tag);
+        return Ok(());
+    }
+
+    match args {
+        [command, tag, paths @ ..] if command == "add" && !paths.is_empty() => {
+            let media_file_ids = media_file_ids_for_paths(conn, paths)?;
+
+            let added = operations::tag_media_files(conn, tag, &media_file_ids, "manual")
+                .map_err(io::Error::other)?;
+
+            println!("Tagged {} media file(s) with {:?}", added, tag);
+        }
+        [command, tag, paths @ ..] if command == "remove" && !paths.is_empty() => {
+            let media_file_ids = media_file_ids_for_paths(conn, paths)?;
+
+            let removed = operations::untag_media_files(conn, tag, &media_file_ids)
+                .map_err(io::Error::other)?;
+
+            println!("Removed {:?} from {} media file(s)", tag, removed);
+        }
+        [command, rest @ ..] if command == "list" && rest.len() <= 1 => {
+            let tags = operations::list_tags(conn, rest.first().map(|p| p.as_str()))
+                .map_err(io::Error::other)?;
+
+            for tag in tags {
+                println!("{}\t{}", tag.name, tag.media_count);
+            }
+        }
+        [command, path] if command == "show" => {
+            for media_file_id in media_file_ids_for_paths(conn, std::slice::from_ref(path))? {
+                let tags = operations::tags_for_media_file(conn, media_file_id)
+                    .map_err(io::Error::other)?;
+
+                println!("{}\t{}", media_file_id, tags.join(", "));
+            }
+        }
+        _ => println!("{}", USAGE),
+    }
+
+    Ok(())
+}
+
+fn media_file_ids_for_paths(conn: &Connection, paths: &[String]) -> io::Result<Vec<i64>> {
+    let mut media_file_ids: Vec<i64> = Vec::new();
+
+    for path in paths {
+        // The catalog stores paths as they were scanned, try both the given and the absolute form
+        let mut candidates = vec![path.clone()];
+        if let Some(canonical) = fs::canonicalize(path)
+            .ok()
+            .and_then(|p| p.to_str().map(|s| s.to_string()))
+        {
+            candidates.push(canonical);
+        }
+
+        let before = media_file_ids.len();
+        for candidate in candidates {
+            for id in
+                operations::media_file_ids_under_path(conn, &candidate).map_err(io::Error::other)?
+            {
+                if !media_file_ids.contains(&id) {
+                    media_file_ids.push(id);
+                }
+            }
+        }
+
+        if media_file_ids.len() == before {
+            println!("No cataloged media found for {:?}", path);
+        }
+    }
+
+    Ok(media_file_ids)
+}
@@ -0,0 +1,674 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::NaiveDateTime;
+use notify::{EventKind, RecursiveMode, Watcher};
+use rusqlite::Connection;
+
+use crate::{
+    commands::import::import_card,
+    config::{Config, MountAction, StorageConfig},
+    database::{connection::Database, operations},
+    utils::{
+        core::{
+            FileType, collect_media_files, export_images_to_new_destination, library_copy,
+            scan_files,
+        },
+        derivatives::link_edited_to_originals,
+        devices::{find_device, identify_volume, register_device},
+        duplicates::{calculate_full_hash, find_duplicates},
+        storage::{LocalBackend, StorageBackend, key_under, open_backend},
+        volume::mount_points,
+    },
+};
+
+const USAGE: &str = "Usage:
+    analytics watch [--settle <seconds>] [<inbox> ...]
+
+Runs until stopped, watching the inbox folders ([watch] inboxes in config.toml, or the
+<inbox> folders given) for new photos and videos. Once none of the new files in an inbox
+has changed size for --settle seconds (30 by default), they are scanned into the catalog,
+duplicates among them are found and the best copy of each is exported to the library like
+a backup does, to <root>/final_export or the [export] storage. Files the library already
+holds are skipped, under any name when it's a local folder. Each batch is recorded as a
+backup session.
+
+Files already in an inbox when watch starts are picked up as well, unless watch took them
+in before at the same size. A batch that fails to export is tried again once it settles, and
+after a restart; files without a date taken are never exported and are reported each time.
+
+A camera card (a volume with a DCIM folder) mounted while watch runs is imported the way
+import does, to [watch] import_library, once asked on the terminal or right away as
+[watch] on_mount says. Rules under [watch.devices.<marker id, UUID or label>] (looked up in
+that order) override it for one volume, can import drives the catalog knows by their device
+marker too, and can delete what was imported from the volume once its library copy reads
+back the same. A rule keyed by label applies to every volume with that label, so with
+delete_after_import on, key it by marker id or UUID.";
+
+/// A new file, waiting for its size to settle
+struct Pending {
+    inbox: usize,
+    size: Option<u64>,
+    changed_at: Instant,
+}
+
+struct Watch<'a> {
+    conn: &'a Connection,
+    config: &'a Config,
+    storage: Box<dyn StorageBackend>,
+    destination: PathBuf,
+    /// The folder the library is exported to, unless it's on a remote storage
+    library: Option<PathBuf>,
+    inboxes: Vec<PathBuf>,
+    settle: Duration,
+    pending: HashMap<PathBuf, Pending>,
+    /// The batch being ingested or the card being imported, marked cancelled if watch is
+    /// stopped halfway
+    session_id: Arc<Mutex<Option<i64>>>,
+    /// Mounted as of the last check, so only volumes mounted since are looked at
+    mounts: HashSet<PathBuf>,
+}
+
+pub fn run(conn: &Connection, args: &[String], config: &Config) -> io::Result<()> {
+    let mut settle_seconds = config.watch.settle_seconds;
+    let mut inboxes = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--settle" if !args.as_slice().is_empty() => match args.next().unwrap().parse() {
+                Ok(seconds) => settle_seconds = seconds,
+                Err(_) => {
+                    println!("{}", USAGE);
+                    return Ok(());
+                }
+            },
+            _ if !arg.starts_with("--") => inboxes.push(arg.clone()),
+            _ => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+        }
+    }
+
+    if inboxes.is_empty() {
+        inboxes = config.watch.inboxes.clone();
+    }
+
+    let watches_mounts =
+        config.watch.on_mount != MountAction::Ignore || !config.watch.devices.is_empty();
+
+    if inboxes.is_empty() && !watches_mounts {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // Event paths are absolute
+    let inboxes = inboxes
+        .iter()
+        .map(fs::canonicalize)
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let destination = PathBuf::from(format!("{}/final_export", crate::ROOT_PROJECT_PATH));
+
+    let storage: Box<dyn StorageBackend> = match config.export.storage.as_str() {
+        "" => Box::new(LocalBackend::new(&destination)),
+        name => open_backend(name, config)?,
+    };
+
+    let library = match config.export.storage.as_str() {
+        "" => Some(destination.clone()),
+        name => match config.storage.get(name) {
+            Some(StorageConfig::Local { path }) => Some(PathBuf::from(path)),
+            _ => None,
+        },
+    };
+
+    let session_id = Arc::new(Mutex::new(None));
+    let session_id_for_handler = session_id.clone();
+
+    ctrlc::set_handler(move || {
+        println!("\nReceived interrupt signal. Stopping watch...");
+
+        if let Ok(session_id_guard) = session_id_for_handler.lock()
+            && let Some(session_id) = *session_id_guard
+        {
+            match Database::new(crate::database_path()) {
+                Ok(db) => {
+                    match operations::update_backup_session_cancelled(db.conn(), session_id) {
+                        Ok(()) => println!("Backup session {} marked as cancelled.", session_id),
+                        Err(e) => eprintln!("Error updating backup session to cancelled: {}", e),
+                    }
+                }
+                Err(_) => eprintln!("Error connecting to database for cleanup."),
+            }
+        }
+
+        std::process::exit(130);
+    })
+    .map_err(io::Error::other)?;
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+
+    for inbox in &inboxes {
+        watcher
+            .watch(inbox, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+        println!("Watching {:?}", inbox);
+    }
+
+    let mut watch = Watch {
+        conn,
+        config,
+        storage,
+        destination,
+        library,
+        inboxes,
+        settle: Duration::from_secs(settle_seconds),
+        pending: HashMap::new(),
+        session_id,
+        mounts: mount_points().into_iter().collect(),
+    };
+
+    if watches_mounts {
+        println!("Watching for camera cards being mounted");
+    }
+
+    pick_up_existing(&mut watch)?;
+
+    let mut checked_at = Instant::now();
+
+    loop {
+        match events.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        track(&mut watch, &path);
+                    }
+                }
+            }
+            Ok(Err(e)) => println!("Error watching inboxes; Error : {:?}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        if checked_at.elapsed() >= Duration::from_secs(1) {
+            ingest_settled(&mut watch)?;
+
+            if watches_mounts {
+                check_mounts(&mut watch);
+            }
+
+            checked_at = Instant::now();
+        }
+    }
+}
+
+/// Queues the files that landed in the inboxes while watch wasn't running
+fn pick_up_existing(watch: &mut Watch) -> io::Result<()> {
+    for inbox in watch.inboxes.clone() {
+        let device = register_device(watch.conn, &inbox)?;
+
+        let mut files = Vec::new();
+        collect_media_files(&inbox, &mut files)?;
+
+        for path in files {
+            let file_size = fs::metadata(&path)?.len();
+
+            let ingested = operations::inbox_file_ingested(
+                watch.conn,
+                device.id,
+                &device.relative_path(&path),
+                file_size as i64,
+            )
+            .map_err(io::Error::other)?;
+
+            if !ingested {
+                track(watch, &path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Restarts the settle time of a new or changed photo or video, or of everything in a
+/// folder moved into an inbox
+fn track(watch: &mut Watch, path: &Path) {
+    if path.is_dir() {
+        let mut files = Vec::new();
+        if collect_media_files(path, &mut files).is_ok() {
+            for file in files {
+                track(watch, &file);
+            }
+        }
+        return;
+    }
+
+    if !FileType::from_path(path).is_some_and(|t| t.is_image() || t.is_video()) {
+        return;
+    }
+
+    let Some(inbox) = watch
+        .inboxes
+        .iter()
+        .position(|inbox| path.starts_with(inbox))
+    else {
+        return;
+    };
+
+    watch
+        .pending
+        .entry(path.to_path_buf())
+        .and_modify(|pending| pending.changed_at = Instant::now())
+        .or_insert_with(|| Pending {
+            inbox,
+            size: None,
+            changed_at: Instant::now(),
+        });
+}
+
+/// Ingests the new files of every inbox none of whose new files is still changing, so a
+/// card copied into an inbox is ingested as one batch once the copy is done
+fn ingest_settled(watch: &mut Watch) -> io::Result<()> {
+    let now = Instant::now();
+
+    watch.pending.retain(|path, pending| {
+        let Ok(metadata) = fs::metadata(path) else {
+            // Moved away or deleted before it settled
+            return false;
+        };
+
+        if pending.size != Some(metadata.len()) {
+            pending.size = Some(metadata.len());
+            pending.changed_at = now;
+        }
+
+        true
+    });
+
+    for inbox in 0..watch.inboxes.len() {
+        let mut settled = true;
+        let mut batch = Vec::new();
+
+        for (path, pending) in &watch.pending {
+            if pending.inbox == inbox {
+                settled &= now.duration_since(pending.changed_at) >= watch.settle;
+                batch.push(path.clone());
+            }
+        }
+
+        if batch.is_empty() || !settled {
+            continue;
+        }
+
+        for path in &batch {
+            watch.pending.remove(path);
+        }
+
+        batch.sort();
+
+        if let Err(e) = ingest(watch, inbox, &batch) {
+            println!(
+                "Error ingesting batch, trying again once it settles; Path : {:?}; Error : {:?}",
+                watch.inboxes[inbox].to_str(),
+                e
+            );
+
+            for path in batch {
+                track(watch, &path);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What a batch came to
+struct Ingested {
+    copied: i64,
+    skipped: i64,
+    bytes_copied: i64,
+    undated: usize,
+}
+
+/// Scan, dedup and export for one batch, recorded as a backup session that is completed
+/// (with the error, if any) whatever happens
+fn ingest(watch: &Watch, inbox: usize, batch: &[PathBuf]) -> io::Result<()> {
+    let inbox = &watch.inboxes[inbox];
+
+    println!("{} new file(s) in {:?}", batch.len(), inbox);
+
+    let session_id = operations::new_backup_session(
+        watch.conn,
+        inbox.to_str().unwrap_or(""),
+        &watch.storage.name(),
+    )
+    .map_err(io::Error::other)?;
+
+    *watch.session_id.lock().unwrap() = Some(session_id);
+
+    let ingested = export_batch(watch, session_id, inbox, batch);
+
+    let recorded = match &ingested {
+        Ok(ingested) => operations::update_backup_session_counts(
+            watch.conn,
+            session_id,
+            batch.len() as i64,
+            ingested.copied,
+            ingested.skipped,
+            ingested.bytes_copied,
+            (ingested.undated > 0)
+                .then(|| {
+                    format!(
+                        "{} file(s) have no date taken and were not exported",
+                        ingested.undated
+                    )
+                })
+                .as_deref(),
+        ),
+        Err(e) => operations::update_backup_session_counts(
+            watch.conn,
+            session_id,
+            batch.len() as i64,
+            0,
+            0,
+            0,
+            Some(&e.to_string()),
+        ),
+    }
+    .and_then(|()| operations::update_backup_session_completed(watch.conn, session_id));
+
+    *watch.session_id.lock().unwrap() = None;
+
+    let ingested = ingested?;
+    recorded.map_err(io::Error::other)?;
+
+    println!(
+        "Session {}: {} file(s) scanned, {} exported, {} skipped",
+        session_id,
+        batch.len(),
+        ingested.copied,
+        ingested.skipped
+    );
+
+    Ok(())
+}
+
+/// Exports the best copy of every dated file of the batch the library doesn't hold yet.
+/// Only once that succeeded are the files recorded as taken in, so a batch that failed
+/// is picked up again.
+fn export_batch(
+    watch: &Watch,
+    session_id: i64,
+    inbox: &Path,
+    batch: &[PathBuf],
+) -> io::Result<Ingested> {
+    let (media_items, undated): (Vec<_>, Vec<_>) = scan_files(watch.conn, batch)?
+        .into_iter()
+        .partition(|media| {
+            media
+                .exif_data
+                .as_ref()
+                .and_then(|e| e.date_taken.as_deref())
+                .is_some_and(|d| NaiveDateTime::parse_from_str(d, "%Y-%m-%d %H:%M:%S").is_ok())
+        });
+
+    for media in &undated {
+        println!(
+            "No date taken, can't place it in the library: {:?}",
+            media.file_path
+        );
+    }
+
+    link_edited_to_originals(watch.conn, &media_items).unwrap_or_else(|e| {
+        println!("Error linking edited exports to originals; Error : {:?}", e);
+    });
+
+    let dated = media_items
+        .iter()
+        .map(|media| media.file_path.clone())
+        .collect::<Vec<_>>();
+
+    let duplicates = find_duplicates(media_items, &watch.destination, watch.config)?;
+
+    operations::replace_duplicate_groups(watch.conn, session_id, &duplicates).unwrap_or_else(|e| {
+        println!(
+            "Error storing duplicate groups to database; Session : {:?}; Error : {:?}",
+            session_id, e
+        );
+    });
+
+    // Extra copies within the batch are never exported
+    let mut skipped = duplicates.iter().map(|d| d.count as i64 - 1).sum::<i64>();
+    let mut to_export = Vec::new();
+    let mut exported_keys = Vec::new();
+
+    for duplicate in duplicates {
+        let key = key_under(&watch.destination, &duplicate.final_path)?;
+
+        let existing = match &watch.library {
+            Some(library) => {
+                library_copy(watch.conn, &duplicate.files[0], &duplicate.hash, library)?
+            }
+            None => None,
+        };
+
+        if let Some(existing) = existing {
+            println!(
+                "Already in the library as {:?}: {:?}",
+                existing, duplicate.files[0]
+            );
+            skipped += 1;
+        } else if watch.storage.exists(&key)?
+            && watch.storage.content_hash(&key)? == calculate_full_hash(&duplicate.files[0])?
+        {
+            println!("Already in the library: {:?}", duplicate.files[0]);
+            skipped += 1;
+        } else {
+            exported_keys.push((duplicate.hash.clone(), key, duplicate.file_size));
+            to_export.push(duplicate);
+        }
+    }
+
+    let copied = to_export.len() as i64;
+    let bytes_copied = to_export.iter().map(|d| d.file_size as i64).sum::<i64>();
+
+    export_images_to_new_destination(watch.storage.as_ref(), &watch.destination, to_export)?;
+
+    // So later batches find them as library copies
+    if let Some(library) = &watch.library {
+        let device = register_device(watch.conn, library)?;
+
+        for (hash, key, file_size) in exported_keys {
+            operations::upsert_file_location(
+                watch.conn,
+                &hash,
+                device.id,
+                &device.relative_path(&library.join(key)),
+                file_size as i64,
+            )
+            .map_err(io::Error::other)?;
+        }
+    }
+
+    // So a restart doesn't pick them up again
+    let device = register_device(watch.conn, inbox)?;
+
+    for path in dated {
+        operations::upsert_inbox_file(
+            watch.conn,
+            device.id,
+            &device.relative_path(&path),
+            fs::metadata(&path)?.len() as i64,
+            session_id,
+        )
+        .map_err(io::Error::other)?;
+    }
+
+    Ok(Ingested {
+        copied,
+        skipped,
+        bytes_copied,
+        undated: undated.len(),
+    })
+}
+
+/// Looks at every volume mounted since the last check
+fn check_mounts(watch: &mut Watch) {
+    let mounted = mount_points().into_iter().collect::<HashSet<_>>();
+
+    let mut new_mounts = mounted
+        .difference(&watch.mounts)
+        .cloned()
+        .collect::<Vec<_>>();
+    new_mounts.sort();
+
+    watch.mounts = mounted;
+
+    for mount_point in new_mounts {
+        if let Err(e) = on_mount(watch, &mount_point) {
+            println!(
+                "Error importing volume; Path : {:?}; Error : {:?}",
+                mount_point.to_str(),
+                e
+            );
+        }
+    }
+}
+
+/// Imports a newly mounted camera card or known drive, as its rule or [watch] on_mount
+/// says
+fn on_mount(watch: &Watch, mount_point: &Path) -> io::Result<()> {
+    let has_dcim = mount_point.join("DCIM").is_dir();
+    let volume = identify_volume(mount_point);
+
+    let known = volume.marker_id.is_some() && find_device(watch.conn, mount_point)?.is_some();
+
+    if !has_dcim && !known {
+        return Ok(());
+    }
+
+    // Most specific first: many cards share a label, a marker id is one volume's own
+    let rule = [
+        volume.marker_id.as_ref(),
+        volume.volume_uuid.as_ref(),
+        Some(&volume.label),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(|key| watch.config.watch.devices.get(key));
+
+    let action = match rule.and_then(|rule| rule.on_mount) {
+        Some(action) => action,
+        None if has_dcim => watch.config.watch.on_mount,
+        // A library drive, say, which has nothing to import
+        None => return Ok(()),
+    };
+
+    match action {
+        MountAction::Ignore => {
+            println!(
+                "{} mounted at {:?}, leaving it alone",
+                volume.label, mount_point
+            );
+            return Ok(());
+        }
+        MountAction::Ask if !io::stdin().is_terminal() => {
+            println!(
+                "{} mounted at {:?}, not importing it as there is no one to ask; add a rule for it to [watch.devices] to import it",
+                volume.label, mount_point
+            );
+            return Ok(());
+        }
+        MountAction::Ask => {
+            let question = format!(
+                "{} mounted at {:?}. Import it? [y/N] ",
+                volume.label, mount_point
+            );
+
+            if !confirm(&question)? {
+                return Ok(());
+            }
+        }
+        MountAction::Import => {
+            println!(
+                "{} mounted at {:?}, importing it",
+                volume.label, mount_point
+            )
+        }
+    }
+
+    let library = match watch.config.watch.import_library.as_str() {
+        "" => format!("{}/final_export", crate::ROOT_PROJECT_PATH),
+        library => library.to_string(),
+    };
+
+    let session_id = import_card(
+        watch.conn,
+        mount_point,
+        &library,
+        &watch.config.layout,
+        &watch.session_id,
+    )?;
+
+    if rule.is_some_and(|rule| rule.delete_after_import) {
+        delete_imported(watch.conn, mount_point, session_id)?;
+    }
+
+    Ok(())
+}
+
+/// Deletes what an import session took off a volume, each file only once its library
+/// copy reads back the same
+fn delete_imported(conn: &Connection, volume_root: &Path, session_id: i64) -> io::Result<()> {
+    let (mut deleted, mut kept) = (0, 0);
+
+    for file in
+        operations::imported_files_for_session(conn, session_id).map_err(io::Error::other)?
+    {
+        let path = volume_root.join(&file.relative_path);
+        let library_copy = Path::new(&file.destination_path);
+
+        if !path.is_file() {
+            continue;
+        }
+
+        if library_copy.is_file()
+            && calculate_full_hash(library_copy)? == calculate_full_hash(&path)?
+        {
+            fs::remove_file(&path)?;
+            deleted += 1;
+        } else {
+            println!("Kept {:?}, its library copy doesn't match", path);
+            kept += 1;
+        }
+    }
+
+    println!(
+        "Deleted {} imported file(s) from {:?}, kept {}",
+        deleted, volume_root, kept
+    );
+
+    Ok(())
+}
+
+fn confirm(question: &str) -> io::Result<bool> {
+    print!("{}", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
@@ -0,0 +1,301 @@
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Settings read from `.photo_app_rs/config.toml`. Every field has a default,
+/// so the file (and any section in it) is optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub layout: LayoutConfig,
+    pub near_duplicates: NearDuplicatesConfig,
+    pub duplicates: DuplicatesConfig,
+    pub cleanup: CleanupConfig,
+    pub parity: ParityConfig,
+    pub s3: S3Config,
+    pub export: ExportConfig,
+    pub watch: WatchConfig,
+    /// Named backends, `[storage.<name>]`, that export and cloud-backup can write to
+    pub storage: HashMap<String, StorageConfig>,
+}
+
+/// Folder names used inside each `[year]/[month]/[day]` export folder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub raw_folder: String,
+    pub original_folder: String,
+    pub edited_folder: String,
+    pub video_folder: String,
+    /// Frames of a panorama go to `<panorama_folder>/<first frame name>/`
+    pub panorama_folder: String,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            raw_folder: "raw".to_string(),
+            original_folder: "image".to_string(),
+            edited_folder: "edited".to_string(),
+            video_folder: "video".to_string(),
+            panorama_folder: "panorama".to_string(),
+        }
+    }
+}
+
+/// How different two perceptual hashes may be and still count as the same picture
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NearDuplicatesConfig {
+    /// Max differing bits out of 64 between two image dHashes
+    pub image_threshold: u32,
+    /// Max mean differing bits per sampled frame between two videos of matching duration
+    pub video_threshold: u32,
+}
+
+impl Default for NearDuplicatesConfig {
+    fn default() -> Self {
+        NearDuplicatesConfig {
+            image_threshold: 8,
+            video_threshold: 10,
+        }
+    }
+}
+
+/// Which copy of a duplicate group is kept (exported, and used for EXIF)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DuplicatesConfig {
+    /// Rules tried in order, the first one that tells two copies apart decides
+    pub ranking: Vec<RankingRule>,
+    /// Folder names that mean "deleted", matched case-insensitively against each path component
+    pub trash_folders: Vec<String>,
+    /// Folder names that mean "straight off the camera"
+    pub original_folders: Vec<String>,
+}
+
+impl Default for DuplicatesConfig {
+    fn default() -> Self {
+        DuplicatesConfig {
+            ranking: vec![
+                RankingRule::NotInTrash,
+                RankingRule::RawOverJpeg,
+                RankingRule::OriginalFolder,
+                RankingRule::Resolution,
+                RankingRule::Metadata,
+                RankingRule::OldestMtime,
+            ],
+            trash_folders: [
+                ".Trash",
+                ".Trashes",
+                "$RECYCLE.BIN",
+                "Trash",
+                "Recently Deleted",
+            ]
+            .map(String::from)
+            .to_vec(),
+            original_folders: ["DCIM", "Camera", "Originals"].map(String::from).to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RankingRule {
+    NotInTrash,
+    RawOverJpeg,
+    OriginalFolder,
+    Resolution,
+    Metadata,
+    OldestMtime,
+}
+
+/// Where `cleanup` moves the extra copies and how long they stay there
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CleanupConfig {
+    /// One sub folder per cleanup session is created in here
+    pub quarantine_folder: String,
+    /// `purge` only deletes sessions older than this
+    pub grace_period_days: u64,
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        CleanupConfig {
+            quarantine_folder: "./.photo_app_rs/quarantine".to_string(),
+            grace_period_days: 30,
+        }
+    }
+}
+
+/// Recovery data `parity` writes to each year folder
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ParityConfig {
+    /// Parity written per group of slices, as a share of the group, and so how much of
+    /// each group can be lost and rebuilt. `parity --redundancy` overrides it
+    pub redundancy_percent: u32,
+    /// Damage anywhere in a slice costs the whole slice
+    pub slice_size_kb: u64,
+}
+
+impl Default for ParityConfig {
+    fn default() -> Self {
+        ParityConfig {
+            redundancy_percent: 10,
+            slice_size_kb: 64,
+        }
+    }
+}
+
+/// S3-compatible bucket `cloud-backup` uploads to (AWS, MinIO, Backblaze B2, ...)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct S3Config {
+    /// `https://s3.eu-west-1.amazonaws.com`, `http://localhost:9000`, ... Empty means no cloud backup
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Objects are stored under `<prefix>/objects/`
+    pub prefix: String,
+    /// Read from AWS_ACCESS_KEY_ID / AWS_SECRET_ACCESS_KEY when left empty
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Files bigger than this are uploaded in parts
+    pub multipart_threshold_mb: u64,
+    /// At least 5, S3 rejects smaller parts
+    pub part_size_mb: u64,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: String::new(),
+            region: "us-east-1".to_string(),
+            bucket: String::new(),
+            prefix: "photo_app_rs".to_string(),
+            access_key_id: String::new(),
+            secret_access_key: String::new(),
+            multipart_threshold_mb: 64,
+            part_size_mb: 16,
+        }
+    }
+}
+
+/// Where the organised library is written
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Name of a `[storage.<name>]` backend. Empty means the local `final_export` folder
+    pub storage: String,
+}
+
+/// Inbox folders the `watch` daemon ingests from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WatchConfig {
+    /// `watch <inbox>...` overrides them
+    pub inboxes: Vec<String>,
+    /// A new file is picked up once its size hasn't changed for this long, so files
+    /// still being copied in are left alone
+    pub settle_seconds: u64,
+    /// What happens when a volume with a DCIM folder is mounted, unless `devices` has a
+    /// rule for it
+    pub on_mount: MountAction,
+    /// Where mounted cards are imported to. Empty means `<root>/final_export`
+    pub import_library: String,
+    /// Rules for particular volumes, by device marker id, filesystem UUID or label, looked
+    /// up in that order
+    pub devices: HashMap<String, DeviceRule>,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            inboxes: Vec::new(),
+            settle_seconds: 30,
+            on_mount: MountAction::Ask,
+            import_library: String::new(),
+            devices: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountAction {
+    Import,
+    /// Asks on the terminal `watch` runs in, and leaves the volume alone without one
+    Ask,
+    Ignore,
+}
+
+/// `[watch.devices.<label, UUID or marker id>]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DeviceRule {
+    /// Also applies to volumes without a DCIM folder, which are otherwise left alone
+    pub on_mount: Option<MountAction>,
+    /// Deletes photos and videos from the volume once their library copy reads back the
+    /// same. Off unless set
+    pub delete_after_import: bool,
+}
+
+/// One storage backend, picked by `kind`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StorageConfig {
+    Local { path: String },
+    Sftp(SftpConfig),
+    Webdav(WebDavConfig),
+    S3(S3Config),
+    Encrypted(EncryptedConfig),
+}
+
+/// A folder on an SSH server, reached with the system `sftp` client so keys and
+/// `~/.ssh/config` work as they do in a terminal
+#[derive(Debug, Clone, Deserialize)]
+pub struct SftpConfig {
+    /// `user@host`, or a host alias from `~/.ssh/config`
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Folder on the server everything is stored under
+    pub path: String,
+}
+
+/// A WebDAV collection (Nextcloud, a NAS, ...)
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebDavConfig {
+    /// e.g. `https://cloud.example.com/remote.php/dav/files/me/photos`
+    pub url: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+/// Another `[storage.<name>]` backend, with everything encrypted before it is sent
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncryptedConfig {
+    /// Name of the backend that holds the encrypted data
+    pub storage: String,
+    /// File holding the passphrase. PHOTO_APP_RS_PASSPHRASE wins over it, and
+    /// without either the passphrase is asked for
+    #[serde(default)]
+    pub passphrase_file: String,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let contents = fs::read_to_string(path)?;
+
+        toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
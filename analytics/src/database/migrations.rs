@@ -1,9 +1,71 @@
 use rusqlite::Connection;
 
-const MIGRATIONS: &[(&str, &str)] = &[(
-    "001_initial",
-    include_str!("migrations/001_initial_schema.sql"),
-)];
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "001_initial",
+        include_str!("migrations/001_initial_schema.sql"),
+    ),
+    (
+        "002_xmp_sidecars",
+        include_str!("migrations/002_xmp_sidecars.sql"),
+    ),
+    ("003_tags", include_str!("migrations/003_tags.sql")),
+    ("004_assets", include_str!("migrations/004_assets.sql")),
+    (
+        "005_media_relations",
+        include_str!("migrations/005_media_relations.sql"),
+    ),
+    (
+        "006_live_photos",
+        include_str!("migrations/006_live_photos.sql"),
+    ),
+    (
+        "007_near_duplicates",
+        include_str!("migrations/007_near_duplicates.sql"),
+    ),
+    (
+        "008_video_fingerprints",
+        include_str!("migrations/008_video_fingerprints.sql"),
+    ),
+    ("009_cleanup", include_str!("migrations/009_cleanup.sql")),
+    (
+        "010_duplicate_group_members",
+        include_str!("migrations/010_duplicate_group_members.sql"),
+    ),
+    ("011_stacks", include_str!("migrations/011_stacks.sql")),
+    (
+        "012_file_locations",
+        include_str!("migrations/012_file_locations.sql"),
+    ),
+    (
+        "013_sd_import",
+        include_str!("migrations/013_sd_import.sql"),
+    ),
+    ("014_devices", include_str!("migrations/014_devices.sql")),
+    ("015_sync", include_str!("migrations/015_sync.sql")),
+    (
+        "016_remote_objects",
+        include_str!("migrations/016_remote_objects.sql"),
+    ),
+    ("017_scrub", include_str!("migrations/017_scrub.sql")),
+    ("018_parity", include_str!("migrations/018_parity.sql")),
+    (
+        "019_panorama_pairs",
+        include_str!("migrations/019_panorama_pairs.sql"),
+    ),
+    (
+        "020_inbox_files",
+        include_str!("migrations/020_inbox_files.sql"),
+    ),
+    (
+        "021_stack_tags",
+        include_str!("migrations/021_stack_tags.sql"),
+    ),
+    (
+        "022_sync_failures",
+        include_str!("migrations/022_sync_failures.sql"),
+    ),
+];
 
 pub fn run_migrations(conn: &mut Connection) -> rusqlite::Result<()> {
     conn.execute_batch(
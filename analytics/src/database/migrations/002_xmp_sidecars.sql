@@ -0,0 +1,8 @@
+-- ============================================================
+-- media_files: XMP sidecar data (Lightroom / darktable)
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN sidecar_path TEXT;
+ALTER TABLE media_files ADD COLUMN rating INTEGER;               -- xmp:Rating, -1 (rejected) to 5
+ALTER TABLE media_files ADD COLUMN color_label TEXT;             -- xmp:Label
+ALTER TABLE media_files ADD COLUMN title TEXT;                   -- dc:title
+ALTER TABLE media_files ADD COLUMN keywords TEXT;                -- dc:subject, ';' separated
@@ -0,0 +1,45 @@
+-- ============================================================
+-- tags
+-- Hierarchical: `trips/2025/japan` is stored with its full path
+-- as name, and points at `trips/2025` through parent_id.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS tags (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+    parent_id INTEGER,
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    FOREIGN KEY (parent_id)
+        REFERENCES tags(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_tags_parent_id
+    ON tags(parent_id);
+
+-- ============================================================
+-- media_tags
+-- ============================================================
+CREATE TABLE IF NOT EXISTS media_tags (
+    media_file_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+
+    -- 'metadata' = ingested from IPTC / XMP, 'manual' = added from the CLI
+    source TEXT NOT NULL CHECK (source IN ('metadata', 'manual')),
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (media_file_id, tag_id),
+
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (tag_id)
+        REFERENCES tags(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_media_tags_tag_id
+    ON media_tags(tag_id);
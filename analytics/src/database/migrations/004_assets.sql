@@ -0,0 +1,24 @@
+-- ============================================================
+-- assets
+-- One logical photo/video made of several renditions,
+-- e.g. DSC0001.ARW + DSC0001.JPG shot at the same time.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS assets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    asset_key TEXT NOT NULL UNIQUE,              -- lowercased path without extension + capture time
+    kind TEXT NOT NULL,                          -- 'raw_jpeg', 'photo_video', 'renditions'
+    date_taken TEXT,
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- ============================================================
+-- media_files: rendition of an asset
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN asset_id INTEGER
+    REFERENCES assets(id) ON DELETE SET NULL;
+ALTER TABLE media_files ADD COLUMN rendition TEXT;    -- 'raw', 'original', 'edited', 'video'
+
+CREATE INDEX IF NOT EXISTS idx_media_files_asset_id
+    ON media_files(asset_id);
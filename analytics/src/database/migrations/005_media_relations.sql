@@ -0,0 +1,41 @@
+-- ============================================================
+-- media_files: identifiers used to link edited exports to originals
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN date_time_original TEXT;
+ALTER TABLE media_files ADD COLUMN camera_serial TEXT;
+ALTER TABLE media_files ADD COLUMN image_unique_id TEXT;
+ALTER TABLE media_files ADD COLUMN derived_from_name TEXT;       -- original file name from XMP
+
+CREATE INDEX IF NOT EXISTS idx_media_files_image_unique_id
+    ON media_files(image_unique_id);
+
+CREATE INDEX IF NOT EXISTS idx_media_files_date_time_original
+    ON media_files(date_time_original);
+
+-- ============================================================
+-- media_relations
+-- media_file_id <relation> related_media_file_id,
+-- e.g. edited JPEG 'derived_from' the ARW it was exported from
+-- ============================================================
+CREATE TABLE IF NOT EXISTS media_relations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    media_file_id INTEGER NOT NULL,
+    related_media_file_id INTEGER NOT NULL,
+    relation TEXT NOT NULL CHECK (relation IN ('derived_from')),
+    matched_by TEXT NOT NULL,                    -- which identifier matched
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (media_file_id, related_media_file_id, relation),
+
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (related_media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_media_relations_related_media_file_id
+    ON media_relations(related_media_file_id);
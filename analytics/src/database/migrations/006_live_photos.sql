@@ -0,0 +1,8 @@
+-- ============================================================
+-- media_files: Live Photo / motion photo markers
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN content_identifier TEXT;     -- Apple ContentIdentifier (HEIC + MOV), assets.kind 'live_photo'
+ALTER TABLE media_files ADD COLUMN motion_photo INTEGER NOT NULL DEFAULT 0;  -- Android, assets.kind 'motion_photo'
+
+CREATE INDEX IF NOT EXISTS idx_media_files_content_identifier
+    ON media_files(content_identifier);
@@ -0,0 +1,36 @@
+-- ============================================================
+-- media_files: perceptual hash (64 bit dHash, stored as signed INTEGER)
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN phash INTEGER;
+
+-- ============================================================
+-- similarity_groups
+-- Clusters of files that look the same without being byte identical.
+-- Regenerated per kind on every run.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS similarity_groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    kind TEXT NOT NULL,                          -- 'image_phash'
+    threshold INTEGER NOT NULL,                  -- max distance used to build the group
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_similarity_groups_kind
+    ON similarity_groups(kind);
+
+CREATE TABLE IF NOT EXISTS similarity_group_members (
+    group_id INTEGER NOT NULL,
+    media_file_id INTEGER NOT NULL,
+    distance INTEGER NOT NULL,                   -- from the first member of the group
+
+    PRIMARY KEY (group_id, media_file_id),
+
+    FOREIGN KEY (group_id)
+        REFERENCES similarity_groups(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE
+);
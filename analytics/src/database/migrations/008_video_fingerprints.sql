@@ -0,0 +1,17 @@
+-- ============================================================
+-- video_fingerprints
+-- dHash of frames sampled at fixed fractions of the duration,
+-- grouped as similarity_groups.kind = 'video_fingerprint'
+-- ============================================================
+CREATE TABLE IF NOT EXISTS video_fingerprints (
+    media_file_id INTEGER PRIMARY KEY,
+
+    duration_seconds REAL NOT NULL,
+    frame_hashes TEXT NOT NULL,                  -- comma separated hex dHashes
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,53 @@
+-- ============================================================
+-- cleanup_sessions
+-- One `cleanup` run: every extra copy it removed was moved into
+-- quarantine, where it stays until `undo` or `purge`
+-- ============================================================
+CREATE TABLE IF NOT EXISTS cleanup_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    source_path TEXT NOT NULL,
+    quarantine_path TEXT NOT NULL,              -- files of session N are under <quarantine_path>/<N>
+
+    -- Stats
+    files_moved INTEGER NOT NULL DEFAULT 0,
+    bytes_moved INTEGER NOT NULL DEFAULT 0,
+
+    -- Timing
+    started_at INTEGER NOT NULL,
+    completed_at INTEGER,
+
+    status TEXT NOT NULL
+        CHECK (status IN ('running', 'completed', 'undone', 'purged')),
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_cleanup_sessions_status
+    ON cleanup_sessions(status);
+
+-- ============================================================
+-- quarantine_moves
+-- Every file moved by a cleanup session, so it can be put back exactly
+-- ============================================================
+CREATE TABLE IF NOT EXISTS quarantine_moves (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    session_id INTEGER NOT NULL,
+    hash TEXT NOT NULL,                          -- of the duplicate group (sidecars share their media's hash)
+    original_path TEXT NOT NULL,
+    quarantine_path TEXT NOT NULL,
+    kept_path TEXT NOT NULL,                     -- the copy that stayed in place
+    file_size_bytes INTEGER NOT NULL,
+
+    moved_at INTEGER NOT NULL,
+    restored_at INTEGER,
+    purged_at INTEGER,
+
+    FOREIGN KEY (session_id)
+        REFERENCES cleanup_sessions(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantine_moves_session_id
+    ON quarantine_moves(session_id);
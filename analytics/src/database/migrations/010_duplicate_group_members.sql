@@ -0,0 +1,59 @@
+-- ============================================================
+-- duplicate_groups (rebuilt)
+-- One row per hash with more than one copy, per backup session.
+-- The old table had a row for every hash (singletons included)
+-- appended on every run, so its contents are dropped.
+-- Regenerated as a whole for a session on every run.
+-- ============================================================
+DROP TABLE IF EXISTS duplicate_groups;
+
+CREATE TABLE IF NOT EXISTS duplicate_groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    session_id INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    media_file_id INTEGER,                       -- the cataloged row for this hash, if any
+
+    kept_path TEXT NOT NULL,
+    keep_reason TEXT NOT NULL,
+
+    total_copies INTEGER NOT NULL CHECK (total_copies > 1),
+    file_size_bytes INTEGER NOT NULL,
+    total_size_bytes INTEGER NOT NULL,
+    wasted_space_bytes INTEGER NOT NULL,
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (session_id, hash),
+
+    FOREIGN KEY (session_id)
+        REFERENCES backup_sessions(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_duplicate_groups_session_id
+    ON duplicate_groups(session_id);
+
+-- ============================================================
+-- duplicate_group_members
+-- Every path holding a copy. Paths rather than media_files ids,
+-- since media_files keeps one row per hash.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS duplicate_group_members (
+    group_id INTEGER NOT NULL,
+    path TEXT NOT NULL,
+    folder TEXT NOT NULL,                        -- parent folder of path, for per folder totals
+    is_kept INTEGER NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (group_id, path),
+
+    FOREIGN KEY (group_id)
+        REFERENCES duplicate_groups(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_duplicate_group_members_folder
+    ON duplicate_group_members(folder);
@@ -0,0 +1,47 @@
+-- ============================================================
+-- media_files: EXIF used to spot burst / bracket sequences
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN exposure_bias TEXT;          -- EV, e.g. "-0.7"
+ALTER TABLE media_files ADD COLUMN exposure_mode TEXT;          -- "auto exposure", "manual exposure", "auto bracket"
+ALTER TABLE media_files ADD COLUMN subject_distance TEXT;       -- metres, "unknown" or "infinity"
+ALTER TABLE media_files ADD COLUMN sub_sec_time TEXT;           -- SubSecTimeOriginal, fraction of date_taken's second
+
+-- ============================================================
+-- stacks
+-- Runs of frames shot in quick succession by one camera
+-- ============================================================
+CREATE TABLE IF NOT EXISTS stacks (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    stack_key TEXT NOT NULL UNIQUE,              -- hash of the first frame
+    kind TEXT NOT NULL,                          -- 'burst', 'ae_bracket', 'focus_bracket'
+    camera TEXT,
+    date_taken TEXT,                             -- of the first frame
+    frame_count INTEGER NOT NULL,
+
+    pick_media_file_id INTEGER,                  -- the frame to keep / show for the stack
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    FOREIGN KEY (pick_media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_stacks_kind
+    ON stacks(kind);
+
+CREATE TABLE IF NOT EXISTS stack_members (
+    stack_id INTEGER NOT NULL,
+    media_file_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,                   -- frame order within the stack, from 0
+
+    PRIMARY KEY (stack_id, media_file_id),
+
+    FOREIGN KEY (stack_id)
+        REFERENCES stacks(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,26 @@
+-- ============================================================
+-- file_locations
+-- Every place a file has been seen, by device. media_files keeps one
+-- row per hash, this keeps all of them, so we know what is backed up
+-- where even when the disk isn't plugged in.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS file_locations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    hash TEXT NOT NULL,
+    device TEXT NOT NULL,                        -- volume label, 'local' for the system disk
+    path TEXT NOT NULL,
+    file_size_bytes INTEGER NOT NULL,
+
+    last_seen_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (device, path)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_locations_hash
+    ON file_locations(hash);
+
+-- Files cataloged before locations were tracked; the device they were on is unknown
+INSERT OR IGNORE INTO file_locations (hash, device, path, file_size_bytes)
+SELECT hash, 'unknown', path, file_size_bytes FROM media_files;
@@ -0,0 +1,48 @@
+-- ============================================================
+-- sd_cards
+-- Camera cards seen by `import`. A card is known by its volume
+-- UUID when the OS gives one, otherwise by its label plus the
+-- DCIM folders on it (100MSDCF, 101MSDCF, ...).
+-- ============================================================
+CREATE TABLE IF NOT EXISTS sd_cards (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    volume_uuid TEXT UNIQUE,                     -- changes when the card is formatted
+    label TEXT NOT NULL,
+    dcim_folders TEXT NOT NULL,                  -- ';' separated, as of the last import
+
+    last_imported_at INTEGER,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_sd_cards_label
+    ON sd_cards(label);
+
+-- ============================================================
+-- imported_files
+-- Every file `import` has copied (or found already in the
+-- library) from a card, so the next import of the same card
+-- only looks at what is new.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS imported_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    card_id INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,
+
+    relative_path TEXT NOT NULL,                 -- path on the card, from its root
+    file_size_bytes INTEGER NOT NULL,
+    modified_at INTEGER NOT NULL,                -- mtime on the card
+    hash TEXT NOT NULL,
+    destination_path TEXT NOT NULL,              -- copy in the library
+
+    imported_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (card_id, relative_path, file_size_bytes, modified_at),
+    FOREIGN KEY (card_id)
+        REFERENCES sd_cards(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (session_id)
+        REFERENCES backup_sessions(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,60 @@
+-- ============================================================
+-- devices
+-- Volumes the catalog has files on. A volume is recognised by
+-- the marker file written to its root, or its filesystem UUID,
+-- so it keeps its identity when mounted somewhere else or
+-- plugged into another host. Paths are stored relative to it.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS devices (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    marker_id TEXT UNIQUE,                       -- id in <root>/.photo_app_rs_device
+    volume_uuid TEXT UNIQUE,                     -- from /dev/disk/by-uuid
+    label TEXT NOT NULL,                         -- volume name, 'local' for the system disk
+    mount_point TEXT,                            -- where it was mounted last time
+
+    last_seen_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- Devices recorded by label only, before this table
+INSERT INTO devices (label)
+SELECT DISTINCT device FROM file_locations;
+
+-- ============================================================
+-- file_locations, keyed by device and path on the device
+-- ============================================================
+CREATE TABLE IF NOT EXISTS file_locations_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    hash TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    relative_path TEXT NOT NULL,                 -- from the device root; absolute for rows recorded before devices
+    file_size_bytes INTEGER NOT NULL,
+
+    last_seen_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (device_id, relative_path),
+    FOREIGN KEY (device_id)
+        REFERENCES devices(id)
+        ON DELETE CASCADE
+);
+
+INSERT INTO file_locations_new (id, hash, device_id, relative_path, file_size_bytes, last_seen_at, created_at)
+SELECT fl.id, fl.hash, d.id, fl.path, fl.file_size_bytes, fl.last_seen_at, fl.created_at
+FROM file_locations fl
+JOIN devices d ON d.label = fl.device;
+
+DROP TABLE file_locations;
+ALTER TABLE file_locations_new RENAME TO file_locations;
+
+CREATE INDEX IF NOT EXISTS idx_file_locations_hash
+    ON file_locations(hash);
+
+-- ============================================================
+-- media_files: device of the cataloged copy
+-- ============================================================
+ALTER TABLE media_files ADD COLUMN device_id INTEGER
+    REFERENCES devices(id) ON DELETE SET NULL;
+ALTER TABLE media_files ADD COLUMN relative_path TEXT;
@@ -0,0 +1,57 @@
+-- ============================================================
+-- sync_sessions
+-- Every `sync` run between library volumes
+-- ============================================================
+CREATE TABLE IF NOT EXISTS sync_sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    libraries TEXT NOT NULL,                     -- library roots, ';' separated, source first for one_way
+    mode TEXT NOT NULL
+        CHECK (mode IN ('two_way', 'one_way')),
+
+    files_copied INTEGER NOT NULL DEFAULT 0,
+    bytes_copied INTEGER NOT NULL DEFAULT 0,
+    conflicts INTEGER NOT NULL DEFAULT 0,
+
+    started_at INTEGER NOT NULL,
+    completed_at INTEGER,
+
+    status TEXT NOT NULL
+        CHECK (status IN ('running', 'completed')),
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- ============================================================
+-- sync_conflicts
+-- Files a sync left alone because the libraries disagree
+-- ============================================================
+CREATE TABLE IF NOT EXISTS sync_conflicts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    session_id INTEGER NOT NULL,
+    relative_path TEXT NOT NULL,                 -- from the library root
+    kind TEXT NOT NULL,                          -- 'media' (different files at one path), 'sidecar' (edited on both sides)
+    details TEXT NOT NULL,
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    FOREIGN KEY (session_id)
+        REFERENCES sync_sessions(id)
+        ON DELETE CASCADE
+);
+
+-- ============================================================
+-- sync_sidecars
+-- Hash every library agreed on for a sidecar at the last sync,
+-- to tell which side edited it since
+-- ============================================================
+CREATE TABLE IF NOT EXISTS sync_sidecars (
+    sync_set TEXT NOT NULL,                      -- '<device id>:<library path on device>' of each library, sorted, ';' separated
+    relative_path TEXT NOT NULL,
+    hash TEXT NOT NULL,
+
+    synced_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (sync_set, relative_path)
+);
@@ -0,0 +1,45 @@
+-- ============================================================
+-- remote_objects
+-- Objects uploaded to a backup target (an S3 bucket), keyed by
+-- the SHA-256 of their content, so a file is uploaded once
+-- however many copies of it there are.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS remote_objects (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    target TEXT NOT NULL,                        -- e.g. 's3://bucket/prefix'
+    object_key TEXT NOT NULL,                    -- objects/<2 hash chars>/<content_hash>
+    content_hash TEXT NOT NULL,                  -- SHA256 of the whole file
+    hash TEXT NOT NULL,                          -- media_files.hash (first 128 KB)
+    file_size_bytes INTEGER NOT NULL,
+
+    uploaded_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (target, object_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_remote_objects_hash
+    ON remote_objects(hash);
+
+-- ============================================================
+-- remote_sources
+-- Which object each backed up file went to. A file whose size
+-- and mtime haven't changed isn't read again on the next run.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS remote_sources (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    target TEXT NOT NULL,
+    device_id INTEGER NOT NULL,
+    relative_path TEXT NOT NULL,                 -- from the device root
+    file_size_bytes INTEGER NOT NULL,
+    modified_at INTEGER NOT NULL,
+    object_key TEXT NOT NULL,
+
+    backed_up_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (target, device_id, relative_path),
+    FOREIGN KEY (device_id)
+        REFERENCES devices(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,8 @@
+-- ============================================================
+-- file_locations: what `scrub` found the last time it read
+-- each copy in full
+-- ============================================================
+ALTER TABLE file_locations ADD COLUMN content_hash TEXT;      -- SHA256 of the whole file, as it should be
+ALTER TABLE file_locations ADD COLUMN verify_status TEXT
+    CHECK (verify_status IN ('ok', 'damaged', 'missing'));
+ALTER TABLE file_locations ADD COLUMN last_verified_at INTEGER; -- last time the copy read back as content_hash
@@ -0,0 +1,44 @@
+-- ============================================================
+-- parity_sets
+-- Recovery data `parity` wrote to a year folder, as
+-- <folder>/.photo_app_rs.parity. Any redundancy_percent of every
+-- group of slices of the folder can be rebuilt from it.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS parity_sets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    device_id INTEGER NOT NULL,
+    folder TEXT NOT NULL,                        -- from the device root
+    file_count INTEGER NOT NULL,
+    data_bytes INTEGER NOT NULL,
+    parity_bytes INTEGER NOT NULL,               -- size of the parity file
+    slice_size INTEGER NOT NULL,
+    redundancy_percent INTEGER NOT NULL,
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (device_id, folder),
+    FOREIGN KEY (device_id)
+        REFERENCES devices(id)
+        ON DELETE CASCADE
+);
+
+-- ============================================================
+-- parity_files
+-- Every file a parity set covers, as it was when the set was
+-- written
+-- ============================================================
+CREATE TABLE IF NOT EXISTS parity_files (
+    set_id INTEGER NOT NULL,
+    relative_path TEXT NOT NULL,                 -- from the device root
+    file_size_bytes INTEGER NOT NULL,
+    content_hash TEXT NOT NULL,                  -- SHA256 of the whole file
+
+    PRIMARY KEY (set_id, relative_path),
+    FOREIGN KEY (set_id)
+        REFERENCES parity_sets(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_parity_files_path
+    ON parity_files(relative_path);
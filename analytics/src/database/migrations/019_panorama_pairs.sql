@@ -0,0 +1,14 @@
+-- ============================================================
+-- panorama_pairs
+-- Neighbouring frames scanning compared for a panorama, by hash, so
+-- a re-scan doesn't decode them again.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS panorama_pairs (
+    a_hash TEXT NOT NULL,                        -- the earlier frame
+    b_hash TEXT NOT NULL,                        -- the frame shot after it
+    overlaps INTEGER NOT NULL,                   -- 1 when b carries on the panorama of a
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (a_hash, b_hash)
+);
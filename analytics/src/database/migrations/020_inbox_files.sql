@@ -0,0 +1,23 @@
+-- ============================================================
+-- inbox_files
+-- Files `watch` took in from an inbox: exported to the library or
+-- found there already. Files whose batch failed, or that have no
+-- date taken, aren't recorded, so watch picks them up again.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS inbox_files (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+
+    device_id INTEGER NOT NULL,
+    relative_path TEXT NOT NULL,                 -- from the device root
+    file_size_bytes INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,                 -- the backup session that took it in
+
+    ingested_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    UNIQUE (device_id, relative_path),
+    FOREIGN KEY (device_id)
+        REFERENCES devices(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (session_id)
+        REFERENCES backup_sessions(id)
+);
@@ -0,0 +1,46 @@
+-- ============================================================
+-- media_tags, with a source for the tags scanning gives the
+-- frames of a stack (`panorama`), so replacing a folder's
+-- stacks leaves keywords from IPTC / XMP alone.
+-- ============================================================
+CREATE TABLE IF NOT EXISTS media_tags_new (
+    media_file_id INTEGER NOT NULL,
+    tag_id INTEGER NOT NULL,
+
+    -- 'metadata' = ingested from IPTC / XMP, 'manual' = added from the CLI,
+    -- 'stack' = added for the stack the file is a frame of
+    source TEXT NOT NULL CHECK (source IN ('metadata', 'manual', 'stack')),
+
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+
+    PRIMARY KEY (media_file_id, tag_id),
+
+    FOREIGN KEY (media_file_id)
+        REFERENCES media_files(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (tag_id)
+        REFERENCES tags(id)
+        ON DELETE CASCADE
+);
+
+INSERT INTO media_tags_new (media_file_id, tag_id, source, created_at)
+SELECT media_file_id, tag_id, source, created_at
+FROM media_tags;
+
+DROP TABLE media_tags;
+ALTER TABLE media_tags_new RENAME TO media_tags;
+
+CREATE INDEX IF NOT EXISTS idx_media_tags_tag_id
+    ON media_tags(tag_id);
+
+-- `panorama` tags on panorama frames were stored as 'metadata' before;
+-- a file that also has the keyword gets it back as 'metadata' on its next scan
+UPDATE media_tags
+   SET source = 'stack'
+ WHERE source = 'metadata'
+   AND tag_id IN (SELECT id FROM tags WHERE name = 'panorama')
+   AND media_file_id IN (
+       SELECT sm.media_file_id
+         FROM stack_members sm
+         JOIN stacks s ON s.id = sm.stack_id
+        WHERE s.kind = 'panorama');
@@ -0,0 +1,5 @@
+-- ============================================================
+-- sync_sessions: files a sync couldn't read or copy. The sync
+-- carries on past them and they are tried again next time.
+-- ============================================================
+ALTER TABLE sync_sessions ADD COLUMN files_failed INTEGER NOT NULL DEFAULT 0;
@@ -16,23 +16,85 @@ pub struct MediaFileRow {
     pub shutter_speed: Option<String>,
     pub focal_length: Option<String>,
     pub software: Option<String>,
+    pub date_time_original: Option<String>,
+    pub camera_serial: Option<String>,
+    pub image_unique_id: Option<String>,
+    pub derived_from_name: Option<String>,
+    pub exposure_bias: Option<String>,
+    pub exposure_mode: Option<String>,
+    pub subject_distance: Option<String>,
+    pub sub_sec_time: Option<String>,
 
     // Video specific
     pub duration_seconds: Option<f64>,
     pub resolution_width: Option<i32>,
     pub resolution_height: Option<i32>,
 
+    // XMP sidecar
+    pub sidecar_path: Option<String>,
+    pub rating: Option<i32>,
+    pub color_label: Option<String>,
+    pub title: Option<String>,
+    pub keywords: Option<String>,
+
+    // Asset grouping
+    pub rendition: String,
+    pub content_identifier: Option<String>,
+    pub motion_photo: bool,
+
     // File system
     pub path: String,
     pub date_added: i64,
     pub date_modified: i64,
 }
 
+pub struct TagUsageRow {
+    pub name: String,
+    pub media_count: i64,
+}
+
+pub struct MediaRelationRow {
+    pub path: String,
+    pub related_path: String,
+    pub relation: String,
+    pub matched_by: String,
+}
+
+#[derive(Clone)]
+pub struct SimilarityMemberRow {
+    pub group_id: i64,
+    pub path: String,
+    pub distance: i64,
+    pub file_size_bytes: i64,
+    /// What the ranking looks at, as recorded when the file was scanned
+    pub rendition: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub metadata_fields: i64,
+    pub date_modified: i64,
+}
+
+pub struct StackMemberRow {
+    pub stack_id: i64,
+    pub kind: String,
+    pub position: i64,
+    pub path: String,
+    pub is_pick: bool,
+}
+
 pub struct DuplicateGroupRow {
-    pub id: Option<i64>,
-    pub media_file_id: i64,
-    pub total_copies: i32,
-    pub total_size_bytes: i64,
+    pub id: i64,
+    pub hash: String,
+    pub kept_path: String,
+    pub keep_reason: String,
+    pub total_copies: i64,
+    pub wasted_space_bytes: i64,
+    /// Every path holding a copy, the kept one included
+    pub paths: Vec<String>,
+}
+
+pub struct FolderWasteRow {
+    pub folder: String,
+    pub extra_copies: i64,
     pub wasted_space_bytes: i64,
 }
 
@@ -50,3 +112,73 @@ pub struct BackupSessionRow {
     pub status: String,
     pub error_message: Option<String>,
 }
+
+pub struct CleanupSessionRow {
+    pub id: i64,
+    pub quarantine_path: String,
+    pub status: String,
+}
+
+pub struct QuarantineMoveRow {
+    pub id: i64,
+    pub original_path: String,
+    pub quarantine_path: String,
+    pub file_size_bytes: i64,
+}
+
+pub struct SdCardRow {
+    pub id: i64,
+    pub volume_uuid: Option<String>,
+    /// ';' separated
+    pub dcim_folders: String,
+}
+
+pub struct ImportedFileRow {
+    pub card_id: i64,
+    pub session_id: i64,
+    /// Path on the card, from its root
+    pub relative_path: String,
+    pub file_size_bytes: i64,
+    pub modified_at: i64,
+    pub hash: String,
+    pub destination_path: String,
+}
+
+pub struct DeviceRow {
+    pub id: i64,
+    pub marker_id: Option<String>,
+    pub volume_uuid: Option<String>,
+    pub label: String,
+    /// Where it was mounted last time
+    pub mount_point: Option<String>,
+    pub last_seen_at: i64,
+    pub file_count: i64,
+}
+
+/// A file the catalog knows at a path on a device, from `file_locations` or a cloud backup
+pub struct LibraryFileRow {
+    /// From the device root
+    pub relative_path: String,
+    pub hash: String,
+    pub file_size_bytes: i64,
+    /// SHA-256 of the whole file, known once it has been backed up or scrubbed
+    pub content_hash: Option<String>,
+    /// Known for files backed up by cloud-backup
+    pub modified_at: Option<i64>,
+}
+
+pub struct LocationRow {
+    pub hash: String,
+    pub device_id: i64,
+    pub relative_path: String,
+    pub file_size_bytes: i64,
+    /// What this copy hashed to when it was last verified, or should have
+    pub content_hash: Option<String>,
+}
+
+/// A folder's recovery data as recorded when `parity` wrote it
+pub struct ParitySetRow {
+    pub redundancy_percent: i64,
+    /// `(relative_path, file_size_bytes)` of every file covered, sorted by path
+    pub files: Vec<(String, i64)>,
+}
@@ -1,10 +1,20 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 
-use crate::database::models::MediaFileRow;
+use crate::database::models::{
+    CleanupSessionRow, DeviceRow, DuplicateGroupRow, FolderWasteRow, ImportedFileRow,
+    LibraryFileRow, LocationRow, MediaFileRow, MediaRelationRow, ParitySetRow, QuarantineMoveRow,
+    SdCardRow, SimilarityMemberRow, StackMemberRow, TagUsageRow,
+};
 use crate::utils::core::Media;
+use crate::utils::duplicates::Duplicates;
+use crate::utils::tags::{normalize_tag, tag_ancestors};
+use crate::utils::video_fingerprint::VideoFingerprint;
 
 pub fn new_backup_session(
     conn: &Connection,
@@ -54,6 +64,34 @@ pub fn update_backup_session_cancelled(conn: &Connection, session_id: i64) -> ru
 
     Ok(())
 }
+/// Stores the final counts of a session, `error_message` when some files failed
+pub fn update_backup_session_counts(
+    conn: &Connection,
+    session_id: i64,
+    files_scanned: i64,
+    files_copied: i64,
+    files_skipped: i64,
+    bytes_copied: i64,
+    error_message: Option<&str>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE backup_sessions
+            SET files_scanned = ?1, files_copied = ?2, files_skipped = ?3, bytes_copied = ?4,
+                error_message = ?5, duration_seconds = strftime('%s', 'now') - started_at
+          WHERE id = ?6",
+        rusqlite::params![
+            files_scanned,
+            files_copied,
+            files_skipped,
+            bytes_copied,
+            error_message,
+            session_id
+        ],
+    )?;
+
+    Ok(())
+}
+
 pub fn insert_media_file(conn: &Connection, media: &Media) -> rusqlite::Result<()> {
     // We know the file exists since we just created Media from it, so unwrap is safe
     let metadata = fs::metadata(&media.file_path).unwrap();
@@ -87,9 +125,54 @@ pub fn insert_media_file(conn: &Connection, media: &Media) -> rusqlite::Result<(
             .as_ref()
             .and_then(|e| e.focal_length.clone()),
         software: media.exif_data.as_ref().and_then(|e| e.software.clone()),
+        date_time_original: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.date_time_original.clone()),
+        camera_serial: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.camera_serial.clone()),
+        image_unique_id: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.image_unique_id.clone()),
+        derived_from_name: media.derived_from_name.clone(),
+        exposure_bias: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.exposure_bias.clone()),
+        exposure_mode: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.exposure_mode.clone()),
+        subject_distance: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.subject_distance.clone()),
+        sub_sec_time: media
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.sub_sec_time.clone()),
         duration_seconds: None,
-        resolution_width: None,
-        resolution_height: None,
+        resolution_width: media.resolution.map(|(width, _)| width as i32),
+        resolution_height: media.resolution.map(|(_, height)| height as i32),
+        sidecar_path: media
+            .sidecar
+            .as_ref()
+            .and_then(|s| s.path.to_str())
+            .map(|s| s.to_string()),
+        rating: media.sidecar.as_ref().and_then(|s| s.rating),
+        color_label: media.sidecar.as_ref().and_then(|s| s.label.clone()),
+        title: media.sidecar.as_ref().and_then(|s| s.title.clone()),
+        keywords: media
+            .sidecar
+            .as_ref()
+            .filter(|s| !s.keywords.is_empty())
+            .map(|s| s.keywords.join(";")),
+        rendition: media.rendition.as_str().to_string(),
+        content_identifier: media.content_identifier.clone(),
+        motion_photo: media.motion_photo,
         path: media.file_path.to_str().unwrap().to_string(),
         date_added: metadata
             .created()
@@ -106,8 +189,8 @@ pub fn insert_media_file(conn: &Connection, media: &Media) -> rusqlite::Result<(
     };
 
     conn.execute(
-        "INSERT INTO media_files (hash, file_size_bytes, media_type, extension, camera_make, camera_model, lens_model, date_taken, iso, aperture, shutter_speed, focal_length, software, duration_seconds, resolution_width, resolution_height, path, date_added, date_modified) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)",
+        "INSERT INTO media_files (hash, file_size_bytes, media_type, extension, camera_make, camera_model, lens_model, date_taken, iso, aperture, shutter_speed, focal_length, software, duration_seconds, resolution_width, resolution_height, path, date_added, date_modified, sidecar_path, rating, color_label, title, keywords, rendition, date_time_original, camera_serial, image_unique_id, derived_from_name, content_identifier, motion_photo, exposure_bias, exposure_mode, subject_distance, sub_sec_time) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34, ?35)",
         rusqlite::params![
             media_file_row.hash,
             media_file_row.file_size_bytes as i64,
@@ -127,45 +210,1616 @@ pub fn insert_media_file(conn: &Connection, media: &Media) -> rusqlite::Result<(
             media_file_row.resolution_height,
             media_file_row.path,
             media_file_row.date_added as i64,
-            media_file_row.date_modified as i64
+            media_file_row.date_modified as i64,
+            media_file_row.sidecar_path,
+            media_file_row.rating,
+            media_file_row.color_label,
+            media_file_row.title,
+            media_file_row.keywords,
+            media_file_row.rendition,
+            media_file_row.date_time_original,
+            media_file_row.camera_serial,
+            media_file_row.image_unique_id,
+            media_file_row.derived_from_name,
+            media_file_row.content_identifier,
+            media_file_row.motion_photo,
+            media_file_row.exposure_bias,
+            media_file_row.exposure_mode,
+            media_file_row.subject_distance,
+            media_file_row.sub_sec_time
         ],
     )?;
     Ok(())
 }
 
-pub fn insert_duplicate_group(conn: &Connection, media: &Media) -> rusqlite::Result<()> {
-    // Get the media_file_id by querying with hash
-    let media_file_id: i64 = conn.query_row(
+/// Replaces the duplicate groups stored for a backup session, so running it again
+/// doesn't pile up rows. Only hashes with more than one copy are stored.
+pub fn replace_duplicate_groups(
+    conn: &Connection,
+    session_id: i64,
+    duplicates: &[Duplicates],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        "DELETE FROM duplicate_groups WHERE session_id = ?1",
+        [session_id],
+    )?;
+
+    for duplicate in duplicates.iter().filter(|d| d.count > 1) {
+        let media_file_id = media_file_id_by_hash(&tx, &duplicate.hash).optional()?;
+        let file_size = duplicate.file_size as i64;
+
+        let group_id: i64 = tx.query_row(
+            "INSERT INTO duplicate_groups (session_id, hash, media_file_id, kept_path, keep_reason, total_copies, file_size_bytes, total_size_bytes, wasted_space_bytes)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) RETURNING id",
+            rusqlite::params![
+                session_id,
+                duplicate.hash,
+                media_file_id,
+                duplicate.files[0].to_str(),
+                duplicate.keep_reason,
+                duplicate.count as i64,
+                file_size,
+                file_size * duplicate.count as i64,
+                file_size * (duplicate.count as i64 - 1)
+            ],
+            |row| row.get::<_, i64>(0),
+        )?;
+
+        for (index, path) in duplicate.files.iter().enumerate() {
+            let folder = path.parent().and_then(|p| p.to_str()).unwrap_or("");
+
+            tx.execute(
+                "INSERT OR IGNORE INTO duplicate_group_members (group_id, path, folder, is_kept) VALUES (?1, ?2, ?3, ?4)",
+                (group_id, path.to_str(), folder, index == 0),
+            )?;
+        }
+    }
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+/// Most recent backup session that has duplicate groups stored
+pub fn latest_duplicate_session(conn: &Connection) -> rusqlite::Result<Option<i64>> {
+    conn.query_row("SELECT MAX(session_id) FROM duplicate_groups", [], |row| {
+        row.get(0)
+    })
+}
+
+/// Duplicate groups of a session with at least `min_copies` copies, biggest waste first
+pub fn duplicate_groups_with_copies(
+    conn: &Connection,
+    session_id: i64,
+    min_copies: i64,
+) -> rusqlite::Result<Vec<DuplicateGroupRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, hash, kept_path, keep_reason, total_copies, wasted_space_bytes
+           FROM duplicate_groups
+          WHERE session_id = ?1 AND total_copies >= ?2
+          ORDER BY wasted_space_bytes DESC, hash",
+    )?;
+
+    let mut groups = stmt
+        .query_map((session_id, min_copies), |row| {
+            Ok(DuplicateGroupRow {
+                id: row.get(0)?,
+                hash: row.get(1)?,
+                kept_path: row.get(2)?,
+                keep_reason: row.get(3)?,
+                total_copies: row.get(4)?,
+                wasted_space_bytes: row.get(5)?,
+                paths: Vec::new(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut members = conn.prepare(
+        "SELECT path FROM duplicate_group_members WHERE group_id = ?1 ORDER BY is_kept DESC, path",
+    )?;
+
+    for group in groups.iter_mut() {
+        group.paths = members
+            .query_map([group.id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+    }
+
+    Ok(groups)
+}
+
+/// Bytes taken by the copies that are not kept, per folder, biggest first
+pub fn wasted_bytes_per_folder(
+    conn: &Connection,
+    session_id: i64,
+) -> rusqlite::Result<Vec<FolderWasteRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT gm.folder, COUNT(*), SUM(g.file_size_bytes)
+           FROM duplicate_group_members gm
+           JOIN duplicate_groups g ON g.id = gm.group_id
+          WHERE g.session_id = ?1 AND gm.is_kept = 0
+          GROUP BY gm.folder
+          ORDER BY SUM(g.file_size_bytes) DESC, gm.folder",
+    )?;
+
+    stmt.query_map([session_id], |row| {
+        Ok(FolderWasteRow {
+            folder: row.get(0)?,
+            extra_copies: row.get(1)?,
+            wasted_space_bytes: row.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn media_file_id_by_hash(conn: &Connection, hash: &str) -> rusqlite::Result<i64> {
+    conn.query_row(
         "SELECT id FROM media_files WHERE hash = ?1",
-        [&media.hash],
+        [hash],
+        |row| row.get::<_, i64>(0),
+    )
+}
+
+/// Media file ids stored at `path`, or anywhere below it when `path` is a folder
+pub fn media_file_ids_under_path(conn: &Connection, path: &str) -> rusqlite::Result<Vec<i64>> {
+    let path = path.trim_end_matches('/');
+
+    let mut stmt = conn.prepare(
+        "SELECT id FROM media_files WHERE path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/'",
+    )?;
+
+    stmt.query_map([path], |row| row.get::<_, i64>(0))?
+        .collect()
+}
+
+/// Returns the id of the tag, creating it and any missing ancestors
+/// (`trips`, `trips/2025` for `trips/2025/japan`). Commands check tag names with
+/// `normalize_tag` before getting here.
+pub fn ensure_tag(conn: &Connection, name: &str) -> rusqlite::Result<i64> {
+    let name = normalize_tag(name).ok_or_else(|| {
+        rusqlite::Error::ToSqlConversionFailure(Box::new(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Not a tag name: {:?}", name),
+        )))
+    })?;
+
+    let mut parent_id: Option<i64> = None;
+
+    for tag_name in tag_ancestors(&name) {
+        conn.execute(
+            "INSERT OR IGNORE INTO tags (name, parent_id) VALUES (?1, ?2)",
+            rusqlite::params![tag_name, parent_id],
+        )?;
+
+        parent_id = Some(conn.query_row(
+            "SELECT id FROM tags WHERE name = ?1",
+            [&tag_name],
+            |row| row.get::<_, i64>(0),
+        )?);
+    }
+
+    Ok(parent_id.unwrap())
+}
+
+/// Tags every media file in `media_file_ids` in one transaction, returns how many links were added.
+/// A link a stack made is taken over by the keyword or manual tag, so it outlives the stack
+pub fn tag_media_files(
+    conn: &Connection,
+    tag: &str,
+    media_file_ids: &[i64],
+    source: &str,
+) -> rusqlite::Result<usize> {
+    let tx = conn.unchecked_transaction()?;
+
+    let tag_id = ensure_tag(&tx, tag)?;
+
+    let mut added = 0;
+    for media_file_id in media_file_ids {
+        added += tx.execute(
+            "INSERT INTO media_tags (media_file_id, tag_id, source) VALUES (?1, ?2, ?3)
+             ON CONFLICT(media_file_id, tag_id) DO UPDATE SET source = excluded.source
+             WHERE media_tags.source = 'stack' AND excluded.source <> 'stack'",
+            (media_file_id, tag_id, source),
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(added)
+}
+
+/// Removes the tag from every media file in `media_file_ids`, returns how many links were removed
+pub fn untag_media_files(
+    conn: &Connection,
+    tag: &str,
+    media_file_ids: &[i64],
+) -> rusqlite::Result<usize> {
+    let Some(tag) = normalize_tag(tag) else {
+        return Ok(0);
+    };
+
+    let tx = conn.unchecked_transaction()?;
+
+    let mut removed = 0;
+    for media_file_id in media_file_ids {
+        removed += tx.execute(
+            "DELETE FROM media_tags WHERE media_file_id = ?1 AND tag_id = (SELECT id FROM tags WHERE name = ?2)",
+            (media_file_id, &tag),
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(removed)
+}
+
+/// Lists tags (optionally only `prefix` and its children) with the number of
+/// media files tagged with it or any of its descendants
+pub fn list_tags(conn: &Connection, prefix: Option<&str>) -> rusqlite::Result<Vec<TagUsageRow>> {
+    let prefix = prefix.and_then(normalize_tag);
+
+    let mut stmt = conn.prepare(
+        "SELECT t.name,
+                (SELECT COUNT(DISTINCT mt.media_file_id)
+                   FROM media_tags mt
+                   JOIN tags d ON d.id = mt.tag_id
+                  WHERE d.name = t.name OR substr(d.name, 1, length(t.name) + 1) = t.name || '/')
+           FROM tags t
+          WHERE ?1 IS NULL OR t.name = ?1 OR substr(t.name, 1, length(?1) + 1) = ?1 || '/'
+          ORDER BY t.name",
+    )?;
+
+    stmt.query_map([prefix], |row| {
+        Ok(TagUsageRow {
+            name: row.get(0)?,
+            media_count: row.get(1)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn tags_for_media_file(conn: &Connection, media_file_id: i64) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.name FROM media_tags mt JOIN tags t ON t.id = mt.tag_id WHERE mt.media_file_id = ?1 ORDER BY t.name",
+    )?;
+
+    stmt.query_map([media_file_id], |row| row.get::<_, String>(0))?
+        .collect()
+}
+
+/// Stores the IPTC / XMP keywords found while scanning as tags
+pub fn insert_media_keywords(conn: &Connection, media: &Media) -> rusqlite::Result<()> {
+    if media.keywords.is_empty() {
+        return Ok(());
+    }
+
+    let media_file_id = media_file_id_by_hash(conn, &media.hash)?;
+
+    for keyword in &media.keywords {
+        tag_media_files(conn, keyword, &[media_file_id], "metadata")?;
+    }
+
+    Ok(())
+}
+
+/// Creates the asset if needed and links the media files (by hash) to it
+pub fn insert_asset(
+    conn: &Connection,
+    asset_key: &str,
+    kind: &str,
+    date_taken: Option<&str>,
+    hashes: &[&str],
+) -> rusqlite::Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        "INSERT INTO assets (asset_key, kind, date_taken) VALUES (?1, ?2, ?3)
+         ON CONFLICT(asset_key) DO UPDATE SET kind = excluded.kind",
+        (asset_key, kind, date_taken),
+    )?;
+
+    let asset_id: i64 = tx.query_row(
+        "SELECT id FROM assets WHERE asset_key = ?1",
+        [asset_key],
+        |row| row.get::<_, i64>(0),
+    )?;
+
+    for hash in hashes {
+        tx.execute(
+            "UPDATE media_files SET asset_id = ?1 WHERE hash = ?2",
+            (asset_id, hash),
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(asset_id)
+}
+
+/// Drops every stack any of these files is in, with the `panorama` tags scanning gave their
+/// frames, so a folder's stacks can be stored again from scratch
+pub fn delete_stacks_with_members(conn: &Connection, hashes: &[&str]) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    for hash in hashes {
+        tx.execute(
+            "DELETE FROM media_tags
+              WHERE source = 'stack'
+                AND tag_id IN (SELECT id FROM tags WHERE name = 'panorama')
+                AND media_file_id IN (
+                    SELECT sm.media_file_id
+                      FROM stack_members sm
+                      JOIN stacks s ON s.id = sm.stack_id
+                     WHERE s.kind = 'panorama'
+                       AND sm.stack_id IN (
+                           SELECT sm2.stack_id FROM stack_members sm2
+                             JOIN media_files m ON m.id = sm2.media_file_id
+                            WHERE m.hash = ?1))",
+            [hash],
+        )?;
+
+        tx.execute(
+            "DELETE FROM stacks WHERE id IN (
+                 SELECT sm.stack_id FROM stack_members sm
+                   JOIN media_files m ON m.id = sm.media_file_id
+                  WHERE m.hash = ?1)",
+            [hash],
+        )?;
+    }
+
+    tx.commit()
+}
+
+/// Stores a burst / bracket stack keyed by the hashes of all its files, so the same frames
+/// always make the same stack. `members` are (frame position, hash); a frame can hold more
+/// than one file (RAW + JPEG).
+pub fn insert_stack(
+    conn: &Connection,
+    stack_key: &str,
+    kind: &str,
+    camera: &str,
+    date_taken: Option<&str>,
+    pick_hash: &str,
+    members: &[(usize, &str)],
+) -> rusqlite::Result<i64> {
+    let tx = conn.unchecked_transaction()?;
+
+    let frame_count = members
+        .iter()
+        .map(|(position, _)| position + 1)
+        .max()
+        .unwrap_or(0);
+    let pick_media_file_id = media_file_id_by_hash(&tx, pick_hash).optional()?;
+
+    tx.execute(
+        "INSERT INTO stacks (stack_key, kind, camera, date_taken, frame_count, pick_media_file_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(stack_key) DO UPDATE SET kind = excluded.kind, camera = excluded.camera,
+             date_taken = excluded.date_taken, frame_count = excluded.frame_count,
+             pick_media_file_id = excluded.pick_media_file_id",
+        rusqlite::params![stack_key, kind, camera, date_taken, frame_count as i64, pick_media_file_id],
+    )?;
+
+    let stack_id: i64 = tx.query_row(
+        "SELECT id FROM stacks WHERE stack_key = ?1",
+        [stack_key],
         |row| row.get::<_, i64>(0),
     )?;
 
-    // Count how many files have the same hash (total_copies)
-    let total_copies: i32 = conn.query_row(
-        "SELECT COUNT(*) FROM media_files WHERE hash = ?1",
-        [&media.hash],
-        |row| row.get::<_, i32>(0),
+    for (position, hash) in members {
+        tx.execute(
+            "INSERT OR IGNORE INTO stack_members (stack_id, media_file_id, position)
+             SELECT ?1, id, ?2 FROM media_files WHERE hash = ?3",
+            (stack_id, *position as i64, hash),
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(stack_id)
+}
+
+/// Whether frame `b_hash` was found to carry on the panorama of frame `a_hash`, if that pair
+/// was compared before
+pub fn panorama_pair(
+    conn: &Connection,
+    a_hash: &str,
+    b_hash: &str,
+) -> rusqlite::Result<Option<bool>> {
+    conn.query_row(
+        "SELECT overlaps FROM panorama_pairs WHERE a_hash = ?1 AND b_hash = ?2",
+        (a_hash, b_hash),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn insert_panorama_pair(
+    conn: &Connection,
+    a_hash: &str,
+    b_hash: &str,
+    overlaps: bool,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO panorama_pairs (a_hash, b_hash, overlaps) VALUES (?1, ?2, ?3)",
+        (a_hash, b_hash, overlaps),
+    )?;
+
+    Ok(())
+}
+
+/// Every file of every stack (optionally of one kind), in stack and frame order
+pub fn stack_members(
+    conn: &Connection,
+    kind: Option<&str>,
+) -> rusqlite::Result<Vec<StackMemberRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.kind, sm.position, m.path, m.id = s.pick_media_file_id
+           FROM stacks s
+           JOIN stack_members sm ON sm.stack_id = s.id
+           JOIN media_files m ON m.id = sm.media_file_id
+          WHERE ?1 IS NULL OR s.kind = ?1
+          ORDER BY s.date_taken, s.id, sm.position, m.path",
+    )?;
+
+    stmt.query_map([kind], |row| {
+        Ok(StackMemberRow {
+            stack_id: row.get(0)?,
+            kind: row.get(1)?,
+            position: row.get(2)?,
+            path: row.get(3)?,
+            is_pick: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// The registered device with this marker, else this volume UUID, else (for volumes
+/// known by neither) this label
+pub fn device_id(
+    conn: &Connection,
+    marker_id: Option<&str>,
+    volume_uuid: Option<&str>,
+    label: &str,
+) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM devices
+          WHERE marker_id = ?1
+             OR volume_uuid = ?2
+             OR (marker_id IS NULL AND volume_uuid IS NULL AND ?1 IS NULL AND ?2 IS NULL AND label = ?3)
+          ORDER BY marker_id = ?1 DESC, volume_uuid = ?2 DESC, id
+          LIMIT 1",
+        (marker_id, volume_uuid, label),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Gives a device known by UUID or label the marker just written to its root
+pub fn set_device_marker(
+    conn: &Connection,
+    device_id: i64,
+    marker_id: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE devices SET marker_id = ?1 WHERE id = ?2 AND marker_id IS NULL",
+        (marker_id, device_id),
+    )?;
+
+    Ok(())
+}
+
+/// Finds or records a device and refreshes its label and where it is mounted
+pub fn upsert_device(
+    conn: &Connection,
+    marker_id: Option<&str>,
+    volume_uuid: Option<&str>,
+    label: &str,
+    mount_point: &str,
+) -> rusqlite::Result<i64> {
+    match device_id(conn, marker_id, volume_uuid, label)? {
+        Some(id) => {
+            conn.execute(
+                "UPDATE devices
+                    SET marker_id = COALESCE(marker_id, ?1), volume_uuid = COALESCE(volume_uuid, ?2),
+                        label = ?3, mount_point = ?4, last_seen_at = strftime('%s', 'now')
+                  WHERE id = ?5",
+                (marker_id, volume_uuid, label, mount_point, id),
+            )?;
+
+            Ok(id)
+        }
+        None => conn.query_row(
+            "INSERT INTO devices (marker_id, volume_uuid, label, mount_point) VALUES (?1, ?2, ?3, ?4) RETURNING id",
+            (marker_id, volume_uuid, label, mount_point),
+            |row| row.get::<_, i64>(0),
+        ),
+    }
+}
+
+pub fn list_devices(conn: &Connection) -> rusqlite::Result<Vec<DeviceRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT d.id, d.marker_id, d.volume_uuid, d.label, d.mount_point, d.last_seen_at, COUNT(fl.id)
+           FROM devices d
+           LEFT JOIN file_locations fl ON fl.device_id = d.id
+          GROUP BY d.id
+          ORDER BY d.id",
     )?;
 
-    // Calculate total_size_bytes = file_size * total_copies
-    let total_size_bytes = media.file_size as i64 * total_copies as i64;
+    stmt.query_map([], |row| {
+        Ok(DeviceRow {
+            id: row.get(0)?,
+            marker_id: row.get(1)?,
+            volume_uuid: row.get(2)?,
+            label: row.get(3)?,
+            mount_point: row.get(4)?,
+            last_seen_at: row.get(5)?,
+            file_count: row.get(6)?,
+        })
+    })?
+    .collect()
+}
 
-    // Calculate wasted_space_bytes = file_size * (total_copies - 1)
-    // This matches the formula from main.rs: duplicate.file_size * (duplicate.count as u64 - 1)
-    let wasted_space_bytes = media.file_size as i64 * (total_copies as i64 - 1);
+/// Records (or refreshes) where a file with this hash was seen. A different file at
+/// the path drops what `scrub` found about the old one.
+pub fn upsert_file_location(
+    conn: &Connection,
+    hash: &str,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO file_locations (hash, device_id, relative_path, file_size_bytes) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(device_id, relative_path) DO UPDATE SET
+            hash = excluded.hash, file_size_bytes = excluded.file_size_bytes, last_seen_at = strftime('%s', 'now'),
+            content_hash = CASE WHEN hash = excluded.hash AND file_size_bytes = excluded.file_size_bytes THEN content_hash END,
+            verify_status = CASE WHEN hash = excluded.hash AND file_size_bytes = excluded.file_size_bytes THEN verify_status END,
+            last_verified_at = CASE WHEN hash = excluded.hash AND file_size_bytes = excluded.file_size_bytes THEN last_verified_at END",
+        (hash, device_id, relative_path, file_size_bytes),
+    )?;
+
+    Ok(())
+}
 
-    // Insert into duplicate_groups table
+/// Records which device the cataloged copy at `path` is on
+pub fn set_media_file_device(
+    conn: &Connection,
+    hash: &str,
+    path: &str,
+    device_id: i64,
+    relative_path: &str,
+) -> rusqlite::Result<()> {
     conn.execute(
-        "INSERT INTO duplicate_groups (media_file_id, total_copies, total_size_bytes, wasted_space_bytes) 
+        "UPDATE media_files SET device_id = ?1, relative_path = ?2 WHERE hash = ?3 AND path = ?4",
+        (device_id, relative_path, hash, path),
+    )?;
+
+    Ok(())
+}
+
+/// Every path a file with this hash has been seen at, under the mount point its
+/// device had last time. Copies `scrub` found intact come first, damaged ones last.
+pub fn paths_with_hash(conn: &Connection, hash: &str) -> rusqlite::Result<Vec<PathBuf>> {
+    let mut stmt = conn.prepare(
+        "SELECT d.mount_point, fl.relative_path
+           FROM file_locations fl
+           JOIN devices d ON d.id = fl.device_id
+          WHERE fl.hash = ?1
+          ORDER BY fl.verify_status IS 'damaged', fl.verify_status IS NOT 'ok',
+                   fl.last_verified_at DESC, fl.relative_path",
+    )?;
+
+    stmt.query_map([hash], |row| {
+        let mount_point = row.get::<_, Option<String>>(0)?.unwrap_or_default();
+        Ok(Path::new(&mount_point).join(row.get::<_, String>(1)?))
+    })?
+    .collect()
+}
+
+/// Devices holding a copy of the file with this hash and size, other than
+/// `exclude_device_id`, as (device id, label, full hash the copy was verified as).
+/// Locations backfilled before devices were tracked don't count, their device is unknown.
+pub fn devices_with_hash(
+    conn: &Connection,
+    hash: &str,
+    file_size_bytes: i64,
+    exclude_device_id: Option<i64>,
+) -> rusqlite::Result<Vec<(i64, String, Option<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT d.id, d.label, fl.content_hash
+           FROM devices d
+           JOIN file_locations fl ON fl.device_id = d.id
+          WHERE d.id IS NOT ?3
+            AND fl.hash = ?1 AND fl.file_size_bytes = ?2
+            AND NOT (d.label = 'unknown' AND d.marker_id IS NULL AND d.volume_uuid IS NULL)
+          ORDER BY d.label, d.id",
+    )?;
+
+    stmt.query_map((hash, file_size_bytes, exclude_device_id), |row| {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?
+    .collect()
+}
+
+/// Looks for the RAW / camera original an edited export was made from.
+///
+/// Tried from strongest to weakest: EXIF ImageUniqueID, the original file name
+/// recorded in XMP, then DateTimeOriginal + camera serial. RAWs win over camera JPEGs.
+/// Returns the original's id and what matched.
+pub fn find_original_media_file(
+    conn: &Connection,
+    media_file_id: i64,
+) -> rusqlite::Result<Option<(i64, &'static str)>> {
+    let checks: [(&str, &str); 3] = [
+        (
+            "image_unique_id",
+            "o.image_unique_id IS NOT NULL AND o.image_unique_id = m.image_unique_id",
+        ),
+        (
+            "xmp_derived_from",
+            "m.derived_from_name IS NOT NULL
+             AND lower(substr(o.path, -length(m.derived_from_name) - 1)) = '/' || lower(m.derived_from_name)
+             AND (m.date_time_original IS NULL OR o.date_time_original IS NULL OR o.date_time_original = m.date_time_original)",
+        ),
+        (
+            "date_time_original_serial",
+            "o.date_time_original IS NOT NULL AND o.date_time_original = m.date_time_original
+             AND o.camera_serial IS NOT NULL AND o.camera_serial = m.camera_serial",
+        ),
+    ];
+
+    for (matched_by, condition) in checks {
+        let sql = format!(
+            "SELECT o.id FROM media_files m, media_files o
+              WHERE m.id = ?1 AND o.id != m.id AND o.rendition IN ('raw', 'original') AND {}
+              ORDER BY o.rendition = 'raw' DESC, o.id
+              LIMIT 1",
+            condition
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let mut rows = stmt.query([media_file_id])?;
+
+        if let Some(row) = rows.next()? {
+            return Ok(Some((row.get::<_, i64>(0)?, matched_by)));
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn insert_media_relation(
+    conn: &Connection,
+    media_file_id: i64,
+    related_media_file_id: i64,
+    relation: &str,
+    matched_by: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO media_relations (media_file_id, related_media_file_id, relation, matched_by)
          VALUES (?1, ?2, ?3, ?4)",
-        rusqlite::params![
+        (media_file_id, related_media_file_id, relation, matched_by),
+    )?;
+
+    Ok(())
+}
+
+/// Path of the original a media file (by hash) was derived from, if linked
+pub fn derived_from_path(conn: &Connection, hash: &str) -> rusqlite::Result<Option<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT o.path FROM media_relations r
+           JOIN media_files m ON m.id = r.media_file_id
+           JOIN media_files o ON o.id = r.related_media_file_id
+          WHERE m.hash = ?1 AND r.relation = 'derived_from'
+          LIMIT 1",
+    )?;
+    let mut rows = stmt.query([hash])?;
+
+    rows.next()?.map(|row| row.get::<_, String>(0)).transpose()
+}
+
+pub fn list_media_relations(conn: &Connection) -> rusqlite::Result<Vec<MediaRelationRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.path, o.path, r.relation, r.matched_by FROM media_relations r
+           JOIN media_files m ON m.id = r.media_file_id
+           JOIN media_files o ON o.id = r.related_media_file_id
+          ORDER BY o.path, m.path",
+    )?;
+
+    stmt.query_map([], |row| {
+        Ok(MediaRelationRow {
+            path: row.get(0)?,
+            related_path: row.get(1)?,
+            relation: row.get(2)?,
+            matched_by: row.get(3)?,
+        })
+    })?
+    .collect()
+}
+
+/// Images that don't have a perceptual hash yet, as (id, path)
+pub fn images_missing_phash(conn: &Connection) -> rusqlite::Result<Vec<(i64, String)>> {
+    let mut stmt = conn
+        .prepare("SELECT id, path FROM media_files WHERE media_type = 'image' AND phash IS NULL")?;
+
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+pub fn update_phash(conn: &Connection, media_file_id: i64, phash: u64) -> rusqlite::Result<()> {
+    // SQLite integers are signed, keep the bits as they are
+    conn.execute(
+        "UPDATE media_files SET phash = ?1 WHERE id = ?2",
+        (phash as i64, media_file_id),
+    )?;
+
+    Ok(())
+}
+
+pub fn media_phashes(conn: &Connection) -> rusqlite::Result<Vec<(i64, u64)>> {
+    let mut stmt = conn.prepare("SELECT id, phash FROM media_files WHERE phash IS NOT NULL")?;
+
+    stmt.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? as u64))
+    })?
+    .collect()
+}
+
+/// Replaces every similarity group of `kind` with `groups`, given as (media_file_id, distance) lists
+pub fn replace_similarity_groups(
+    conn: &Connection,
+    kind: &str,
+    threshold: u32,
+    groups: &[Vec<(i64, u32)>],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute("DELETE FROM similarity_groups WHERE kind = ?1", [kind])?;
+
+    for members in groups {
+        let group_id: i64 = tx.query_row(
+            "INSERT INTO similarity_groups (kind, threshold) VALUES (?1, ?2) RETURNING id",
+            (kind, threshold),
+            |row| row.get::<_, i64>(0),
+        )?;
+
+        for (media_file_id, distance) in members {
+            tx.execute(
+                "INSERT OR IGNORE INTO similarity_group_members (group_id, media_file_id, distance) VALUES (?1, ?2, ?3)",
+                (group_id, media_file_id, distance),
+            )?;
+        }
+    }
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+pub fn similarity_group_members(
+    conn: &Connection,
+    kind: &str,
+) -> rusqlite::Result<Vec<SimilarityMemberRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT g.id, m.path, gm.distance, m.file_size_bytes,
+                m.rendition, m.resolution_width, m.resolution_height,
+                (m.camera_make IS NOT NULL) + (m.camera_model IS NOT NULL)
+                  + (m.lens_model IS NOT NULL) + (m.date_taken IS NOT NULL)
+                  + (m.iso IS NOT NULL) + (m.aperture IS NOT NULL)
+                  + (m.shutter_speed IS NOT NULL) + (m.focal_length IS NOT NULL)
+                  + (m.software IS NOT NULL) + (m.date_time_original IS NOT NULL)
+                  + (m.camera_serial IS NOT NULL) + (m.image_unique_id IS NOT NULL)
+                  + (m.sidecar_path IS NOT NULL)
+                  + (SELECT COUNT(*) FROM media_tags mt
+                      WHERE mt.media_file_id = m.id AND mt.source = 'metadata'),
+                m.date_modified
+           FROM similarity_groups g
+           JOIN similarity_group_members gm ON gm.group_id = g.id
+           JOIN media_files m ON m.id = gm.media_file_id
+          WHERE g.kind = ?1
+          ORDER BY g.id, gm.distance, m.path",
+    )?;
+
+    stmt.query_map([kind], |row| {
+        let width: Option<u32> = row.get(5)?;
+        let height: Option<u32> = row.get(6)?;
+
+        Ok(SimilarityMemberRow {
+            group_id: row.get(0)?,
+            path: row.get(1)?,
+            distance: row.get(2)?,
+            file_size_bytes: row.get(3)?,
+            rendition: row.get(4)?,
+            resolution: width.zip(height),
+            metadata_fields: row.get(7)?,
+            date_modified: row.get(8)?,
+        })
+    })?
+    .collect()
+}
+
+/// Videos that don't have a fingerprint yet, as (id, path)
+pub fn videos_missing_fingerprint(conn: &Connection) -> rusqlite::Result<Vec<(i64, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.id, m.path FROM media_files m
+          WHERE m.media_type = 'video'
+            AND NOT EXISTS (SELECT 1 FROM video_fingerprints f WHERE f.media_file_id = m.id)",
+    )?;
+
+    stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+pub fn insert_video_fingerprint(
+    conn: &Connection,
+    media_file_id: i64,
+    fingerprint: &VideoFingerprint,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO video_fingerprints (media_file_id, duration_seconds, frame_hashes) VALUES (?1, ?2, ?3)",
+        (
             media_file_id,
-            total_copies,
-            total_size_bytes,
-            wasted_space_bytes
+            fingerprint.duration_seconds,
+            fingerprint.frame_hashes_to_string(),
+        ),
+    )?;
+
+    conn.execute(
+        "UPDATE media_files SET duration_seconds = ?1 WHERE id = ?2",
+        (fingerprint.duration_seconds, media_file_id),
+    )?;
+
+    Ok(())
+}
+
+pub fn video_fingerprints(conn: &Connection) -> rusqlite::Result<Vec<(i64, VideoFingerprint)>> {
+    let mut stmt = conn
+        .prepare("SELECT media_file_id, duration_seconds, frame_hashes FROM video_fingerprints")?;
+
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(id, duration, hashes)| {
+            VideoFingerprint::from_stored(duration, &hashes).map(|f| (id, f))
+        })
+        .collect())
+}
+
+pub fn new_cleanup_session(
+    conn: &Connection,
+    source_path: &str,
+    quarantine_path: &str,
+) -> rusqlite::Result<i64> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    conn.query_row(
+        "INSERT INTO cleanup_sessions (source_path, quarantine_path, started_at, status) VALUES (?1, ?2, ?3, 'running') RETURNING id",
+        (source_path, quarantine_path, now as i64),
+        |row| row.get::<_, i64>(0),
+    )
+}
+
+pub fn update_cleanup_session_completed(
+    conn: &Connection,
+    session_id: i64,
+    files_moved: i64,
+    bytes_moved: i64,
+) -> rusqlite::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    conn.execute(
+        "UPDATE cleanup_sessions SET status = 'completed', completed_at = ?1, files_moved = ?2, bytes_moved = ?3 WHERE id = ?4",
+        (now as i64, files_moved, bytes_moved, session_id),
+    )?;
+
+    Ok(())
+}
+
+pub fn update_cleanup_session_status(
+    conn: &Connection,
+    session_id: i64,
+    status: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE cleanup_sessions SET status = ?1 WHERE id = ?2",
+        (status, session_id),
+    )?;
+
+    Ok(())
+}
+
+pub fn cleanup_session(
+    conn: &Connection,
+    session_id: i64,
+) -> rusqlite::Result<Option<CleanupSessionRow>> {
+    conn.query_row(
+        "SELECT id, quarantine_path, status FROM cleanup_sessions WHERE id = ?1",
+        [session_id],
+        cleanup_session_from_row,
+    )
+    .optional()
+}
+
+/// Completed sessions that finished before `completed_before` (unix seconds)
+pub fn cleanup_sessions_to_purge(
+    conn: &Connection,
+    completed_before: i64,
+) -> rusqlite::Result<Vec<CleanupSessionRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, quarantine_path, status
+           FROM cleanup_sessions
+          WHERE status = 'completed' AND completed_at < ?1
+          ORDER BY id",
+    )?;
+
+    stmt.query_map([completed_before], cleanup_session_from_row)?
+        .collect()
+}
+
+fn cleanup_session_from_row(row: &rusqlite::Row) -> rusqlite::Result<CleanupSessionRow> {
+    Ok(CleanupSessionRow {
+        id: row.get(0)?,
+        quarantine_path: row.get(1)?,
+        status: row.get(2)?,
+    })
+}
+
+pub fn insert_quarantine_move(
+    conn: &Connection,
+    session_id: i64,
+    hash: &str,
+    original_path: &str,
+    quarantine_path: &str,
+    kept_path: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    conn.execute(
+        "INSERT INTO quarantine_moves (session_id, hash, original_path, quarantine_path, kept_path, file_size_bytes, moved_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            session_id,
+            hash,
+            original_path,
+            quarantine_path,
+            kept_path,
+            file_size_bytes,
+            now as i64
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Moves of a session that are still sitting in quarantine
+pub fn quarantined_moves(
+    conn: &Connection,
+    session_id: i64,
+) -> rusqlite::Result<Vec<QuarantineMoveRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, original_path, quarantine_path, file_size_bytes
+           FROM quarantine_moves
+          WHERE session_id = ?1 AND restored_at IS NULL AND purged_at IS NULL
+          ORDER BY id",
+    )?;
+
+    stmt.query_map([session_id], |row| {
+        Ok(QuarantineMoveRow {
+            id: row.get(0)?,
+            original_path: row.get(1)?,
+            quarantine_path: row.get(2)?,
+            file_size_bytes: row.get(3)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn mark_quarantine_move_restored(conn: &Connection, move_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE quarantine_moves SET restored_at = strftime('%s', 'now') WHERE id = ?1",
+        [move_id],
+    )?;
+
+    Ok(())
+}
+
+pub fn mark_quarantine_move_purged(conn: &Connection, move_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE quarantine_moves SET purged_at = strftime('%s', 'now') WHERE id = ?1",
+        [move_id],
+    )?;
+
+    Ok(())
+}
+
+/// Cards with this volume UUID or label, for `import` to pick the one plugged in
+pub fn sd_cards_matching(
+    conn: &Connection,
+    volume_uuid: Option<&str>,
+    label: &str,
+) -> rusqlite::Result<Vec<SdCardRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, volume_uuid, dcim_folders FROM sd_cards WHERE volume_uuid = ?1 OR label = ?2 ORDER BY id",
+    )?;
+
+    stmt.query_map((volume_uuid, label), |row| {
+        Ok(SdCardRow {
+            id: row.get(0)?,
+            volume_uuid: row.get(1)?,
+            dcim_folders: row.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+pub fn insert_sd_card(
+    conn: &Connection,
+    volume_uuid: Option<&str>,
+    label: &str,
+    dcim_folders: &str,
+) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "INSERT INTO sd_cards (volume_uuid, label, dcim_folders) VALUES (?1, ?2, ?3) RETURNING id",
+        (volume_uuid, label, dcim_folders),
+        |row| row.get::<_, i64>(0),
+    )
+}
+
+/// Refreshes the DCIM folders of a card after an import, and its UUID if it wasn't known
+pub fn update_sd_card_imported(
+    conn: &Connection,
+    card_id: i64,
+    volume_uuid: Option<&str>,
+    dcim_folders: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE sd_cards
+            SET volume_uuid = COALESCE(volume_uuid, ?1), dcim_folders = ?2,
+                last_imported_at = strftime('%s', 'now')
+          WHERE id = ?3",
+        (volume_uuid, dcim_folders, card_id),
+    )?;
+
+    Ok(())
+}
+
+/// Whether this file (same path, size and mtime) was imported from the card before
+pub fn is_file_imported(
+    conn: &Connection,
+    card_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+    modified_at: i64,
+) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(
+        "SELECT 1 FROM imported_files
+          WHERE card_id = ?1 AND relative_path = ?2 AND file_size_bytes = ?3 AND modified_at = ?4",
+    )?;
+
+    stmt.exists((card_id, relative_path, file_size_bytes, modified_at))
+}
+
+pub fn insert_imported_file(conn: &Connection, file: &ImportedFileRow) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO imported_files (card_id, session_id, relative_path, file_size_bytes, modified_at, hash, destination_path)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        rusqlite::params![
+            file.card_id,
+            file.session_id,
+            file.relative_path,
+            file.file_size_bytes,
+            file.modified_at,
+            file.hash,
+            file.destination_path
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Files an import session took off a card, copied or already in the library
+pub fn imported_files_for_session(
+    conn: &Connection,
+    session_id: i64,
+) -> rusqlite::Result<Vec<ImportedFileRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT card_id, session_id, relative_path, file_size_bytes, modified_at, hash, destination_path
+           FROM imported_files
+          WHERE session_id = ?1
+          ORDER BY relative_path",
+    )?;
+
+    stmt.query_map([session_id], |row| {
+        Ok(ImportedFileRow {
+            card_id: row.get(0)?,
+            session_id: row.get(1)?,
+            relative_path: row.get(2)?,
+            file_size_bytes: row.get(3)?,
+            modified_at: row.get(4)?,
+            hash: row.get(5)?,
+            destination_path: row.get(6)?,
+        })
+    })?
+    .collect()
+}
+
+/// Hash of the file cataloged at this path on the device, if its size still matches
+pub fn location_hash(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT hash FROM file_locations WHERE device_id = ?1 AND relative_path = ?2 AND file_size_bytes = ?3",
+        (device_id, relative_path, file_size_bytes),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn new_sync_session(conn: &Connection, libraries: &str, mode: &str) -> rusqlite::Result<i64> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    conn.query_row(
+        "INSERT INTO sync_sessions (libraries, mode, started_at, status) VALUES (?1, ?2, ?3, 'running') RETURNING id",
+        (libraries, mode, now as i64),
+        |row| row.get::<_, i64>(0),
+    )
+}
+
+pub fn update_sync_session_completed(
+    conn: &Connection,
+    session_id: i64,
+    files_copied: i64,
+    bytes_copied: i64,
+    conflicts: i64,
+    files_failed: i64,
+) -> rusqlite::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    conn.execute(
+        "UPDATE sync_sessions SET status = 'completed', completed_at = ?1, files_copied = ?2, bytes_copied = ?3, conflicts = ?4, files_failed = ?5 WHERE id = ?6",
+        (now as i64, files_copied, bytes_copied, conflicts, files_failed, session_id),
+    )?;
+
+    Ok(())
+}
+
+pub fn insert_sync_conflict(
+    conn: &Connection,
+    session_id: i64,
+    relative_path: &str,
+    kind: &str,
+    details: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO sync_conflicts (session_id, relative_path, kind, details) VALUES (?1, ?2, ?3, ?4)",
+        (session_id, relative_path, kind, details),
+    )?;
+
+    Ok(())
+}
+
+/// Sidecar hashes the libraries of `sync_set` agreed on at their last sync, by path
+pub fn synced_sidecars(
+    conn: &Connection,
+    sync_set: &str,
+) -> rusqlite::Result<HashMap<String, String>> {
+    let mut stmt =
+        conn.prepare("SELECT relative_path, hash FROM sync_sidecars WHERE sync_set = ?1")?;
+
+    stmt.query_map([sync_set], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+pub fn upsert_synced_sidecar(
+    conn: &Connection,
+    sync_set: &str,
+    relative_path: &str,
+    hash: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO sync_sidecars (sync_set, relative_path, hash) VALUES (?1, ?2, ?3)
+         ON CONFLICT(sync_set, relative_path) DO UPDATE SET hash = excluded.hash, synced_at = strftime('%s', 'now')",
+        (sync_set, relative_path, hash),
+    )?;
+
+    Ok(())
+}
+
+/// Content hash of what a file was backed up as, if its size and mtime are still the same
+pub fn remote_source_current(
+    conn: &Connection,
+    target: &str,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+    modified_at: i64,
+) -> rusqlite::Result<Option<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT o.content_hash
+           FROM remote_sources s
+           JOIN remote_objects o ON o.target = s.target AND o.object_key = s.object_key
+          WHERE s.target = ?1 AND s.device_id = ?2 AND s.relative_path = ?3
+            AND s.file_size_bytes = ?4 AND s.modified_at = ?5",
+    )?;
+
+    stmt.query_row(
+        (
+            target,
+            device_id,
+            relative_path,
+            file_size_bytes,
+            modified_at,
+        ),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub fn remote_object_exists(
+    conn: &Connection,
+    target: &str,
+    object_key: &str,
+) -> rusqlite::Result<bool> {
+    let mut stmt =
+        conn.prepare("SELECT 1 FROM remote_objects WHERE target = ?1 AND object_key = ?2")?;
+
+    stmt.exists((target, object_key))
+}
+
+pub fn insert_remote_object(
+    conn: &Connection,
+    target: &str,
+    object_key: &str,
+    content_hash: &str,
+    hash: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO remote_objects (target, object_key, content_hash, hash, file_size_bytes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        (target, object_key, content_hash, hash, file_size_bytes),
+    )?;
+
+    Ok(())
+}
+
+pub fn upsert_remote_source(
+    conn: &Connection,
+    target: &str,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+    modified_at: i64,
+    object_key: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO remote_sources (target, device_id, relative_path, file_size_bytes, modified_at, object_key)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(target, device_id, relative_path) DO UPDATE SET
+            file_size_bytes = excluded.file_size_bytes, modified_at = excluded.modified_at,
+            object_key = excluded.object_key, backed_up_at = strftime('%s', 'now')",
+        rusqlite::params![
+            target,
+            device_id,
+            relative_path,
+            file_size_bytes,
+            modified_at,
+            object_key
         ],
     )?;
 
     Ok(())
 }
+
+/// Every file seen under `folder` (from the device root, empty for all of it) on the
+/// device, then every file backed up from there. A path can come up more than once.
+pub fn library_files(
+    conn: &Connection,
+    device_id: i64,
+    folder: &str,
+) -> rusqlite::Result<Vec<LibraryFileRow>> {
+    let folder = folder.trim_end_matches('/');
+
+    let mut stmt = conn.prepare(
+        "SELECT fl.relative_path, fl.hash, fl.file_size_bytes,
+                COALESCE(
+                    (SELECT o.content_hash FROM remote_objects o
+                      WHERE o.hash = fl.hash AND o.file_size_bytes = fl.file_size_bytes
+                      ORDER BY o.id LIMIT 1),
+                    (SELECT c.content_hash FROM file_locations c
+                      WHERE c.hash = fl.hash AND c.file_size_bytes = fl.file_size_bytes
+                        AND c.verify_status = 'ok'
+                      ORDER BY c.last_verified_at LIMIT 1)),
+                NULL
+           FROM file_locations fl
+          WHERE fl.device_id = ?1
+            AND (?2 = '' OR substr(fl.relative_path, 1, length(?2) + 1) = ?2 || '/')
+         UNION ALL
+         SELECT s.relative_path, o.hash, s.file_size_bytes, o.content_hash, s.modified_at
+           FROM remote_sources s
+           JOIN remote_objects o ON o.target = s.target AND o.object_key = s.object_key
+          WHERE s.device_id = ?1
+            AND (?2 = '' OR substr(s.relative_path, 1, length(?2) + 1) = ?2 || '/')",
+    )?;
+
+    stmt.query_map((device_id, folder), |row| {
+        Ok(LibraryFileRow {
+            relative_path: row.get(0)?,
+            hash: row.get(1)?,
+            file_size_bytes: row.get(2)?,
+            content_hash: row.get(3)?,
+            modified_at: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// Hashes of the media files taken in `[taken_from, taken_until)` (`YYYY-MM-DD`) and
+/// tagged with `tag` or one of its children; `None` leaves a condition out
+pub fn media_hashes_matching(
+    conn: &Connection,
+    taken_from: Option<&str>,
+    taken_until: Option<&str>,
+    tag: Option<&str>,
+) -> rusqlite::Result<HashSet<String>> {
+    let tag = tag.and_then(normalize_tag);
+
+    let mut stmt = conn.prepare(
+        "SELECT m.hash
+           FROM media_files m
+          WHERE (?1 IS NULL OR m.date_taken >= ?1)
+            AND (?2 IS NULL OR m.date_taken < ?2)
+            AND (?3 IS NULL OR EXISTS (
+                    SELECT 1
+                      FROM media_tags mt
+                      JOIN tags t ON t.id = mt.tag_id
+                     WHERE mt.media_file_id = m.id
+                       AND (t.name = ?3 OR substr(t.name, 1, length(?3) + 1) = ?3 || '/')))",
+    )?;
+
+    stmt.query_map((taken_from, taken_until, tag), |row| row.get(0))?
+        .collect()
+}
+
+pub fn all_locations(conn: &Connection) -> rusqlite::Result<Vec<LocationRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT hash, device_id, relative_path, file_size_bytes, content_hash
+           FROM file_locations
+          ORDER BY hash, device_id, relative_path",
+    )?;
+
+    stmt.query_map([], |row| {
+        Ok(LocationRow {
+            hash: row.get(0)?,
+            device_id: row.get(1)?,
+            relative_path: row.get(2)?,
+            file_size_bytes: row.get(3)?,
+            content_hash: row.get(4)?,
+        })
+    })?
+    .collect()
+}
+
+/// Every SHA-256 a file with this hash and size was backed up as or verified as. Usually
+/// one, more when different files share their size and first 128 KB.
+pub fn recorded_content_hashes(
+    conn: &Connection,
+    hash: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT content_hash FROM remote_objects
+          WHERE hash = ?1 AND file_size_bytes = ?2
+         UNION
+         SELECT content_hash FROM file_locations
+          WHERE hash = ?1 AND file_size_bytes = ?2 AND content_hash IS NOT NULL",
+    )?;
+
+    stmt.query_map((hash, file_size_bytes), |row| row.get(0))?
+        .collect()
+}
+
+/// Stores what reading a location back found: `ok` also moves its last verified time
+/// to now, `content_hash` is what the file should hash to, when known
+pub fn record_location_check(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+    content_hash: Option<&str>,
+    verify_status: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE file_locations
+            SET content_hash = COALESCE(?3, content_hash), verify_status = ?4,
+                last_verified_at = CASE WHEN ?4 = 'ok' THEN strftime('%s', 'now') ELSE last_verified_at END
+          WHERE device_id = ?1 AND relative_path = ?2",
+        (device_id, relative_path, content_hash, verify_status),
+    )?;
+
+    Ok(())
+}
+
+/// What scrub last found the copy at this path to be: `ok`, `damaged` or `missing`
+pub fn location_status(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT verify_status FROM file_locations WHERE device_id = ?1 AND relative_path = ?2",
+        (device_id, relative_path),
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+/// What the copy at this path hashes to, if it last read back intact at this size
+pub fn verified_content_hash(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT content_hash FROM file_locations
+          WHERE device_id = ?1 AND relative_path = ?2 AND file_size_bytes = ?3 AND verify_status = 'ok'",
+        (device_id, relative_path, file_size_bytes),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Replaces the parity set recorded for `folder` (from the device root) and the files
+/// it covers, as `(relative_path, file_size_bytes, content_hash)`
+pub fn replace_parity_set(
+    conn: &Connection,
+    device_id: i64,
+    folder: &str,
+    parity_bytes: i64,
+    slice_size: i64,
+    redundancy_percent: i64,
+    files: &[(String, i64, String)],
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+
+    tx.execute(
+        "DELETE FROM parity_sets WHERE device_id = ?1 AND folder = ?2",
+        (device_id, folder),
+    )?;
+
+    let set_id: i64 = tx.query_row(
+        "INSERT INTO parity_sets (device_id, folder, file_count, data_bytes, parity_bytes, slice_size, redundancy_percent)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id",
+        rusqlite::params![
+            device_id,
+            folder,
+            files.len() as i64,
+            files.iter().map(|(_, size, _)| size).sum::<i64>(),
+            parity_bytes,
+            slice_size,
+            redundancy_percent
+        ],
+        |row| row.get(0),
+    )?;
+
+    for (relative_path, file_size_bytes, content_hash) in files {
+        tx.execute(
+            "INSERT INTO parity_files (set_id, relative_path, file_size_bytes, content_hash)
+             VALUES (?1, ?2, ?3, ?4)",
+            (set_id, relative_path, file_size_bytes, content_hash),
+        )?;
+    }
+
+    tx.commit()
+}
+
+/// The parity set of `folder` and the files it covers. `None` if the folder has none.
+pub fn parity_set_files(
+    conn: &Connection,
+    device_id: i64,
+    folder: &str,
+) -> rusqlite::Result<Option<ParitySetRow>> {
+    let Some((set_id, redundancy_percent)) = conn
+        .query_row(
+            "SELECT id, redundancy_percent FROM parity_sets WHERE device_id = ?1 AND folder = ?2",
+            (device_id, folder),
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT relative_path, file_size_bytes FROM parity_files
+          WHERE set_id = ?1 ORDER BY relative_path",
+    )?;
+
+    let files = stmt
+        .query_map([set_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    Ok(Some(ParitySetRow {
+        redundancy_percent,
+        files,
+    }))
+}
+
+/// Folder (from the device root) of the parity set covering a file, if any
+pub fn parity_folder_for(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT s.folder FROM parity_files f
+           JOIN parity_sets s ON s.id = f.set_id
+          WHERE s.device_id = ?1 AND f.relative_path = ?2",
+        (device_id, relative_path),
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Whether watch took in a file of this size from this path before
+pub fn inbox_file_ingested(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM inbox_files
+                         WHERE device_id = ?1 AND relative_path = ?2 AND file_size_bytes = ?3)",
+        (device_id, relative_path, file_size_bytes),
+        |row| row.get(0),
+    )
+}
+
+pub fn upsert_inbox_file(
+    conn: &Connection,
+    device_id: i64,
+    relative_path: &str,
+    file_size_bytes: i64,
+    session_id: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO inbox_files (device_id, relative_path, file_size_bytes, session_id)
+         VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (device_id, relative_path) DO UPDATE SET
+            file_size_bytes = excluded.file_size_bytes, session_id = excluded.session_id,
+            ingested_at = strftime('%s', 'now')",
+        (device_id, relative_path, file_size_bytes, session_id),
+    )?;
+
+    Ok(())
+}
@@ -1,31 +1,135 @@
+mod commands;
+mod config;
 mod database;
 mod utils;
 
+use std::env;
 use std::io;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+use rusqlite::Connection;
+
+use crate::config::Config;
 use crate::database::connection::Database;
 use crate::database::operations;
 use crate::utils::core::{export_images_to_new_destination, scan_directory};
+use crate::utils::derivatives::link_edited_to_originals;
 use crate::utils::duplicates::{Duplicates, find_duplicates};
+use crate::utils::storage::{LocalBackend, StorageBackend, open_backend};
 
 const ROOT_PROJECT_PATH: &str = "/Users/saujanya/sandisk_media";
 // const ROOT_PROJECT_PATH: &str = "./test_folder";
 fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|a| a.as_str()) {
+        Some("audit") => commands::audit::run(open_database().conn(), &args[1..]),
+        Some("tag") => commands::tags::run(open_database().conn(), &args[1..]),
+        Some("devices") => commands::devices::run(open_database().conn(), &args[1..]),
+        Some("duplicates") => commands::duplicates::run(open_database().conn(), &args[1..]),
+        Some("import") => commands::import::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?.layout,
+        ),
+        Some("relations") => commands::relations::run(open_database().conn()),
+        Some("stacks") => commands::stacks::run(open_database().conn(), &args[1..]),
+        Some("sync") => commands::sync::run(open_database().conn(), &args[1..]),
+        Some("storage") => commands::storage::run(&args[1..], &Config::load(config_path())?),
+        Some("near-dups") => {
+            let config = Config::load(config_path())?;
+            commands::near_duplicates::run(
+                open_database().conn(),
+                &args[1..],
+                &config.near_duplicates,
+                &config.duplicates,
+            )
+        }
+        Some("cleanup") => {
+            let config = Config::load(config_path())?;
+            commands::cleanup::run(
+                open_database().conn(),
+                &args[1..],
+                &config.cleanup,
+                &config.duplicates,
+            )
+        }
+        Some("cloud-backup") => commands::cloud_backup::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?,
+        ),
+        Some("cloud-restore") => {
+            commands::cloud_backup::run_restore(&args[1..], &Config::load(config_path())?)
+        }
+        Some("restore") => commands::restore::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?,
+        ),
+        Some("scrub") => commands::scrub::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?,
+        ),
+        Some("checksums") => commands::checksums::run(open_database().conn(), &args[1..]),
+        Some("verify-manifest") => commands::checksums::run_verify(&args[1..]),
+        Some("parity") => commands::parity::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?,
+        ),
+        Some("watch") => commands::watch::run(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?,
+        ),
+        Some("undo") => commands::cleanup::run_undo(open_database().conn(), &args[1..]),
+        Some("purge") => commands::cleanup::run_purge(
+            open_database().conn(),
+            &args[1..],
+            &Config::load(config_path())?.cleanup,
+        ),
+        _ => run_backup(),
+    }
+}
+
+fn database_path() -> String {
+    format!("{}/.photo_app_rs/sqlite.db", ".")
+}
+
+fn config_path() -> String {
+    format!("{}/.photo_app_rs/config.toml", ".")
+}
+
+fn open_database() -> Database {
+    Database::new(database_path()).unwrap_or_else(|e| {
+        panic!("Error connecting to database: {}", e);
+    })
+}
+
+fn run_backup() -> io::Result<()> {
     let source_path = ROOT_PROJECT_PATH;
 
     let destination_path = format!("{}/final_export", ROOT_PROJECT_PATH);
 
-    let db_path = format!("{}/.photo_app_rs/sqlite.db", ".");
-    let db = Database::new(&db_path).unwrap_or_else(|e| {
-        panic!("Error connecting to database: {}", e);
-    });
+    let db_path = database_path();
+    let db = open_database();
+
+    let config = Config::load(config_path())?;
+
+    // Final paths are planned under destination_path either way, a backend from
+    // [export] stands in for it
+    let storage: Box<dyn StorageBackend> = match config.export.storage.as_str() {
+        "" => Box::new(LocalBackend::new(&destination_path)),
+        name => open_backend(name, &config)?,
+    };
 
     let backup_session_id = database::operations::new_backup_session(
         &db.conn(),
         source_path.as_ref(),
-        destination_path.as_ref(),
+        storage.name().as_ref(),
     )
     .unwrap();
 
@@ -60,15 +164,32 @@ fn main() -> io::Result<()> {
 
     let media_items = scan_directory(&db.conn(), Path::new(source_path))?;
 
-    let duplicates = find_duplicates(&db.conn(), media_items, Path::new(&destination_path))?;
+    link_edited_to_originals(db.conn(), &media_items).unwrap_or_else(|e| {
+        println!("Error linking edited exports to originals; Error : {:?}", e);
+    });
+
+    let duplicates = find_duplicates(media_items, Path::new(&destination_path), &config)?;
+
+    operations::replace_duplicate_groups(db.conn(), backup_session_id, &duplicates).unwrap_or_else(
+        |e| {
+            println!(
+                "Error storing duplicate groups to database; Session : {:?}; Error : {:?}",
+                backup_session_id, e
+            );
+        },
+    );
 
-    export_to_csv(duplicates.clone(), "./csv_exports/final_export.csv")?;
+    export_to_csv(
+        db.conn(),
+        duplicates.clone(),
+        "./csv_exports/final_export.csv",
+    )?;
 
     let waste_space = calculate_waste_space(duplicates.clone());
 
     println!("Waste Space : {:?}", format_size(waste_space));
 
-    export_images_to_new_destination(duplicates)?;
+    export_images_to_new_destination(storage.as_ref(), Path::new(&destination_path), duplicates)?;
 
     // Clear the session_id from shared state since we're completing normally
     *session_id_shared.lock().unwrap() = None;
@@ -88,7 +209,7 @@ fn calculate_waste_space(data: Vec<Duplicates>) -> u64 {
     total_waste_space
 }
 
-fn export_to_csv(data: Vec<Duplicates>, output_path: &str) -> io::Result<()> {
+fn export_to_csv(conn: &Connection, data: Vec<Duplicates>, output_path: &str) -> io::Result<()> {
     let mut wrt =
         csv::Writer::from_path(output_path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
@@ -110,6 +231,8 @@ fn export_to_csv(data: Vec<Duplicates>, output_path: &str) -> io::Result<()> {
         "Shutter Speed",
         "Focal Length",
         "Software",
+        "Derived From",
+        "Kept Copy Reason",
     ])
     .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
 
@@ -125,6 +248,9 @@ fn export_to_csv(data: Vec<Duplicates>, output_path: &str) -> io::Result<()> {
             .join(";");
         let file_size_str = media.file_size.to_string();
         let file_size_human = format_size(media.file_size);
+        let derived_from = operations::derived_from_path(conn, &media.hash)
+            .unwrap_or(None)
+            .unwrap_or_default();
 
         wrt.write_record(&[
             &media.hash,
@@ -198,6 +324,8 @@ fn export_to_csv(data: Vec<Duplicates>, output_path: &str) -> io::Result<()> {
                 .and_then(|e| e.software.as_ref())
                 .map(|s| s.as_str())
                 .unwrap_or(""),
+            &derived_from,
+            &media.keep_reason,
         ])
         .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
     }
@@ -0,0 +1,167 @@
+use chrono::NaiveDateTime;
+use rusqlite::Connection;
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    database::operations,
+    utils::{
+        core::{Media, Rendition},
+        duplicates::Duplicates,
+    },
+};
+
+/// Max difference in capture time for two files to count as the same shot
+const PAIRING_TOLERANCE_SECONDS: i64 = 2;
+
+/// Groups files of one folder into logical assets by setting the same `asset_key` on them:
+/// Live Photos by their shared ContentIdentifier, Android motion photos on their own, then
+/// files that share a basename and capture time (`DSC0001.ARW` + `DSC0001.JPG`).
+pub fn group_renditions(media_items: &mut [Media]) {
+    for media in media_items.iter_mut() {
+        if let Some(content_identifier) = &media.content_identifier {
+            media.asset_key = Some(format!("live:{}", content_identifier));
+        } else if media.motion_photo {
+            media.asset_key = Some(format!("motion:{}", media.hash));
+        }
+    }
+
+    let mut by_stem: HashMap<String, Vec<usize>> = HashMap::new();
+
+    for (index, media) in media_items.iter().enumerate() {
+        if media.asset_key.is_some() {
+            continue;
+        }
+
+        let stem = media
+            .file_path
+            .with_extension("")
+            .to_str()
+            .unwrap_or("")
+            .to_lowercase();
+
+        by_stem.entry(stem).or_default().push(index);
+    }
+
+    for (stem, mut indexes) in by_stem {
+        if indexes.len() < 2 {
+            continue;
+        }
+
+        indexes.sort_by_key(|&i| capture_time(&media_items[i]));
+
+        // Split the basename group wherever the capture times drift apart
+        let mut clusters: Vec<Vec<usize>> = Vec::new();
+        for index in indexes {
+            let time = capture_time(&media_items[index]);
+
+            let joins_last = clusters.last().is_some_and(|cluster| {
+                let last_time = capture_time(&media_items[*cluster.last().unwrap()]);
+                match (last_time, time) {
+                    (Some(a), Some(b)) => (b - a).num_seconds().abs() <= PAIRING_TOLERANCE_SECONDS,
+                    _ => true,
+                }
+            });
+
+            if joins_last {
+                clusters.last_mut().unwrap().push(index);
+            } else {
+                clusters.push(vec![index]);
+            }
+        }
+
+        for cluster in clusters.into_iter().filter(|c| c.len() > 1) {
+            let time = cluster
+                .iter()
+                .find_map(|&i| media_date_taken(&media_items[i]))
+                .unwrap_or_default();
+            let asset_key = format!("{}@{}", stem, time);
+
+            for index in cluster {
+                media_items[index].asset_key = Some(asset_key.clone());
+            }
+        }
+    }
+}
+
+/// Kind of a grouped asset, derived from the files it holds
+pub fn asset_kind(members: &[&Media]) -> &'static str {
+    let has = |r: Rendition| members.iter().any(|m| m.rendition == r);
+
+    if members.iter().any(|m| m.motion_photo) {
+        "motion_photo"
+    } else if members.iter().any(|m| m.content_identifier.is_some()) {
+        "live_photo"
+    } else if has(Rendition::Raw) {
+        "raw_jpeg"
+    } else if has(Rendition::Video) {
+        "photo_video"
+    } else {
+        "renditions"
+    }
+}
+
+/// Stores each grouped asset and links its media files to it
+pub fn store_assets(conn: &Connection, media_items: &[Media]) -> rusqlite::Result<()> {
+    let mut assets: HashMap<&str, Vec<&Media>> = HashMap::new();
+
+    for media in media_items {
+        if let Some(asset_key) = &media.asset_key {
+            assets.entry(asset_key).or_default().push(media);
+        }
+    }
+
+    for (asset_key, members) in assets {
+        let date_taken = members.iter().find_map(|m| media_date_taken(m));
+        let hashes = members.iter().map(|m| m.hash.as_str()).collect::<Vec<_>>();
+
+        operations::insert_asset(conn, asset_key, asset_kind(&members), date_taken, &hashes)?;
+    }
+
+    Ok(())
+}
+
+/// Moves the video of a Live Photo (or any still + video asset) into the folder of its still,
+/// so the pair is not split between the image and video folders. Their capture dates can
+/// even differ, the video's creation date is UTC while the still's is local time.
+pub fn keep_live_photos_together(duplicates: &mut [Duplicates]) {
+    let mut still_folders: HashMap<String, PathBuf> = HashMap::new();
+
+    for duplicate in duplicates.iter() {
+        let media = &duplicate.media;
+        if media.file_type.is_video() {
+            continue;
+        }
+
+        if let (Some(asset_key), Some(folder)) = (&media.asset_key, duplicate.final_path.parent()) {
+            still_folders.insert(asset_key.clone(), folder.to_path_buf());
+        }
+    }
+
+    for duplicate in duplicates.iter_mut() {
+        if !duplicate.media.file_type.is_video() {
+            continue;
+        }
+
+        let Some(folder) = duplicate
+            .media
+            .asset_key
+            .as_ref()
+            .and_then(|key| still_folders.get(key))
+        else {
+            continue;
+        };
+
+        duplicate.final_path = folder.join(&duplicate.media.file_name);
+    }
+}
+
+fn media_date_taken(media: &Media) -> Option<&str> {
+    media
+        .exif_data
+        .as_ref()
+        .and_then(|e| e.date_taken.as_deref())
+}
+
+fn capture_time(media: &Media) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(media_date_taken(media)?, "%Y-%m-%d %H:%M:%S").ok()
+}
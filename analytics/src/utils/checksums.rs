@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::utils::duplicates::calculate_full_hash;
+
+/// Hash of a checksum manifest, told apart by the manifest's file name as both are
+/// 64 hex characters
+#[derive(Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    /// What `sha256sum` / `b3sum` output is conventionally saved as
+    pub fn manifest_name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+            Algorithm::Blake3 => "B3SUMS",
+        }
+    }
+
+    /// `SHA256SUMS`, `B3SUMS`, and `*.sha256` / `*.b3` as other tools name them
+    pub fn from_manifest_name(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+        if name.eq_ignore_ascii_case("SHA256SUMS") || extension.eq_ignore_ascii_case("sha256") {
+            Some(Algorithm::Sha256)
+        } else if name.eq_ignore_ascii_case("B3SUMS") || extension.eq_ignore_ascii_case("b3") {
+            Some(Algorithm::Blake3)
+        } else {
+            None
+        }
+    }
+
+    pub fn hash_file(self, path: &Path) -> io::Result<String> {
+        match self {
+            Algorithm::Sha256 => calculate_full_hash(path),
+            Algorithm::Blake3 => {
+                let mut file = File::open(path)?;
+                let mut hasher = blake3::Hasher::new();
+                let mut buffer = vec![0u8; 1024 * 1024];
+
+                loop {
+                    let read = file.read(&mut buffer)?;
+                    if read == 0 {
+                        break;
+                    }
+                    hasher.update(&buffer[..read]);
+                }
+
+                Ok(hasher.finalize().to_hex().to_string())
+            }
+        }
+    }
+}
+
+/// A line as `sha256sum` and `b3sum` write it: `<hash>  <path>`. Names with a newline
+/// or backslash are escaped and the line starts with `\`, like GNU coreutils does.
+pub fn format_line(hash: &str, path: &str) -> String {
+    if path.contains(['\\', '\n', '\r']) {
+        let escaped = path
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+
+        format!("\\{}  {}\n", hash, escaped)
+    } else {
+        format!("{}  {}\n", hash, path)
+    }
+}
+
+/// Hash and path of a manifest line, in the format above (`*` marks binary mode and
+/// is ignored) or the BSD `SHA256 (<path>) = <hash>` one. `None` for lines that are
+/// neither.
+pub fn parse_line(line: &str) -> Option<(String, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+
+    let bsd = ["SHA256 (", "BLAKE3 ("]
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix));
+
+    let (hash, path) = match bsd {
+        Some(rest) => {
+            let (path, hash) = rest.rsplit_once(") = ")?;
+            (hash, path)
+        }
+        None => {
+            let (hash, rest) = line.split_once(' ')?;
+            (hash, rest.strip_prefix([' ', '*'])?)
+        }
+    };
+
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let path = if escaped {
+        unescape(path)
+    } else {
+        path.to_string()
+    };
+
+    Some((hash.to_ascii_lowercase(), path))
+}
+
+fn unescape(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
@@ -3,19 +3,64 @@ use media_info::VideoInfo;
 use rusqlite::Connection;
 use serde::Serialize;
 use std::{
+    collections::BTreeMap,
     fs::{self, File},
-    io::{self, BufReader},
+    io::{self, BufReader, Read},
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
 };
 
 use crate::{
     database::operations,
-    utils::duplicates::{Duplicates, calculate_hash},
+    utils::{
+        assets::{group_renditions, store_assets},
+        devices::{Device, register_device},
+        duplicates::{Duplicates, calculate_full_hash, calculate_hash},
+        embedded::{read_embedded, xmp_derived_from},
+        live_photos::{content_identifier, is_motion_photo},
+        sidecar::{Sidecar, is_sidecar},
+        stacks::store_stacks,
+        storage::{StorageBackend, key_under, put_verified},
+        tags::collect_keywords,
+    },
 };
 
 // pub fn scan_directory(path: &Path, media_items: &mut Vec<Media>) -> io::Result<()> {
 pub fn scan_directory(conn: &Connection, source_path: &Path) -> io::Result<Vec<Media>> {
+    scan_directory_skipping(conn, source_path, &[])
+}
+
+/// Like `scan_directory`, leaving out the `skipped` folders and everything below them
+pub fn scan_directory_skipping(
+    conn: &Connection,
+    source_path: &Path,
+    skipped: &[PathBuf],
+) -> io::Result<Vec<Media>> {
+    let device = register_device(conn, source_path)?;
+    let volume = fs::metadata(source_path)?.dev();
+
+    // Compared with the folders met on the way, which canonicalize the same way
+    let skipped = skipped
+        .iter()
+        .filter_map(|folder| fs::canonicalize(folder).ok())
+        .collect::<Vec<_>>();
+
+    scan_folder(conn, &device, volume, source_path, &skipped)
+}
+
+/// Scans one folder and everything below it. `volume` is the st_dev of `device`; a
+/// subfolder on another one is a volume mounted inside, registered on its own.
+fn scan_folder(
+    conn: &Connection,
+    device: &Device,
+    volume: u64,
+    source_path: &Path,
+    skipped: &[PathBuf],
+) -> io::Result<Vec<Media>> {
     let mut media_items: Vec<Media> = Vec::new();
+    // Media directly inside this folder, RAW + JPEG pairs never span folders
+    let mut folder_media: Vec<Media> = Vec::new();
 
     println!("Scanning dir");
 
@@ -24,43 +69,151 @@ pub fn scan_directory(conn: &Connection, source_path: &Path) -> io::Result<Vec<M
         let path = entry.path();
 
         if path.is_dir() {
-            let mut new_scan: Vec<Media> = scan_directory(conn, &path)?;
+            if !skipped.is_empty()
+                && fs::canonicalize(&path).is_ok_and(|folder| skipped.contains(&folder))
+            {
+                println!("Skipping {:?}", path);
+                continue;
+            }
+
+            let mut new_scan: Vec<Media> = if fs::metadata(&path)?.dev() == volume {
+                scan_folder(conn, device, volume, &path, skipped)?
+            } else {
+                scan_directory_skipping(conn, &path, skipped)?
+            };
             media_items.append(&mut new_scan);
         } else {
             let file_type = FileType::from_path(&path).unwrap();
 
-            if file_type.is_image() || file_type.is_video() {
-                match Media::new(&path) {
-                    Ok(media) => {
-                        println!("Scanned Media {:?}", media.file_name);
-
-                        operations::insert_media_file(conn, &media).unwrap_or_else(|e| {
-                            println!(
-                                "Error inserting media to database; Path : {:?}; Error : {:?}",
-                                path.to_str(),
-                                e
-                            );
-                        });
-
-                        media_items.push(media);
-                    }
-                    Err(e) => {
-                        println!(
-                            "Error while creating; Path : {:?}; Error : {:?}",
-                            path.to_str(),
-                            e
-                        )
-                    }
-                }
+            if (file_type.is_image() || file_type.is_video())
+                && let Some(media) = scan_file(conn, device, &path)
+            {
+                folder_media.push(media);
             }
         }
     }
 
+    store_folder_media(conn, source_path, &mut folder_media);
+
+    media_items.append(&mut folder_media);
+
     println!("Done media scan");
 
     Ok(media_items)
 }
 
+/// Scans only `paths`, files that turned up since the folders they are in were last
+/// scanned. They are grouped by folder like `scan_directory` does, so a RAW + JPEG pair
+/// or a burst arriving together is still found.
+pub fn scan_files(conn: &Connection, paths: &[PathBuf]) -> io::Result<Vec<Media>> {
+    let mut folders = BTreeMap::<&Path, Vec<&Path>>::new();
+    for path in paths {
+        folders
+            .entry(path.parent().unwrap_or(Path::new(".")))
+            .or_default()
+            .push(path);
+    }
+
+    let mut media_items = Vec::new();
+
+    for (folder, paths) in folders {
+        let device = register_device(conn, folder)?;
+
+        let mut folder_media = paths
+            .into_iter()
+            .filter_map(|path| scan_file(conn, &device, path))
+            .collect::<Vec<_>>();
+
+        store_folder_media(conn, folder, &mut folder_media);
+
+        media_items.append(&mut folder_media);
+    }
+
+    Ok(media_items)
+}
+
+/// Reads a photo or video and stores it, where it is and its keywords in the catalog
+fn scan_file(conn: &Connection, device: &Device, path: &Path) -> Option<Media> {
+    match Media::new(path) {
+        Ok(media) => {
+            println!("Scanned Media {:?}", media.file_name);
+
+            operations::insert_media_file(conn, &media).unwrap_or_else(|e| {
+                println!(
+                    "Error inserting media to database; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+            });
+
+            let relative_path = device.relative_path(path);
+
+            operations::set_media_file_device(
+                conn,
+                &media.hash,
+                path.to_str().unwrap_or(""),
+                device.id,
+                &relative_path,
+            )
+            .and_then(|_| {
+                operations::upsert_file_location(
+                    conn,
+                    &media.hash,
+                    device.id,
+                    &relative_path,
+                    media.file_size as i64,
+                )
+            })
+            .unwrap_or_else(|e| {
+                println!(
+                    "Error inserting file location to database; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+            });
+
+            operations::insert_media_keywords(conn, &media).unwrap_or_else(|e| {
+                println!(
+                    "Error inserting keywords to database; Path : {:?}; Error : {:?}",
+                    path.to_str(),
+                    e
+                );
+            });
+
+            Some(media)
+        }
+        Err(e) => {
+            println!(
+                "Error while creating; Path : {:?}; Error : {:?}",
+                path.to_str(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Groups the media of one folder into assets and stacks
+fn store_folder_media(conn: &Connection, folder: &Path, folder_media: &mut [Media]) {
+    group_renditions(folder_media);
+
+    store_assets(conn, folder_media).unwrap_or_else(|e| {
+        println!(
+            "Error inserting assets to database; Path : {:?}; Error : {:?}",
+            folder.to_str(),
+            e
+        );
+    });
+
+    store_stacks(conn, folder_media).unwrap_or_else(|e| {
+        println!(
+            "Error inserting stacks to database; Path : {:?}; Error : {:?}",
+            folder.to_str(),
+            e
+        );
+    });
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub enum ImageFormat {
     Jpg,
@@ -86,6 +239,10 @@ impl ImageFormat {
             _ => None,
         }
     }
+
+    pub fn is_raw(&self) -> bool {
+        matches!(self, Self::Arw)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -159,6 +316,59 @@ impl FileType {
     }
 }
 
+/// Software tags written by editors, as opposed to camera firmware
+const EDITING_SOFTWARE: &[&str] = &[
+    "lightroom",
+    "photoshop",
+    "darktable",
+    "capture one",
+    "rawtherapee",
+    "gimp",
+    "luminar",
+    "dxo",
+    "affinity",
+    "snapseed",
+];
+
+/// Role of a file within an asset (a RAW, the camera JPEG next to it, an edited export, a video)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Rendition {
+    Raw,
+    Original,
+    Edited,
+    Video,
+}
+
+impl Rendition {
+    pub fn classify(file_type: &FileType, exif_data: Option<&ExifData>) -> Self {
+        match file_type {
+            FileType::Image(format) if format.is_raw() => Rendition::Raw,
+            FileType::Video(_) => Rendition::Video,
+            _ => {
+                let software = exif_data
+                    .and_then(|e| e.software.as_ref())
+                    .map(|s| s.to_lowercase())
+                    .unwrap_or_default();
+
+                if EDITING_SOFTWARE.iter().any(|e| software.contains(e)) {
+                    Rendition::Edited
+                } else {
+                    Rendition::Original
+                }
+            }
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rendition::Raw => "raw",
+            Rendition::Original => "original",
+            Rendition::Edited => "edited",
+            Rendition::Video => "video",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ExifData {
     pub camera_make: Option<String>,
@@ -170,6 +380,15 @@ pub struct ExifData {
     pub shutter_speed: Option<String>,
     pub focal_length: Option<String>,
     pub software: Option<String>,
+    // Used to match edited exports back to their originals
+    pub date_time_original: Option<String>,
+    pub camera_serial: Option<String>,
+    pub image_unique_id: Option<String>,
+    // Used to find burst / bracket sequences
+    pub exposure_bias: Option<String>,
+    pub exposure_mode: Option<String>,
+    pub subject_distance: Option<String>,
+    pub sub_sec_time: Option<String>,
 }
 
 impl ExifData {
@@ -215,6 +434,27 @@ impl ExifData {
             software: exifreader
                 .get_field(Tag::Software, In::PRIMARY)
                 .map(|f| f.display_value().to_string()),
+            date_time_original: exifreader
+                .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            camera_serial: exifreader
+                .get_field(Tag::BodySerialNumber, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            image_unique_id: exifreader
+                .get_field(Tag::ImageUniqueID, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            exposure_bias: exifreader
+                .get_field(Tag::ExposureBiasValue, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            exposure_mode: exifreader
+                .get_field(Tag::ExposureMode, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            subject_distance: exifreader
+                .get_field(Tag::SubjectDistance, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
+            sub_sec_time: exifreader
+                .get_field(Tag::SubSecTimeOriginal, In::PRIMARY)
+                .map(|f| f.display_value().to_string()),
         })
     }
 
@@ -235,6 +475,13 @@ impl ExifData {
             shutter_speed: None,
             focal_length: None,
             software: None,
+            date_time_original: None,
+            camera_serial: None,
+            image_unique_id: None,
+            exposure_bias: None,
+            exposure_mode: None,
+            subject_distance: None,
+            sub_sec_time: None,
         })
     }
 
@@ -259,6 +506,21 @@ pub struct Media {
     pub file_size: u64,
     pub exif_data: Option<ExifData>,
     pub hash: String,
+    pub sidecar: Option<Sidecar>,
+    pub keywords: Vec<String>,
+    pub rendition: Rendition,
+    /// Set when the file is one rendition of a grouped asset (e.g. RAW + JPEG)
+    pub asset_key: Option<String>,
+    /// Original file name recorded in the XMP of an edited export
+    pub derived_from_name: Option<String>,
+    /// Shared by the HEIC and MOV of an iPhone Live Photo
+    pub content_identifier: Option<String>,
+    /// Android motion photo, the video is embedded in the JPEG
+    pub motion_photo: bool,
+    /// Width x height in pixels, images only
+    pub resolution: Option<(u32, u32)>,
+    /// Name of the panorama sequence this frame belongs to (its first frame's name)
+    pub panorama: Option<String>,
 }
 
 impl Media {
@@ -293,6 +555,31 @@ impl Media {
 
         let hash = calculate_hash(path)?;
 
+        let sidecar = Sidecar::from_media_path(path);
+
+        let embedded = read_embedded(path, &file_type);
+
+        let keywords = collect_keywords(embedded.as_ref(), sidecar.as_ref());
+
+        let derived_from_name = embedded
+            .as_ref()
+            .and_then(|e| e.xmp.as_deref())
+            .and_then(xmp_derived_from);
+
+        let content_identifier = content_identifier(path, &file_type);
+
+        let motion_photo = embedded
+            .as_ref()
+            .and_then(|e| e.xmp.as_deref())
+            .is_some_and(is_motion_photo);
+
+        let rendition = Rendition::classify(&file_type, exif_data.as_ref());
+
+        let resolution = match file_type {
+            FileType::Image(_) => image_resolution(path),
+            _ => None,
+        };
+
         Ok(Media {
             file_path: path.to_path_buf(),
             file_name,
@@ -300,24 +587,168 @@ impl Media {
             file_size,
             exif_data,
             hash,
+            sidecar,
+            keywords,
+            rendition,
+            asset_key: None,
+            derived_from_name,
+            content_identifier,
+            motion_photo,
+            resolution,
+            panorama: None,
         })
     }
 }
 
-pub fn export_images_to_new_destination(data: Vec<Duplicates>) -> io::Result<()> {
-    for media in data {
-        copy_file_upsert_dirs(&media.files[0], &media.final_path)?;
+/// Pixel size from the EXIF PixelX/YDimension tags, falling back to the image header
+fn image_resolution(path: &Path) -> Option<(u32, u32)> {
+    let from_exif = File::open(path).ok().and_then(|file| {
+        let exifreader = Reader::new()
+            .read_from_container(&mut BufReader::new(&file))
+            .ok()?;
+
+        let width = exifreader
+            .get_field(Tag::PixelXDimension, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+        let height = exifreader
+            .get_field(Tag::PixelYDimension, In::PRIMARY)?
+            .value
+            .get_uint(0)?;
+
+        Some((width, height))
+    });
+
+    from_exif.or_else(|| image::image_dimensions(path).ok())
+}
+
+/// Every photo and video under `folder`, recursively
+pub fn collect_media_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_media_files(&path, files)?;
+        } else if FileType::from_path(&path).is_some_and(|t| t.is_image() || t.is_video()) {
+            files.push(path);
+        }
     }
 
     Ok(())
 }
 
-fn copy_file_upsert_dirs(old_path: &PathBuf, final_path: &PathBuf) -> io::Result<()> {
-    if let Some(parent) = final_path.parent() {
+/// A copy of the file already in the library under another name, found through the
+/// locations recorded by earlier scans, imports and `watch` batches
+pub fn library_copy(
+    conn: &Connection,
+    path: &Path,
+    hash: &str,
+    library: &Path,
+) -> io::Result<Option<PathBuf>> {
+    let candidates = operations::paths_with_hash(conn, hash)
+        .map_err(io::Error::other)?
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(library) && candidate.exists())
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    let full_hash = calculate_full_hash(path)?;
+
+    for candidate in candidates {
+        if calculate_full_hash(&candidate)? == full_hash {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Every photo, video and sidecar under `folder`, recursively
+pub fn collect_library_files(folder: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_library_files(&path, files)?;
+        } else if is_sidecar(&path)
+            || FileType::from_path(&path).is_some_and(|t| t.is_image() || t.is_video())
+        {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies through a `.part` file so an interrupted copy never leaves a truncated file
+/// under the final name. Replaces `to` if it exists.
+pub fn copy_file_atomic(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let partial = to.with_file_name(format!(
+        "{}.part",
+        to.file_name().and_then(|n| n.to_str()).unwrap_or("")
+    ));
+
+    fs::copy(from, &partial)?;
+    fs::rename(&partial, to)
+}
+
+/// Sets a file's mtime to `modified_at` (Unix seconds), e.g. after restoring it
+pub fn set_modified_at(path: &Path, modified_at: i64) -> io::Result<()> {
+    File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(UNIX_EPOCH + Duration::from_secs(modified_at.max(0) as u64))
+}
+
+/// Writes what `body` reads (a download) through a `.part` file, like `copy_file_atomic`
+pub fn write_atomic(path: &Path, body: &mut impl Read) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
         fs::create_dir_all(parent)?;
     }
 
-    fs::copy(old_path, final_path)?;
+    let partial = path.with_file_name(format!(
+        "{}.part",
+        path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+    ));
+
+    io::copy(body, &mut File::create(&partial)?)?;
+    fs::rename(&partial, path)
+}
+
+/// Writes the kept copy of every group, and its sidecar, to `storage`. Final paths
+/// are planned under `destination`, which stands for the root of the storage.
+pub fn export_images_to_new_destination(
+    storage: &dyn StorageBackend,
+    destination: &Path,
+    data: Vec<Duplicates>,
+) -> io::Result<()> {
+    for media in data {
+        export_file(storage, destination, &media.files[0], &media.final_path)?;
+
+        // Sidecars travel with their media file and follow its rename
+        if let Some(sidecar) = &media.media.sidecar {
+            let sidecar_final_path = sidecar.final_path_for(&media.files[0], &media.final_path);
+            export_file(storage, destination, &sidecar.path, &sidecar_final_path)?;
+        }
+    }
 
     Ok(())
 }
+
+fn export_file(
+    storage: &dyn StorageBackend,
+    destination: &Path,
+    old_path: &Path,
+    final_path: &Path,
+) -> io::Result<()> {
+    let key = key_under(destination, final_path)?;
+
+    put_verified(storage, &key, old_path, &calculate_full_hash(old_path)?)
+}
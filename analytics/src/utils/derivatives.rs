@@ -0,0 +1,37 @@
+use rusqlite::Connection;
+
+use crate::{
+    database::operations,
+    utils::core::{Media, Rendition},
+};
+
+/// Records a `derived_from` relation for every edited export in `media_items`
+/// whose original can be found in the catalog (from this scan or an earlier one)
+pub fn link_edited_to_originals(conn: &Connection, media_items: &[Media]) -> rusqlite::Result<()> {
+    for media in media_items
+        .iter()
+        .filter(|m| m.rendition == Rendition::Edited)
+    {
+        let media_file_id = operations::media_file_id_by_hash(conn, &media.hash)?;
+
+        match operations::find_original_media_file(conn, media_file_id)? {
+            Some((original_id, matched_by)) => {
+                println!(
+                    "Linked edited {:?} to its original (by {})",
+                    media.file_name, matched_by
+                );
+
+                operations::insert_media_relation(
+                    conn,
+                    media_file_id,
+                    original_id,
+                    "derived_from",
+                    matched_by,
+                )?;
+            }
+            None => println!("No original found for edited {:?}", media.file_name),
+        }
+    }
+
+    Ok(())
+}
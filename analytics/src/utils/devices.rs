@@ -0,0 +1,165 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    database::{models::DeviceRow, operations},
+    utils::volume::{device_label, is_removable, mount_point, volume_uuid},
+};
+
+/// Written to the root of removable volumes the catalog has files on, and of volumes
+/// registered with `devices register`. The id in it follows the disk to any host and mount
+/// point, including macOS where there is no /dev/disk/by-uuid to read.
+pub const MARKER_FILE: &str = ".photo_app_rs_device";
+
+/// How the volume holding a path identifies itself
+pub struct VolumeIdentity {
+    pub marker_id: Option<String>,
+    pub volume_uuid: Option<String>,
+    pub label: String,
+    pub mount_point: PathBuf,
+}
+
+/// A registered device and where it is mounted right now
+pub struct Device {
+    pub id: i64,
+    pub mount_point: PathBuf,
+}
+
+impl Device {
+    /// What the catalog stores for `path`: its path from the root of the device
+    pub fn relative_path(&self, path: &Path) -> String {
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+        path.strip_prefix(&self.mount_point)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Reads the marker, UUID and label of the volume `path` lives on. Writes nothing.
+pub fn identify_volume(path: &Path) -> VolumeIdentity {
+    let mount_point = mount_point(path);
+
+    let marker_id = fs::read_to_string(mount_point.join(MARKER_FILE))
+        .ok()
+        .map(|id| id.trim().to_string())
+        .filter(|id| !id.is_empty());
+
+    VolumeIdentity {
+        marker_id,
+        volume_uuid: volume_uuid(path),
+        label: device_label(path),
+        mount_point,
+    }
+}
+
+/// Finds or records the device `path` lives on, refreshing where it is mounted.
+/// Removable volumes without a marker get one; fixed disks (the system disk, /home, /boot)
+/// are never written to and are known by UUID or label.
+pub fn register_device(conn: &Connection, path: &Path) -> io::Result<Device> {
+    register(conn, path, is_removable(path))
+}
+
+/// Like `register_device`, but writes a marker to any volume, for disks the user says
+/// are theirs to write to
+pub fn register_device_with_marker(conn: &Connection, path: &Path) -> io::Result<Device> {
+    register(conn, path, true)
+}
+
+fn register(conn: &Connection, path: &Path, write_missing_marker: bool) -> io::Result<Device> {
+    let mut volume = identify_volume(path);
+
+    if volume.marker_id.is_none() && write_missing_marker {
+        // Known by UUID or label until now, it keeps its id and files
+        let known = operations::device_id(conn, None, volume.volume_uuid.as_deref(), &volume.label)
+            .map_err(io::Error::other)?;
+
+        match write_marker(&volume.mount_point) {
+            Ok(marker_id) => {
+                if let Some(id) = known {
+                    operations::set_device_marker(conn, id, &marker_id)
+                        .map_err(io::Error::other)?;
+                }
+                volume.marker_id = Some(marker_id);
+            }
+            // Read-only volumes are still known by UUID or label
+            Err(e) => println!(
+                "Error writing device marker; Path : {:?}; Error : {:?}",
+                volume.mount_point.to_str(),
+                e
+            ),
+        }
+    }
+
+    let id = operations::upsert_device(
+        conn,
+        volume.marker_id.as_deref(),
+        volume.volume_uuid.as_deref(),
+        &volume.label,
+        volume.mount_point.to_str().unwrap_or(""),
+    )
+    .map_err(io::Error::other)?;
+
+    Ok(Device {
+        id,
+        mount_point: volume.mount_point,
+    })
+}
+
+/// The registered device `path` lives on, without recording anything
+pub fn find_device(conn: &Connection, path: &Path) -> io::Result<Option<Device>> {
+    let volume = identify_volume(path);
+
+    let id = operations::device_id(
+        conn,
+        volume.marker_id.as_deref(),
+        volume.volume_uuid.as_deref(),
+        &volume.label,
+    )
+    .map_err(io::Error::other)?;
+
+    Ok(id.map(|id| Device {
+        id,
+        mount_point: volume.mount_point,
+    }))
+}
+
+/// Whether the volume at the device's last mount point is still that device
+pub fn is_mounted(device: &DeviceRow) -> bool {
+    let Some(mount_point) = &device.mount_point else {
+        return false;
+    };
+
+    if !Path::new(mount_point).is_dir() {
+        return false;
+    }
+
+    let volume = identify_volume(Path::new(mount_point));
+
+    match (&device.marker_id, &device.volume_uuid) {
+        (Some(marker_id), _) => volume.marker_id.as_ref() == Some(marker_id),
+        (None, Some(volume_uuid)) => volume.volume_uuid.as_ref() == Some(volume_uuid),
+        (None, None) => volume.label == device.label,
+    }
+}
+
+fn write_marker(mount_point: &Path) -> io::Result<String> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+
+    let digest = Sha256::digest(format!("{}{:?}", now, mount_point));
+    let marker_id = format!("{:x}", digest)[..32].to_string();
+
+    fs::write(mount_point.join(MARKER_FILE), format!("{}\n", marker_id))?;
+
+    Ok(marker_id)
+}
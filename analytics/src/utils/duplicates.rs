@@ -6,12 +6,13 @@ use std::{
 };
 
 use chrono::{Datelike, NaiveDateTime};
-use rusqlite::Connection;
 use serde::Serialize;
 use sha2::{Digest, Sha256};
 
-use crate::database::operations;
-use crate::utils::core::{ExifData, FileType, Media};
+use crate::config::{Config, LayoutConfig};
+use crate::utils::assets::keep_live_photos_together;
+use crate::utils::core::{ExifData, FileType, Media, Rendition};
+use crate::utils::ranking::rank_copies;
 
 const PARTIAL_HASH_SIZE: usize = 128 * 1024; // 128 KB
 
@@ -24,14 +25,17 @@ pub struct Duplicates {
     pub file_type: FileType,
     pub file_size: u64,
     pub exif_data: Option<ExifData>,
+    /// The kept copy, `files[0]`
     pub media: Media,
     pub final_path: PathBuf,
+    /// Ranking rule that picked the kept copy over the others
+    pub keep_reason: String,
 }
 
 pub fn find_duplicates(
-    conn: &Connection,
     data: Vec<Media>,
     destination_path: &Path,
+    config: &Config,
 ) -> io::Result<Vec<Duplicates>> {
     let mut hash_map: HashMap<String, Vec<Media>> = HashMap::new();
 
@@ -45,23 +49,17 @@ pub fn find_duplicates(
 
     println!("Preparing duplicate files data");
 
-    let duplicate_files = hash_map
+    let mut duplicate_files = hash_map
         .into_iter()
-        // .filter(|(_, files)| files.len() > 1)
-        .map(|(hash, media_files)| {
+        .map(|(hash, mut media_files)| {
+            // Best copy first, it is the one exported and read for EXIF
+            let keep_reason = rank_copies(&mut media_files, &config.duplicates);
+
             let metadata = fs::metadata(&media_files[0].file_path)?;
             let file_size = metadata.len();
 
             println!("Duplcate File : {:?}", media_files[0].file_path);
 
-            // Insert duplicate group data into database
-            operations::insert_duplicate_group(conn, &media_files[0]).unwrap_or_else(|e| {
-                println!(
-                    "Error inserting duplicate group to database; Hash : {:?}; Error : {:?}",
-                    media_files[0].hash, e
-                );
-            });
-
             Ok(Duplicates {
                 hash,
                 count: media_files.len(),
@@ -74,17 +72,21 @@ pub fn find_duplicates(
                 final_path: PathBuf::from(final_path_for_media(
                     media_files[0].clone(),
                     destination_path.to_str().unwrap(),
+                    &config.layout,
                 )),
+                keep_reason,
             })
         })
-        .collect::<io::Result<Vec<Duplicates>>>();
+        .collect::<io::Result<Vec<Duplicates>>>()?;
+
+    keep_live_photos_together(&mut duplicate_files);
 
     println!("Done Duplicate data");
 
-    return duplicate_files;
+    Ok(duplicate_files)
 }
 
-fn final_path_for_media(media: Media, destination_path: &str) -> String {
+pub fn final_path_for_media(media: Media, destination_path: &str, layout: &LayoutConfig) -> String {
     let date_taken = media
         .exif_data
         .as_ref()
@@ -99,14 +101,23 @@ fn final_path_for_media(media: Media, destination_path: &str) -> String {
     let month = formatted_date.month();
     let day_of_month = formatted_date.day();
 
+    // RAWs, camera JPEGs and edited exports go to sibling folders
+    let rendition_folder = match media.rendition {
+        Rendition::Raw => &layout.raw_folder,
+        Rendition::Original => &layout.original_folder,
+        Rendition::Edited => &layout.edited_folder,
+        Rendition::Video => &layout.video_folder,
+    };
+
+    // Frames of a panorama stay together, whatever their rendition
+    let folder = match &media.panorama {
+        Some(panorama) => format!("{}/{}", layout.panorama_folder, panorama),
+        None => rendition_folder.to_string(),
+    };
+
     format!(
         "{}/{}/{}/{}/{}/{}",
-        destination_path,
-        year,
-        month,
-        day_of_month,
-        media.file_type.to_string(),
-        media.file_name
+        destination_path, year, month, day_of_month, folder, media.file_name
     )
 }
 
@@ -121,3 +132,14 @@ pub fn calculate_hash(path: &Path) -> io::Result<String> {
 
     Ok(format!("{:x}", hasher.finalize()))
 }
+
+/// SHA-256 of the whole file. `calculate_hash` only reads the first 128 KB, which is
+/// enough to group copies but not to decide that one of them can go.
+pub fn calculate_full_hash(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+
+    io::copy(&mut file, &mut hasher)?;
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
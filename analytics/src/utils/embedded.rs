@@ -0,0 +1,288 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+};
+
+use crate::utils::{
+    core::{FileType, ImageFormat},
+    sidecar::xmp_value,
+};
+
+const XMP_SIGNATURE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PHOTOSHOP_SIGNATURE: &[u8] = b"Photoshop 3.0\0";
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+const IPTC_KEYWORDS: (u8, u8) = (2, 25);
+
+/// Metadata blocks embedded in the APP segments of a JPEG
+#[derive(Debug, Default)]
+pub struct JpegSegments {
+    pub xmp: Option<String>,
+    pub iptc: Option<Vec<u8>>,
+}
+
+/// Embedded metadata blocks for JPEGs, `None` for every other file type
+pub fn read_embedded(path: &Path, file_type: &FileType) -> Option<JpegSegments> {
+    if !matches!(
+        file_type,
+        FileType::Image(ImageFormat::Jpg) | FileType::Image(ImageFormat::Jpeg)
+    ) {
+        return None;
+    }
+
+    match read_jpeg_segments(path) {
+        Ok(segments) => Some(segments),
+        Err(e) => {
+            println!(
+                "Error reading embedded metadata; Path : {:?}; Error : {:?}",
+                path.to_str(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// File name of the original an edited export was made from, as recorded by
+/// Lightroom (`crs:RawFileName`), `xmpMM:PreservedFileName` or `xmpMM:DerivedFrom`
+pub fn xmp_derived_from(xmp: &str) -> Option<String> {
+    let derived_from_path = xmp_value(xmp, "stRef:filePath").and_then(|p| {
+        Path::new(&p)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .map(|n| n.to_string())
+    });
+
+    xmp_value(xmp, "crs:RawFileName")
+        .or_else(|| xmp_value(xmp, "xmpMM:PreservedFileName"))
+        .or(derived_from_path)
+        .filter(|name| !name.is_empty())
+}
+
+/// Walks the JPEG markers up to the start of scan and collects the XMP (APP1)
+/// and IPTC (APP13) blocks
+pub fn read_jpeg_segments(path: &Path) -> io::Result<JpegSegments> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut segments = JpegSegments::default();
+
+    let mut soi = [0u8; 2];
+    reader.read_exact(&mut soi)?;
+    if soi != [0xFF, 0xD8] {
+        return Ok(segments);
+    }
+
+    loop {
+        let mut marker = [0u8; 2];
+        if reader.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            break;
+        }
+
+        // Start of scan: no more metadata after this point
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            break;
+        }
+
+        let mut len = [0u8; 2];
+        reader.read_exact(&mut len)?;
+        let len = u16::from_be_bytes(len) as usize;
+        if len < 2 {
+            break;
+        }
+
+        let mut data = vec![0u8; len - 2];
+        reader.read_exact(&mut data)?;
+
+        match marker[1] {
+            0xE1 if data.starts_with(XMP_SIGNATURE) => {
+                segments.xmp =
+                    Some(String::from_utf8_lossy(&data[XMP_SIGNATURE.len()..]).to_string());
+            }
+            0xED if data.starts_with(PHOTOSHOP_SIGNATURE) => {
+                segments.iptc =
+                    photoshop_resource(&data[PHOTOSHOP_SIGNATURE.len()..], IPTC_RESOURCE_ID)
+                        .map(|r| r.to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Finds an image resource block (`8BIM`) by id inside a Photoshop APP13 segment
+fn photoshop_resource(mut data: &[u8], resource_id: u16) -> Option<&[u8]> {
+    while data.len() >= 12 && data.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([data[4], data[5]]);
+
+        // Pascal string name, padded so that length byte + name is even
+        let name_len = data[6] as usize;
+        let name_total = (name_len + 1 + 1) & !1;
+        let size_offset = 6 + name_total;
+
+        let size_bytes = data.get(size_offset..size_offset + 4)?;
+        let size = u32::from_be_bytes(size_bytes.try_into().ok()?) as usize;
+
+        let start = size_offset + 4;
+        let block = data.get(start..start + size)?;
+
+        if id == resource_id {
+            return Some(block);
+        }
+
+        let next = start + size + (size & 1);
+        data = data.get(next..)?;
+    }
+
+    None
+}
+
+/// Reads the keyword datasets (2:25) from an IPTC-IIM block
+pub fn iptc_keywords(iptc: &[u8]) -> Vec<String> {
+    let mut keywords = Vec::new();
+    let mut offset = 0;
+
+    while offset + 5 <= iptc.len() && iptc[offset] == 0x1C {
+        let record = iptc[offset + 1];
+        let dataset = iptc[offset + 2];
+        let size = u16::from_be_bytes([iptc[offset + 3], iptc[offset + 4]]) as usize;
+
+        // Extended datasets (high bit set) are never used for keywords
+        if size & 0x8000 != 0 {
+            break;
+        }
+
+        let start = offset + 5;
+        let Some(value) = iptc.get(start..start + size) else {
+            break;
+        };
+
+        if (record, dataset) == IPTC_KEYWORDS {
+            let keyword = String::from_utf8_lossy(value).trim().to_string();
+            if !keyword.is_empty() {
+                keywords.push(keyword);
+            }
+        }
+
+        offset = start + size;
+    }
+
+    keywords
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn dataset(record: u8, dataset: u8, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x1C, record, dataset];
+        bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn resource(id: u16, name: &[u8], block: &[u8]) -> Vec<u8> {
+        let mut bytes = b"8BIM".to_vec();
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name);
+        if (name.len() + 1) % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(block);
+        if block.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_keyword_datasets_only() {
+        let iptc = [
+            dataset(1, 90, b"\x1b%G"),
+            dataset(2, 25, b"japan"),
+            dataset(2, 5, b"Title"),
+            dataset(2, 25, b"  "),
+            dataset(2, 25, b"trips/2025"),
+        ]
+        .concat();
+
+        assert_eq!(iptc_keywords(&iptc), ["japan", "trips/2025"]);
+    }
+
+    #[test]
+    fn stops_at_truncated_or_extended_datasets() {
+        let mut truncated = dataset(2, 25, b"japan");
+        truncated.extend_from_slice(&dataset(2, 25, b"food")[..7]);
+        assert_eq!(iptc_keywords(&truncated), ["japan"]);
+
+        // Says 0xFFFF bytes follow, holds 3
+        let oversized = [0x1C, 2, 25, 0x7F, 0xFF, b'a', b'b', b'c'];
+        assert!(iptc_keywords(&oversized).is_empty());
+
+        let extended = [0x1C, 2, 25, 0x80, 0x04, 0, 0, 0, 1, b'a'];
+        assert!(iptc_keywords(&extended).is_empty());
+
+        assert!(iptc_keywords(&[0x1C, 2]).is_empty());
+        assert!(iptc_keywords(&[]).is_empty());
+    }
+
+    #[test]
+    fn finds_the_iptc_resource_past_others() {
+        let data = [
+            resource(0x03ED, b"", b"resolution"),
+            resource(0x0404, b"IPTC", b"iptc"),
+        ]
+        .concat();
+        assert_eq!(
+            photoshop_resource(&data, IPTC_RESOURCE_ID),
+            Some(&b"iptc"[..])
+        );
+
+        // A block size past the end of the segment
+        let mut oversized = resource(0x0404, b"", b"iptc");
+        oversized[8..12].copy_from_slice(&1000u32.to_be_bytes());
+        assert_eq!(photoshop_resource(&oversized, IPTC_RESOURCE_ID), None);
+
+        assert_eq!(photoshop_resource(b"8BIM\x04\x04", IPTC_RESOURCE_ID), None);
+    }
+
+    #[test]
+    fn reads_segments_up_to_the_start_of_scan() {
+        let path = env::temp_dir().join(format!(
+            "photo_app_rs_test_{}_jpeg_segments.jpg",
+            process::id()
+        ));
+
+        let xmp = [XMP_SIGNATURE, b"<x:xmpmeta>keywords</x:xmpmeta>"].concat();
+        let app13 = [
+            PHOTOSHOP_SIGNATURE,
+            &resource(0x0404, b"", &dataset(2, 25, b"japan")),
+        ]
+        .concat();
+
+        let mut jpeg = vec![0xFF, 0xD8];
+        for (marker, data) in [(0xE1, &xmp), (0xED, &app13)] {
+            jpeg.extend_from_slice(&[0xFF, marker]);
+            jpeg.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+            jpeg.extend_from_slice(data);
+        }
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+        fs::write(&path, &jpeg).unwrap();
+
+        let segments = read_jpeg_segments(&path).unwrap();
+        assert_eq!(
+            segments.xmp.as_deref(),
+            Some("<x:xmpmeta>keywords</x:xmpmeta>")
+        );
+        assert_eq!(iptc_keywords(&segments.iptc.unwrap()), ["japan"]);
+
+        // Cut inside the APP13 segment
+        fs::write(&path, &jpeg[..jpeg.len() - 10]).unwrap();
+        assert!(read_jpeg_segments(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,619 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20::{
+    XChaCha20,
+    cipher::{KeyIvInit, StreamCipher},
+};
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::EncryptedConfig,
+    utils::{
+        duplicates::calculate_full_hash,
+        s3::{hex, hmac_sha256},
+        storage::{StorageBackend, temp_path},
+    },
+};
+
+/// Salt and Argon2 parameters, stored unencrypted next to the data. Anyone with the
+/// passphrase can derive the key from it on a new machine.
+const KEY_FILE: &str = "photo_app_rs.key";
+
+/// Encrypted objects all live under here, named after their encrypted key
+const DATA_FOLDER: &str = "data";
+
+const MAGIC: &[u8; 8] = b"PARSENC1";
+const CHUNK_SIZE: usize = 1024 * 1024;
+const TAG_SIZE: usize = 16;
+const NONCE_PREFIX_SIZE: usize = 19;
+const SIV_SIZE: usize = 24;
+
+/// Encoded names are split into folders of this length, filesystems cap names at 255 bytes
+const NAME_SEGMENT: usize = 128;
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    version: u32,
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    /// HMAC of a fixed string under the derived key, tells a wrong passphrase apart
+    check: String,
+}
+
+/// Wraps another backend so nothing readable leaves the machine. Content is sealed with
+/// XChaCha20-Poly1305 in 1 MB chunks (the STREAM construction, so chunks can't be
+/// reordered or cut off), bound to its key. Keys are encrypted deterministically
+/// (SIV: the nonce is an HMAC of the key), so the same file still maps to the same
+/// object and dedup works, but the remote never sees a path or a content hash.
+pub struct EncryptedBackend {
+    inner: Box<dyn StorageBackend>,
+    content_key: [u8; 32],
+    name_mac_key: [u8; 32],
+    name_key: [u8; 32],
+}
+
+impl EncryptedBackend {
+    /// Derives the key from the passphrase, writing a new key file with a random salt
+    /// to the backend the first time
+    pub fn new(inner: Box<dyn StorageBackend>, config: &EncryptedConfig) -> io::Result<Self> {
+        let key_file_path = temp_path("key");
+        let existing = match inner.get(KEY_FILE, &key_file_path) {
+            Ok(()) => Some(fs::read_to_string(&key_file_path)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let _ = fs::remove_file(&key_file_path);
+
+        let master_key = match existing {
+            Some(contents) => {
+                let key_file: KeyFile = toml::from_str(&contents?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+                let master_key = derive_key(&passphrase(config, false)?, &key_file)?;
+
+                if hex(&hmac_sha256(&master_key, b"check")) != key_file.check {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("Wrong passphrase for {}", inner.name()),
+                    ));
+                }
+
+                master_key
+            }
+            None => {
+                let passphrase = passphrase(config, true)?;
+
+                let mut salt = [0u8; 16];
+                getrandom::getrandom(&mut salt).map_err(|e| io::Error::other(e.to_string()))?;
+
+                let mut key_file = KeyFile {
+                    version: 1,
+                    salt: hex(&salt),
+                    memory_kib: 64 * 1024,
+                    iterations: 3,
+                    check: String::new(),
+                };
+
+                let master_key = derive_key(&passphrase, &key_file)?;
+                key_file.check = hex(&hmac_sha256(&master_key, b"check"));
+
+                let contents = toml::to_string(&key_file).map_err(io::Error::other)?;
+                fs::write(&key_file_path, contents)?;
+                let stored = inner.put(KEY_FILE, &key_file_path);
+                let _ = fs::remove_file(&key_file_path);
+                stored?;
+
+                println!("Created a new encryption key for {}", inner.name());
+
+                master_key
+            }
+        };
+
+        Ok(EncryptedBackend {
+            inner,
+            content_key: subkey(&master_key, b"content"),
+            name_mac_key: subkey(&master_key, b"name-mac"),
+            name_key: subkey(&master_key, b"name"),
+        })
+    }
+
+    /// `data/<2 chars>/<hex of SIV + encrypted key>`, the hex split into segments
+    fn encrypt_name(&self, key: &str) -> String {
+        let siv = &hmac_sha256(&self.name_mac_key, key.as_bytes())[..SIV_SIZE];
+
+        let mut name = key.as_bytes().to_vec();
+        XChaCha20::new(&self.name_key.into(), siv.into()).apply_keystream(&mut name);
+
+        let encoded = hex(&[siv, &name].concat());
+        let segments = encoded
+            .as_bytes()
+            .chunks(NAME_SEGMENT)
+            .map(|s| String::from_utf8_lossy(s).into_owned())
+            .collect::<Vec<_>>();
+
+        format!("{}/{}/{}", DATA_FOLDER, &encoded[..2], segments.join("/"))
+    }
+
+    /// None for anything that isn't a name this key wrote
+    fn decrypt_name(&self, name: &str) -> Option<String> {
+        let encoded = name.strip_prefix(DATA_FOLDER)?.get(4..)?.replace('/', "");
+        let bytes = unhex(&encoded)?;
+
+        if bytes.len() < SIV_SIZE {
+            return None;
+        }
+
+        let (siv, encrypted) = bytes.split_at(SIV_SIZE);
+
+        let mut key = encrypted.to_vec();
+        XChaCha20::new(&self.name_key.into(), siv.into()).apply_keystream(&mut key);
+
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.name_mac_key).ok()?;
+        mac.update(&key);
+        mac.verify_truncated_left(siv).ok()?;
+
+        String::from_utf8(key).ok()
+    }
+
+    /// Returns the SHA-256 of what it read, which is what the ciphertext decrypts to
+    fn encrypt_file(&self, key: &str, from: &Path, to: &Path) -> io::Result<String> {
+        let cipher = XChaCha20Poly1305::new(&self.content_key.into());
+        let mut hasher = Sha256::new();
+
+        let mut input = BufReader::new(File::open(from)?);
+        let mut output = BufWriter::new(File::create(to)?);
+
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        getrandom::getrandom(&mut nonce_prefix).map_err(|e| io::Error::other(e.to_string()))?;
+
+        output.write_all(MAGIC)?;
+        output.write_all(&nonce_prefix)?;
+
+        let mut chunk = read_chunk(&mut input, CHUNK_SIZE)?;
+
+        for counter in 0u32.. {
+            let next = read_chunk(&mut input, CHUNK_SIZE)?;
+            let last = next.is_empty();
+            hasher.update(&chunk);
+
+            let payload = Payload {
+                msg: &chunk,
+                aad: key.as_bytes(),
+            };
+            let sealed = cipher
+                .encrypt(&chunk_nonce(&nonce_prefix, counter, last), payload)
+                .map_err(|_| io::Error::other("Encryption failed"))?;
+
+            output.write_all(&sealed)?;
+
+            if last {
+                break;
+            }
+
+            chunk = next;
+        }
+
+        output.flush()?;
+
+        Ok(hex(&hasher.finalize()))
+    }
+
+    fn decrypt_file(&self, key: &str, from: &Path, to: &Path) -> io::Result<()> {
+        let cipher = XChaCha20Poly1305::new(&self.content_key.into());
+        let tampered = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} doesn't decrypt, it was changed or cut short", key),
+            )
+        };
+
+        let mut input = BufReader::new(File::open(from)?);
+        let mut output = BufWriter::new(File::create(to)?);
+
+        let header = read_chunk(&mut input, MAGIC.len() + NONCE_PREFIX_SIZE)?;
+        if header.len() < MAGIC.len() + NONCE_PREFIX_SIZE || &header[..MAGIC.len()] != MAGIC {
+            return Err(tampered());
+        }
+        let nonce_prefix = &header[MAGIC.len()..];
+
+        let mut chunk = read_chunk(&mut input, CHUNK_SIZE + TAG_SIZE)?;
+
+        for counter in 0u32.. {
+            let next = read_chunk(&mut input, CHUNK_SIZE + TAG_SIZE)?;
+            let last = next.is_empty();
+
+            let payload = Payload {
+                msg: &chunk,
+                aad: key.as_bytes(),
+            };
+            let plain = cipher
+                .decrypt(&chunk_nonce(nonce_prefix, counter, last), payload)
+                .map_err(|_| tampered())?;
+
+            output.write_all(&plain)?;
+
+            if last {
+                break;
+            }
+
+            chunk = next;
+        }
+
+        output.flush()
+    }
+}
+
+impl StorageBackend for EncryptedBackend {
+    fn name(&self) -> String {
+        format!("encrypted:{}", self.inner.name())
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        self.inner.exists(&self.encrypt_name(key))
+    }
+
+    fn put(&self, key: &str, path: &Path) -> io::Result<()> {
+        let encrypted = temp_path("encrypted");
+
+        let stored = self
+            .encrypt_file(key, path, &encrypted)
+            .and_then(|_| self.inner.put(&self.encrypt_name(key), &encrypted));
+        let _ = fs::remove_file(&encrypted);
+
+        stored
+    }
+
+    /// The inner backend checks the ciphertext; intact, it can only decrypt to the file
+    fn put_checked(&self, key: &str, path: &Path, content_hash: &str) -> io::Result<bool> {
+        let encrypted = temp_path("encrypted");
+
+        let checked = self
+            .encrypt_file(key, path, &encrypted)
+            .and_then(|plaintext_hash| {
+                if plaintext_hash != content_hash {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{:?} no longer hashes to {}", path, content_hash),
+                    ));
+                }
+
+                self.inner.put_checked(
+                    &self.encrypt_name(key),
+                    &encrypted,
+                    &calculate_full_hash(&encrypted)?,
+                )
+            });
+        let _ = fs::remove_file(&encrypted);
+
+        checked
+    }
+
+    fn get(&self, key: &str, path: &Path) -> io::Result<()> {
+        let encrypted = temp_path("encrypted");
+
+        let partial = path.with_file_name(format!(
+            "{}.part",
+            path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+        ));
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let fetched = self
+            .inner
+            .get(&self.encrypt_name(key), &encrypted)
+            .and_then(|_| self.decrypt_file(key, &encrypted, &partial));
+        let _ = fs::remove_file(&encrypted);
+
+        if let Err(e) = fetched {
+            let _ = fs::remove_file(&partial);
+            return Err(e);
+        }
+
+        fs::rename(&partial, path)
+    }
+
+    /// Lists and decrypts every name, the remote can't filter on a prefix it can't read
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = self
+            .inner
+            .list(&format!("{}/", DATA_FOLDER))?
+            .iter()
+            .filter_map(|name| self.decrypt_name(name))
+            .filter(|key| key.starts_with(prefix))
+            .collect::<Vec<_>>();
+
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.inner.delete(&self.encrypt_name(key))
+    }
+}
+
+fn passphrase(config: &EncryptedConfig, new_key: bool) -> io::Result<String> {
+    if let Ok(passphrase) = env::var("PHOTO_APP_RS_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    if !config.passphrase_file.is_empty() {
+        let contents = fs::read_to_string(&config.passphrase_file)?;
+        return Ok(contents.trim_end_matches(['\n', '\r']).to_string());
+    }
+
+    let passphrase = prompt(&format!("Passphrase for {}: ", config.storage))?;
+
+    if passphrase.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "An empty passphrase isn't allowed",
+        ));
+    }
+
+    // A typo here would make every backup unreadable
+    if new_key && prompt("Same passphrase again: ")? != passphrase {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Passphrases don't match",
+        ));
+    }
+
+    Ok(passphrase)
+}
+
+/// Reads from the terminal with echo off, so the passphrase isn't left on screen
+fn prompt(message: &str) -> io::Result<String> {
+    rpassword::prompt_password(message)
+}
+
+/// Argon2id, so guessing passphrases from a stolen bucket is slow
+fn derive_key(passphrase: &str, key_file: &KeyFile) -> io::Result<[u8; 32]> {
+    let salt = unhex(&key_file.salt)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Bad salt in key file"))?;
+
+    let params = Params::new(key_file.memory_kib, key_file.iterations, 1, Some(32))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    Ok(key)
+}
+
+/// Separate keys for content and names, derived from the one the passphrase gives
+fn subkey(master_key: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hmac_sha256(master_key, purpose));
+    key
+}
+
+/// Random per object prefix, chunk counter, and whether this is the last chunk
+fn chunk_nonce(prefix: &[u8], counter: u32, last: bool) -> XNonce {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..23].copy_from_slice(&counter.to_be_bytes());
+    nonce[23] = last as u8;
+
+    nonce.into()
+}
+
+/// Reads `size` bytes, fewer only at the end of the file
+fn read_chunk(reader: &mut impl Read, size: usize) -> io::Result<Vec<u8>> {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk)?;
+
+    Ok(chunk)
+}
+
+fn unhex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, process};
+
+    use super::*;
+    use crate::utils::storage::LocalBackend;
+
+    /// A backend under a fresh folder, with a key made up for the test (Argon2 takes a while)
+    fn backend(name: &str, master_key: &[u8]) -> (EncryptedBackend, PathBuf) {
+        let folder = env::temp_dir().join(format!("photo_app_rs_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+
+        let backend = EncryptedBackend {
+            inner: Box::new(LocalBackend::new(folder.join("remote"))),
+            content_key: subkey(master_key, b"content"),
+            name_mac_key: subkey(master_key, b"name-mac"),
+            name_key: subkey(master_key, b"name"),
+        };
+
+        (backend, folder)
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 251) as u8).collect()
+    }
+
+    /// Encrypts `len` bytes under "photo.jpg", returns the path of the stored object
+    fn store(backend: &EncryptedBackend, folder: &Path, len: usize) -> PathBuf {
+        let plain = folder.join("plain");
+        fs::write(&plain, content(len)).unwrap();
+        backend.put("photo.jpg", &plain).unwrap();
+
+        folder
+            .join("remote")
+            .join(backend.encrypt_name("photo.jpg"))
+    }
+
+    /// Decrypting fails, and leaves nothing at the destination
+    fn assert_rejected(backend: &EncryptedBackend, folder: &Path) {
+        let restored = folder.join("restored");
+        let error = backend.get("photo.jpg", &restored).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!restored.exists());
+    }
+
+    #[test]
+    fn round_trips_every_size_around_a_chunk() {
+        let (backend, folder) = backend("round_trip", b"master");
+
+        for len in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1] {
+            let stored = store(&backend, &folder, len);
+            assert_eq!(
+                fs::metadata(&stored).unwrap().len() as usize,
+                MAGIC.len() + NONCE_PREFIX_SIZE + len + len.div_ceil(CHUNK_SIZE).max(1) * TAG_SIZE
+            );
+
+            let restored = folder.join("restored");
+            backend.get("photo.jpg", &restored).unwrap();
+            assert_eq!(fs::read(&restored).unwrap(), content(len), "{} bytes", len);
+
+            let content_hash = calculate_full_hash(&folder.join("plain")).unwrap();
+            assert_eq!(backend.content_hash("photo.jpg").unwrap(), content_hash);
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_cut_off_last_chunk() {
+        let (backend, folder) = backend("truncated", b"master");
+        let stored = store(&backend, &folder, CHUNK_SIZE + 1);
+        let sealed = fs::read(&stored).unwrap();
+
+        // Part of the last chunk gone
+        fs::write(&stored, &sealed[..sealed.len() - 1]).unwrap();
+        assert_rejected(&backend, &folder);
+
+        // All of it gone, the chunk before isn't marked last
+        fs::write(&stored, &sealed[..sealed.len() - 1 - TAG_SIZE]).unwrap();
+        assert_rejected(&backend, &folder);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_reordered_chunks() {
+        let (backend, folder) = backend("reordered", b"master");
+        let stored = store(&backend, &folder, 2 * CHUNK_SIZE + 1);
+        let sealed = fs::read(&stored).unwrap();
+
+        let header = MAGIC.len() + NONCE_PREFIX_SIZE;
+        let sealed_chunk = CHUNK_SIZE + TAG_SIZE;
+        let (first, second) = (
+            header..header + sealed_chunk,
+            header + sealed_chunk..header + 2 * sealed_chunk,
+        );
+
+        let mut swapped = sealed[..header].to_vec();
+        swapped.extend_from_slice(&sealed[second]);
+        swapped.extend_from_slice(&sealed[first]);
+        swapped.extend_from_slice(&sealed[header + 2 * sealed_chunk..]);
+        fs::write(&stored, swapped).unwrap();
+
+        assert_rejected(&backend, &folder);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_flipped_byte() {
+        let (backend, folder) = backend("flipped", b"master");
+        let stored = store(&backend, &folder, 1000);
+        let mut sealed = fs::read(&stored).unwrap();
+
+        sealed[MAGIC.len() + NONCE_PREFIX_SIZE + 10] ^= 1;
+        fs::write(&stored, sealed).unwrap();
+
+        assert_rejected(&backend, &folder);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_wrong_key() {
+        let (backend, folder) = backend("wrong_key", b"master");
+        let stored = store(&backend, &folder, 1000);
+
+        let (other, other_folder) = backend_with_key_over(&folder, b"another master");
+        let copy = other_folder
+            .join("remote")
+            .join(other.encrypt_name("photo.jpg"));
+        fs::create_dir_all(copy.parent().unwrap()).unwrap();
+        fs::copy(&stored, &copy).unwrap();
+
+        assert_rejected(&other, &other_folder);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    /// A second backend with its own key, in a subfolder of `folder`
+    fn backend_with_key_over(folder: &Path, master_key: &[u8]) -> (EncryptedBackend, PathBuf) {
+        let folder = folder.join("other");
+
+        let backend = EncryptedBackend {
+            inner: Box::new(LocalBackend::new(folder.join("remote"))),
+            content_key: subkey(master_key, b"content"),
+            name_mac_key: subkey(master_key, b"name-mac"),
+            name_key: subkey(master_key, b"name"),
+        };
+
+        (backend, folder)
+    }
+
+    #[test]
+    fn names_round_trip_and_are_deterministic() {
+        let (backend, folder) = backend("names", b"master");
+        let long_key = format!("2024/{}.jpg", "x".repeat(300));
+
+        for key in [
+            "photo.jpg",
+            "2024/06/IMG_0001.HEIC",
+            "objects/ab/abcdef",
+            &long_key,
+        ] {
+            let name = backend.encrypt_name(key);
+
+            assert_eq!(name, backend.encrypt_name(key));
+            assert!(name.split('/').all(|segment| segment.len() <= NAME_SEGMENT));
+            assert!(!name.contains("photo") && !name.contains("2024"));
+            assert_eq!(backend.decrypt_name(&name).as_deref(), Some(key));
+        }
+
+        assert_ne!(backend.encrypt_name("a.jpg"), backend.encrypt_name("b.jpg"));
+
+        let (other, _) = backend_with_key_over(&folder, b"another master");
+        assert_ne!(other.encrypt_name("a.jpg"), backend.encrypt_name("a.jpg"));
+        assert_eq!(other.decrypt_name(&backend.encrypt_name("a.jpg")), None);
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
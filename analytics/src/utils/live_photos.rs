@@ -0,0 +1,469 @@
+use exif::{In, Reader, Tag, Value};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::utils::{core::FileType, sidecar::xmp_value};
+
+const APPLE_MAKER_NOTE_SIGNATURE: &[u8] = b"Apple iOS\0";
+const APPLE_CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+const QUICKTIME_CONTENT_IDENTIFIER_KEY: &[u8] = b"com.apple.quicktime.content.identifier";
+/// A `moov` holds the sample tables, a few MB even for long videos; anything bigger is a
+/// corrupt length, not something to allocate
+const MAX_MOOV_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Identifier shared by the still and the video of an iPhone Live Photo.
+/// Read from the Apple MakerNote for images and the QuickTime metadata for videos.
+pub fn content_identifier(path: &Path, file_type: &FileType) -> Option<String> {
+    let identifier = match file_type {
+        FileType::Image(_) => image_content_identifier(path),
+        FileType::Video(_) => video_content_identifier(path).unwrap_or_else(|e| {
+            println!(
+                "Error reading QuickTime metadata; Path : {:?}; Error : {:?}",
+                path.to_str(),
+                e
+            );
+            None
+        }),
+        _ => None,
+    };
+
+    identifier.filter(|id| !id.is_empty())
+}
+
+/// Android motion photos (Google "MicroVideo" / "MotionPhoto") embed the video in the JPEG itself
+pub fn is_motion_photo(xmp: &str) -> bool {
+    [
+        "GCamera:MicroVideo",
+        "GCamera:MotionPhoto",
+        "Camera:MotionPhoto",
+    ]
+    .iter()
+    .any(|name| xmp_value(xmp, name).is_some_and(|v| v.trim() == "1"))
+}
+
+fn image_content_identifier(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let mut bufreader = BufReader::new(&file);
+    let exifreader = Reader::new().read_from_container(&mut bufreader).ok()?;
+
+    let maker_note = match &exifreader.get_field(Tag::MakerNote, In::PRIMARY)?.value {
+        Value::Undefined(bytes, _) => bytes.clone(),
+        _ => return None,
+    };
+
+    apple_maker_note_string(&maker_note, APPLE_CONTENT_IDENTIFIER_TAG)
+}
+
+/// Apple MakerNote: "Apple iOS\0", 2 byte version, "MM", then a big endian IFD
+/// whose offsets are relative to the start of the MakerNote
+fn apple_maker_note_string(maker_note: &[u8], tag: u16) -> Option<String> {
+    if !maker_note.starts_with(APPLE_MAKER_NOTE_SIGNATURE) {
+        return None;
+    }
+
+    let read_u16 = |at: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            maker_note.get(at..at + 2)?.try_into().ok()?,
+        ))
+    };
+    let read_u32 = |at: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(
+            maker_note.get(at..at + 4)?.try_into().ok()?,
+        ))
+    };
+
+    let ifd_start = 14;
+    let entry_count = read_u16(ifd_start)? as usize;
+
+    for i in 0..entry_count {
+        let entry = ifd_start + 2 + i * 12;
+
+        if read_u16(entry)? != tag {
+            continue;
+        }
+
+        let count = read_u32(entry + 4)? as usize;
+        let value = if count <= 4 {
+            maker_note.get(entry + 8..entry + 8 + count)?
+        } else {
+            let offset = read_u32(entry + 8)? as usize;
+            maker_note.get(offset..offset + count)?
+        };
+
+        let value = value.split(|b| *b == 0).next().unwrap_or(&[]);
+        return Some(String::from_utf8_lossy(value).trim().to_string());
+    }
+
+    None
+}
+
+/// QuickTime keeps Apple metadata as `moov/meta/keys` (names) + `moov/meta/ilst` (values,
+/// indexed by the 1-based position of their key)
+fn video_content_identifier(path: &Path) -> io::Result<Option<String>> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let Some((moov_start, moov_len)) = find_box(&mut file, 0, file_len, b"moov")? else {
+        return Ok(None);
+    };
+
+    if moov_len > MAX_MOOV_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("moov box of {} bytes", moov_len),
+        ));
+    }
+
+    let mut moov = vec![0u8; moov_len as usize];
+    file.seek(SeekFrom::Start(moov_start))?;
+    file.read_exact(&mut moov)?;
+
+    Ok(find_meta(&moov).and_then(content_identifier_in_meta))
+}
+
+/// Walks the boxes in `[start, end)` of the file and returns the payload range of the first `kind`
+fn find_box(
+    file: &mut File,
+    start: u64,
+    end: u64,
+    kind: &[u8; 4],
+) -> io::Result<Option<(u64, u64)>> {
+    let mut offset = start;
+
+    while offset + 8 <= end {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+
+        let mut size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let mut header_len = 8;
+
+        if size == 1 {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = end - offset;
+        }
+
+        // A length running past the end of its parent is corrupt
+        if size < header_len || size > end - offset {
+            break;
+        }
+
+        if &header[4..8] == kind {
+            return Ok(Some((offset + header_len, size - header_len)));
+        }
+
+        offset += size;
+    }
+
+    Ok(None)
+}
+
+/// Iterates the child boxes of an in-memory box payload as (type, payload)
+fn child_boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        if size < 8 || offset + size > data.len() {
+            break;
+        }
+
+        boxes.push((
+            &data[offset + 4..offset + 8],
+            &data[offset + 8..offset + size],
+        ));
+        offset += size;
+    }
+
+    boxes
+}
+
+/// `meta` sits directly under `moov` in iPhone videos, or under `moov/udta` in some edits
+fn find_meta(moov: &[u8]) -> Option<&[u8]> {
+    for (kind, payload) in child_boxes(moov) {
+        match kind {
+            b"meta" => return Some(payload),
+            b"udta" => {
+                if let Some(meta) = find_meta(payload) {
+                    return Some(meta);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn content_identifier_in_meta(meta: &[u8]) -> Option<String> {
+    let mut children = child_boxes(meta);
+
+    // ISO style `meta` boxes start with version/flags, QuickTime ones don't
+    if !children.iter().any(|(kind, _)| *kind == b"hdlr") {
+        children = child_boxes(meta.get(4..)?);
+    }
+
+    let keys = children.iter().find(|(kind, _)| *kind == b"keys")?.1;
+    let ilst = children.iter().find(|(kind, _)| *kind == b"ilst")?.1;
+
+    // keys: version/flags, entry count, then (size, namespace, name) entries
+    let entry_count = u32::from_be_bytes(keys.get(4..8)?.try_into().ok()?);
+    let mut offset = 8;
+    let mut key_index = None;
+
+    for index in 1..=entry_count {
+        let size = u32::from_be_bytes(keys.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        if size < 8 {
+            return None;
+        }
+
+        if keys.get(offset + 8..offset + size)? == QUICKTIME_CONTENT_IDENTIFIER_KEY {
+            key_index = Some(index);
+            break;
+        }
+
+        offset += size;
+    }
+
+    let key_index = key_index?.to_be_bytes();
+
+    let (_, item) = child_boxes(ilst)
+        .into_iter()
+        .find(|(kind, _)| *kind == key_index)?;
+
+    // data box: type indicator (4) + locale (4) + value
+    let (_, data) = child_boxes(item)
+        .into_iter()
+        .find(|(kind, _)| *kind == b"data")?;
+
+    Some(String::from_utf8_lossy(data.get(8..)?).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf, process};
+
+    use super::*;
+
+    const IDENTIFIER: &str = "1A2B3C4D-0000-4F6E-9E1A-5D8C0B7A6E21";
+
+    fn boxed(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        [
+            &((payload.len() + 8) as u32).to_be_bytes(),
+            &kind[..],
+            payload,
+        ]
+        .concat()
+    }
+
+    /// `meta` with the content identifier as the second key, ISO style (version/flags
+    /// first) or QuickTime style
+    fn meta(iso: bool) -> Vec<u8> {
+        let key =
+            |name: &[u8]| [&((name.len() + 8) as u32).to_be_bytes(), &b"mdta"[..], name].concat();
+
+        let keys = [
+            &[0, 0, 0, 0][..],
+            &2u32.to_be_bytes(),
+            &key(b"com.apple.quicktime.make"),
+            &key(QUICKTIME_CONTENT_IDENTIFIER_KEY),
+        ]
+        .concat();
+
+        let value = |text: &str| {
+            boxed(
+                b"data",
+                &[&[0, 0, 0, 1, 0, 0, 0, 0][..], text.as_bytes()].concat(),
+            )
+        };
+        let ilst = [
+            boxed(&1u32.to_be_bytes(), &value("Apple")),
+            boxed(&2u32.to_be_bytes(), &value(IDENTIFIER)),
+        ]
+        .concat();
+
+        let children = [
+            boxed(b"hdlr", &[0; 24]),
+            boxed(b"keys", &keys),
+            boxed(b"ilst", &ilst),
+        ]
+        .concat();
+
+        if iso {
+            boxed(b"meta", &[&[0, 0, 0, 0][..], &children].concat())
+        } else {
+            boxed(b"meta", &children)
+        }
+    }
+
+    fn movie(name: &str, bytes: &[u8]) -> PathBuf {
+        let path =
+            env::temp_dir().join(format!("photo_app_rs_test_{}_{}.mov", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_the_identifier_from_quicktime_metadata() {
+        let ftyp = boxed(b"ftyp", b"qt  \0\0\0\0qt  ");
+        let mdat = boxed(b"mdat", &[0xAB; 100]);
+
+        for (name, moov) in [
+            ("qt_meta", boxed(b"moov", &meta(false))),
+            ("iso_meta", boxed(b"moov", &meta(true))),
+            (
+                "udta_meta",
+                boxed(
+                    b"moov",
+                    &[boxed(b"mvhd", &[0; 100]), boxed(b"udta", &meta(false))].concat(),
+                ),
+            ),
+        ] {
+            let path = movie(name, &[&ftyp[..], &mdat, &moov].concat());
+            assert_eq!(
+                video_content_identifier(&path).unwrap().as_deref(),
+                Some(IDENTIFIER),
+                "{}",
+                name
+            );
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn gives_up_on_truncated_boxes() {
+        let moov = boxed(b"moov", &meta(false));
+
+        // Children cut short inside a complete moov are skipped, not read past
+        let mut cut_meta = meta(false);
+        cut_meta.truncate(cut_meta.len() - 30);
+        let declared = cut_meta.len() as u32 + 200;
+        cut_meta[..4].copy_from_slice(&declared.to_be_bytes());
+
+        for (name, bytes) in [
+            // The moov is cut short: its length runs past the end of the file
+            ("cut_moov", moov[..moov.len() - 20].to_vec()),
+            // A box length shorter than its own header
+            ("short_box", [&[0, 0, 0, 4][..], b"ftyp", &moov].concat()),
+            ("cut_meta", boxed(b"moov", &cut_meta)),
+        ] {
+            let path = movie(name, &bytes);
+            assert_eq!(video_content_identifier(&path).unwrap(), None, "{}", name);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn refuses_an_oversized_moov() {
+        // A 64-bit length past MAX_MOOV_BYTES, in a sparse file that really is that long
+        let size = MAX_MOOV_BYTES + 16 + 1;
+        let path = movie(
+            "huge_moov",
+            &[&1u32.to_be_bytes()[..], b"moov", &size.to_be_bytes()].concat(),
+        );
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(size)
+            .unwrap();
+
+        let error = video_content_identifier(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn maker_note(entries: &[(u16, u16, u32, [u8; 4])], tail: &[u8]) -> Vec<u8> {
+        let mut note = [APPLE_MAKER_NOTE_SIGNATURE, &[0, 1], b"MM"].concat();
+        note.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+        for (tag, kind, count, value) in entries {
+            note.extend_from_slice(&tag.to_be_bytes());
+            note.extend_from_slice(&kind.to_be_bytes());
+            note.extend_from_slice(&count.to_be_bytes());
+            note.extend_from_slice(value);
+        }
+        note.extend_from_slice(tail);
+        note
+    }
+
+    #[test]
+    fn reads_the_identifier_from_the_apple_maker_note() {
+        // 14 byte header, 2 byte count, two 12 byte entries: the string follows at 40
+        let string = format!("{}\0", IDENTIFIER);
+        let note = maker_note(
+            &[
+                (0x0001, 9, 1, [0, 0, 0, 14]),
+                (
+                    APPLE_CONTENT_IDENTIFIER_TAG,
+                    2,
+                    string.len() as u32,
+                    40u32.to_be_bytes(),
+                ),
+            ],
+            string.as_bytes(),
+        );
+        assert_eq!(
+            apple_maker_note_string(&note, APPLE_CONTENT_IDENTIFIER_TAG).as_deref(),
+            Some(IDENTIFIER)
+        );
+
+        // Short values sit in the entry itself
+        let inline = maker_note(&[(APPLE_CONTENT_IDENTIFIER_TAG, 2, 3, *b"ab\0\0")], b"");
+        assert_eq!(
+            apple_maker_note_string(&inline, APPLE_CONTENT_IDENTIFIER_TAG).as_deref(),
+            Some("ab")
+        );
+    }
+
+    #[test]
+    fn gives_up_on_broken_maker_notes() {
+        // Value offset past the end
+        let past_end = maker_note(
+            &[(APPLE_CONTENT_IDENTIFIER_TAG, 2, 37, 400u32.to_be_bytes())],
+            b"",
+        );
+        assert_eq!(
+            apple_maker_note_string(&past_end, APPLE_CONTENT_IDENTIFIER_TAG),
+            None
+        );
+
+        // More entries announced than there are bytes
+        let mut truncated = maker_note(&[(0x0001, 9, 1, [0; 4])], b"");
+        truncated[14..16].copy_from_slice(&500u16.to_be_bytes());
+        assert_eq!(
+            apple_maker_note_string(&truncated, APPLE_CONTENT_IDENTIFIER_TAG),
+            None
+        );
+
+        assert_eq!(
+            apple_maker_note_string(b"Nikon\0", APPLE_CONTENT_IDENTIFIER_TAG),
+            None
+        );
+        assert_eq!(
+            apple_maker_note_string(APPLE_MAKER_NOTE_SIGNATURE, APPLE_CONTENT_IDENTIFIER_TAG),
+            None
+        );
+    }
+
+    #[test]
+    fn recognizes_motion_photos() {
+        assert!(is_motion_photo(
+            r#"<rdf:Description GCamera:MotionPhoto="1"/>"#
+        ));
+        assert!(is_motion_photo(
+            "<GCamera:MicroVideo>1</GCamera:MicroVideo>"
+        ));
+        assert!(!is_motion_photo(
+            r#"<rdf:Description GCamera:MotionPhoto="0"/>"#
+        ));
+    }
+}
@@ -1,2 +1,22 @@
+pub mod assets;
+pub mod checksums;
 pub mod core;
+pub mod derivatives;
+pub mod devices;
 pub mod duplicates;
+pub mod embedded;
+pub mod encryption;
+pub mod live_photos;
+pub mod parity;
+pub mod perceptual;
+pub mod quarantine;
+pub mod ranking;
+pub mod s3;
+pub mod sftp;
+pub mod sidecar;
+pub mod stacks;
+pub mod storage;
+pub mod tags;
+pub mod video_fingerprint;
+pub mod volume;
+pub mod webdav;
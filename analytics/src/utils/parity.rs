@@ -0,0 +1,582 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::utils::duplicates::calculate_full_hash;
+
+/// Written to the folder it covers
+pub const PARITY_FILE: &str = ".photo_app_rs.parity";
+
+const MAGIC: &[u8; 8] = b"PARPHOT1";
+/// Data slices per Reed-Solomon group. GF(2^8) allows 256 slices, data and parity,
+/// per group.
+const GROUP_SLICES: usize = 128;
+/// Truncated SHA-256 of every slice, to tell which ones are damaged
+const SLICE_HASH_SIZE: usize = 16;
+
+/// What a parity file covers, stored at its end
+#[derive(Serialize, Deserialize)]
+pub struct ParityIndex {
+    pub slice_size: u64,
+    /// Parity slices per group, as a share of its data slices
+    pub redundancy_percent: u32,
+    /// In the order their bytes were laid end to end
+    pub files: Vec<ParityFile>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ParityFile {
+    /// From the folder, `/` separated
+    pub path: String,
+    pub size: u64,
+    pub content_hash: String,
+}
+
+impl ParityIndex {
+    fn data_bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.size).sum()
+    }
+
+    fn data_slices(&self) -> usize {
+        self.data_bytes().div_ceil(self.slice_size) as usize
+    }
+
+    fn groups(&self) -> usize {
+        self.data_slices().div_ceil(GROUP_SLICES)
+    }
+
+    /// Data slices in group `group`; the last one can be short
+    fn group_len(&self, group: usize) -> usize {
+        (self.data_slices() - group * GROUP_SLICES).min(GROUP_SLICES)
+    }
+
+    /// Parity slices of group `group`, and so how many of its slices can be rebuilt
+    fn parity_len(&self, group: usize) -> usize {
+        (self.group_len(group) * self.redundancy_percent as usize)
+            .div_ceil(100)
+            .max(1)
+    }
+
+    /// Parity slices before group `group`'s
+    fn parity_start(&self, group: usize) -> usize {
+        (0..group).map(|earlier| self.parity_len(earlier)).sum()
+    }
+}
+
+/// The files of a folder read as one run of bytes, cut in slices. Missing or short
+/// files read as zeros, which their slice hashes then give away.
+struct Slices<'a> {
+    folder: &'a Path,
+    files: &'a [ParityFile],
+    slice_size: u64,
+    /// Where each file starts in the run
+    starts: Vec<u64>,
+    open: Option<(usize, File)>,
+    /// Hashing the files on the way through, while writing parity
+    hashers: Option<Vec<Sha256>>,
+}
+
+impl<'a> Slices<'a> {
+    fn new(folder: &'a Path, files: &'a [ParityFile], slice_size: u64) -> Self {
+        let starts = files
+            .iter()
+            .scan(0, |start, file| {
+                let file_start = *start;
+                *start += file.size;
+                Some(file_start)
+            })
+            .collect();
+
+        Slices {
+            folder,
+            files,
+            slice_size,
+            starts,
+            open: None,
+            hashers: None,
+        }
+    }
+
+    fn read(&mut self, slice: usize, buffer: &mut [u8]) -> io::Result<()> {
+        buffer.fill(0);
+
+        let slice_start = slice as u64 * self.slice_size;
+        let slice_end = slice_start + self.slice_size;
+
+        for index in 0..self.files.len() {
+            let file_start = self.starts[index];
+            let file_end = file_start + self.files[index].size;
+
+            if file_end <= slice_start || file_start >= slice_end {
+                continue;
+            }
+
+            let from = slice_start.max(file_start);
+            let to = slice_end.min(file_end);
+            let part = &mut buffer[(from - slice_start) as usize..(to - slice_start) as usize];
+
+            let read = self.read_file(index, from - file_start, part)?;
+
+            if let Some(hashers) = &mut self.hashers {
+                hashers[index].update(&part[..read]);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fills `part` from `offset` in file `index`, returns how much of it the file had
+    fn read_file(&mut self, index: usize, offset: u64, part: &mut [u8]) -> io::Result<usize> {
+        if self.open.as_ref().is_none_or(|(open, _)| *open != index) {
+            let path = self.folder.join(&self.files[index].path);
+
+            self.open = match File::open(&path) {
+                Ok(file) => Some((index, file)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+                Err(e) => return Err(e),
+            };
+        }
+
+        let Some((_, file)) = &mut self.open else {
+            return Ok(0);
+        };
+
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut filled = 0;
+        while filled < part.len() {
+            match file.read(&mut part[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+
+        Ok(filled)
+    }
+}
+
+/// Writes recovery data for `files` (paths from `folder`) to `to`, with
+/// `redundancy_percent` of parity slices per group of data slices. Returns what it covers,
+/// with the content hash every file was read with.
+///
+/// Layout: magic, every parity slice group by group, the hash of every data slice then
+/// of every parity slice, the index as TOML, and the offset of the hashes and length of
+/// the index as two big endian u64s.
+pub fn write_parity(
+    folder: &Path,
+    files: &[(String, u64)],
+    slice_size: u64,
+    redundancy_percent: u32,
+    to: &Path,
+) -> io::Result<ParityIndex> {
+    let mut index = ParityIndex {
+        slice_size,
+        redundancy_percent: redundancy_percent.clamp(1, 100),
+        files: files
+            .iter()
+            .map(|(path, size)| ParityFile {
+                path: path.clone(),
+                size: *size,
+                content_hash: String::new(),
+            })
+            .collect(),
+    };
+
+    let mut out = BufWriter::new(File::create(to)?);
+    out.write_all(MAGIC)?;
+
+    let mut data_hashes = Vec::new();
+    let mut parity_hashes = Vec::new();
+
+    let mut slices = Slices::new(folder, &index.files, slice_size);
+    slices.hashers = Some(vec![Sha256::new(); index.files.len()]);
+
+    for group in 0..index.groups() {
+        let data_len = index.group_len(group);
+        let parity_len = index.parity_len(group);
+        let codec = ReedSolomon::new(data_len, parity_len).map_err(rs_error)?;
+
+        let mut shards = vec![vec![0u8; slice_size as usize]; data_len + parity_len];
+
+        for (i, shard) in shards.iter_mut().take(data_len).enumerate() {
+            slices.read(group * GROUP_SLICES + i, shard)?;
+            data_hashes.extend_from_slice(&slice_hash(shard));
+        }
+
+        codec.encode(&mut shards).map_err(rs_error)?;
+
+        for shard in &shards[data_len..] {
+            out.write_all(shard)?;
+            parity_hashes.extend_from_slice(&slice_hash(shard));
+        }
+    }
+
+    let hashers = slices.hashers.take().unwrap_or_default();
+    for (file, hasher) in index.files.iter_mut().zip(hashers) {
+        file.content_hash = format!("{:x}", hasher.finalize());
+    }
+
+    let hashes_offset = MAGIC.len() as u64 + index.parity_start(index.groups()) as u64 * slice_size;
+    let toml = toml::to_string(&index).map_err(io::Error::other)?;
+
+    out.write_all(&data_hashes)?;
+    out.write_all(&parity_hashes)?;
+    out.write_all(toml.as_bytes())?;
+    out.write_all(&hashes_offset.to_be_bytes())?;
+    out.write_all(&(toml.len() as u64).to_be_bytes())?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+    Ok(index)
+}
+
+/// Rebuilds the damaged parts of `relative_path` (from the folder) from the folder's
+/// parity file. The rebuilt file replaces the damaged one only if it hashes to
+/// `content_hash`.
+pub fn repair_from_parity(
+    folder: &Path,
+    relative_path: &str,
+    content_hash: &str,
+) -> io::Result<()> {
+    let mut parity = File::open(folder.join(PARITY_FILE))?;
+    let (index, hashes_offset) = read_index(&mut parity)?;
+
+    let Some(position) = index
+        .files
+        .iter()
+        .position(|file| file.path == relative_path)
+    else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} isn't covered by the recovery data", relative_path),
+        ));
+    };
+
+    if index.files[position].content_hash != content_hash {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "The recovery data was written for another version of the file",
+        ));
+    }
+
+    let slice_size = index.slice_size;
+    let data_slices = index.data_slices();
+    let parity_slices = index.parity_start(index.groups());
+
+    let mut slice_hashes = vec![0u8; (data_slices + parity_slices) * SLICE_HASH_SIZE];
+    parity.seek(SeekFrom::Start(hashes_offset))?;
+    parity.read_exact(&mut slice_hashes)?;
+
+    let mut slices = Slices::new(folder, &index.files, slice_size);
+
+    let file_start = slices.starts[position];
+    let file_end = file_start + index.files[position].size;
+    let first_slice = (file_start / slice_size) as usize;
+    let last_slice = (file_end.saturating_sub(1) / slice_size) as usize;
+
+    let path = folder.join(relative_path);
+    let rebuilt = path.with_file_name(format!(
+        "{}.repair",
+        path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+    ));
+    let mut out = BufWriter::new(File::create(&rebuilt)?);
+
+    let result = (|| {
+        for group in first_slice / GROUP_SLICES..=last_slice / GROUP_SLICES {
+            let data_len = index.group_len(group);
+            let first_in_group = group * GROUP_SLICES;
+
+            let parity_len = index.parity_len(group);
+            let first_parity = index.parity_start(group);
+
+            let mut shards = Vec::with_capacity(data_len + parity_len);
+
+            for i in 0..data_len {
+                let slice = first_in_group + i;
+                let mut shard = vec![0u8; slice_size as usize];
+                slices.read(slice, &mut shard)?;
+
+                let expected = &slice_hashes[slice * SLICE_HASH_SIZE..][..SLICE_HASH_SIZE];
+                shards.push((slice_hash(&shard)[..] == *expected).then_some(shard));
+            }
+
+            // Slices of the file in this group
+            let needed =
+                first_slice.max(first_in_group)..=last_slice.min(first_in_group + data_len - 1);
+
+            if needed
+                .clone()
+                .any(|slice| shards[slice - first_in_group].is_none())
+            {
+                for j in 0..parity_len {
+                    let parity_index = first_parity + j;
+                    let mut shard = vec![0u8; slice_size as usize];
+
+                    parity.seek(SeekFrom::Start(
+                        MAGIC.len() as u64 + parity_index as u64 * slice_size,
+                    ))?;
+                    parity.read_exact(&mut shard)?;
+
+                    let expected = &slice_hashes[(data_slices + parity_index) * SLICE_HASH_SIZE..]
+                        [..SLICE_HASH_SIZE];
+                    shards.push((slice_hash(&shard)[..] == *expected).then_some(shard));
+                }
+
+                let damaged = shards.iter().filter(|shard| shard.is_none()).count();
+                if damaged > parity_len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{} slice(s) of group {} are damaged, the recovery data can rebuild {}",
+                            damaged, group, parity_len
+                        ),
+                    ));
+                }
+
+                ReedSolomon::new(data_len, parity_len)
+                    .map_err(rs_error)?
+                    .reconstruct_data(&mut shards)
+                    .map_err(rs_error)?;
+            }
+
+            for slice in needed {
+                write_part(
+                    &mut out,
+                    &shards[slice - first_in_group],
+                    slice,
+                    slice_size,
+                    file_start,
+                    file_end,
+                )?;
+            }
+        }
+
+        out.flush()?;
+        drop(out);
+
+        let rebuilt_hash = calculate_full_hash(&rebuilt)?;
+        if rebuilt_hash != content_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Rebuilt file has hash {}, expected {}",
+                    rebuilt_hash, content_hash
+                ),
+            ));
+        }
+
+        fs::rename(&rebuilt, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&rebuilt);
+    }
+
+    result
+}
+
+/// Reads the index and where the slice hashes start. Every length and offset is checked
+/// against the size of the file before anything is allocated or read with it.
+fn read_index(parity: &mut File) -> io::Result<(ParityIndex, u64)> {
+    let corrupt = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Corrupt parity file: {}", what),
+        )
+    };
+
+    let file_len = parity.metadata()?.len();
+    let header_len = MAGIC.len() as u64;
+
+    let mut magic = [0u8; 8];
+    parity.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Not a photo_app_rs parity file",
+        ));
+    }
+
+    if file_len < header_len + 16 {
+        return Err(corrupt("no footer"));
+    }
+
+    let mut footer = [0u8; 16];
+    parity.seek(SeekFrom::End(-16))?;
+    parity.read_exact(&mut footer)?;
+
+    let hashes_offset = u64::from_be_bytes(footer[..8].try_into().unwrap());
+    let index_len = u64::from_be_bytes(footer[8..].try_into().unwrap());
+
+    // magic, parity slices, slice hashes, index, footer
+    let Some(index_start) = (file_len - 16)
+        .checked_sub(index_len)
+        .filter(|start| *start >= header_len)
+    else {
+        return Err(corrupt("index length past the start of the file"));
+    };
+
+    if hashes_offset < header_len || hashes_offset > index_start {
+        return Err(corrupt("slice hashes outside the file"));
+    }
+
+    let mut toml = vec![0u8; index_len as usize];
+    parity.seek(SeekFrom::Start(index_start))?;
+    parity.read_exact(&mut toml)?;
+
+    let index: ParityIndex = toml::from_str(&String::from_utf8_lossy(&toml))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    if index.slice_size == 0 {
+        return Err(corrupt("slice size of 0"));
+    }
+
+    // The index has to describe the parity slices and hashes the file holds
+    let data_slices = index.data_slices() as u64;
+    let parity_slices = index.parity_start(index.groups()) as u64;
+
+    let parity_bytes = parity_slices.checked_mul(index.slice_size);
+    let hash_bytes = (data_slices + parity_slices).checked_mul(SLICE_HASH_SIZE as u64);
+
+    if parity_bytes != Some(hashes_offset - header_len)
+        || hash_bytes != Some(index_start - hashes_offset)
+    {
+        return Err(corrupt("index doesn't match the file size"));
+    }
+
+    Ok((index, hashes_offset))
+}
+
+/// Writes the bytes of `slice` that belong to the file spanning `[file_start, file_end)`
+/// of the run
+fn write_part(
+    out: &mut impl Write,
+    shard: &Option<Vec<u8>>,
+    slice: usize,
+    slice_size: u64,
+    file_start: u64,
+    file_end: u64,
+) -> io::Result<()> {
+    let Some(shard) = shard else {
+        return Err(io::Error::other("Slice wasn't rebuilt"));
+    };
+
+    let slice_start = slice as u64 * slice_size;
+    let from = file_start.max(slice_start) - slice_start;
+    let to = file_end.min(slice_start + slice_size) - slice_start;
+
+    out.write_all(&shard[from as usize..to as usize])
+}
+
+fn slice_hash(slice: &[u8]) -> [u8; SLICE_HASH_SIZE] {
+    Sha256::digest(slice)[..SLICE_HASH_SIZE].try_into().unwrap()
+}
+
+fn rs_error(e: reed_solomon_erasure::Error) -> io::Error {
+    io::Error::other(format!("Reed-Solomon: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf, process};
+
+    use super::*;
+
+    const SLICE_SIZE: u64 = 1024;
+
+    /// A folder with two files spanning `slices` slices of 1 KB, and parity at 10 %
+    fn protected_folder(name: &str, slices: u64) -> (PathBuf, Vec<u8>) {
+        let folder = env::temp_dir().join(format!("photo_app_rs_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(folder.join("2024")).unwrap();
+
+        let photo = (0..slices * SLICE_SIZE - 300)
+            .map(|i| (i * 31 % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(folder.join("2024/a.jpg"), b"first file").unwrap();
+        fs::write(folder.join("2024/b.jpg"), &photo).unwrap();
+
+        let files = vec![
+            ("2024/a.jpg".to_string(), 10),
+            ("2024/b.jpg".to_string(), photo.len() as u64),
+        ];
+        write_parity(&folder, &files, SLICE_SIZE, 10, &folder.join(PARITY_FILE)).unwrap();
+
+        (folder, photo)
+    }
+
+    /// Flips a byte in each of these 1 KB stretches of the file, so in as many slices
+    fn damage(path: &Path, slices: impl Iterator<Item = u64>) {
+        let mut bytes = fs::read(path).unwrap();
+        for slice in slices {
+            bytes[(slice * SLICE_SIZE) as usize] ^= 0xff;
+        }
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    fn rebuilds_the_exact_bytes_of_a_damaged_file() {
+        // 40 data slices, so 4 parity slices
+        let (folder, photo) = protected_folder("parity_repair", 40);
+        let content_hash = calculate_full_hash(&folder.join("2024/b.jpg")).unwrap();
+
+        damage(&folder.join("2024/b.jpg"), [3, 17, 38].into_iter());
+        assert_ne!(fs::read(folder.join("2024/b.jpg")).unwrap(), photo);
+
+        repair_from_parity(&folder, "2024/b.jpg", &content_hash).unwrap();
+
+        assert_eq!(fs::read(folder.join("2024/b.jpg")).unwrap(), photo);
+        assert!(!folder.join("2024/b.jpg.repair").exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn fails_cleanly_past_what_the_parity_covers() {
+        let (folder, photo) = protected_folder("parity_too_damaged", 40);
+        let content_hash = calculate_full_hash(&folder.join("2024/b.jpg")).unwrap();
+
+        damage(&folder.join("2024/b.jpg"), (0..6).map(|slice| slice * 6));
+        let damaged = fs::read(folder.join("2024/b.jpg")).unwrap();
+
+        let error = repair_from_parity(&folder, "2024/b.jpg", &content_hash).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // The damaged file is left as it was, and nothing half rebuilt stays behind
+        assert_eq!(fs::read(folder.join("2024/b.jpg")).unwrap(), damaged);
+        assert_ne!(damaged, photo);
+        assert!(!folder.join("2024/b.jpg.repair").exists());
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_footer_pointing_outside_the_file() {
+        let (folder, _) = protected_folder("parity_footer", 4);
+        let parity_path = folder.join(PARITY_FILE);
+        let original = fs::read(&parity_path).unwrap();
+        let footer = original.len() - 16;
+
+        for (at, value) in [
+            (8, u64::MAX),
+            (8, original.len() as u64),
+            (0, u64::MAX),
+            (0, 0),
+        ] {
+            let mut bytes = original.clone();
+            bytes[footer + at..footer + at + 8].copy_from_slice(&value.to_be_bytes());
+            fs::write(&parity_path, bytes).unwrap();
+
+            let read = read_index(&mut File::open(&parity_path).unwrap());
+            assert!(read.is_err_and(|e| e.kind() == io::ErrorKind::InvalidData));
+        }
+
+        fs::remove_dir_all(&folder).unwrap();
+    }
+}
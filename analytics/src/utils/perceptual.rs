@@ -0,0 +1,291 @@
+use exif::{In, Reader, Tag};
+use image::{DynamicImage, imageops::FilterType};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+};
+
+use crate::utils::core::{FileType, ImageFormat};
+
+/// Difference hash of the picture: 64 bits, one per "is this pixel darker than its
+/// right neighbour" on a 9x8 grayscale thumbnail. Survives resizing and re-encoding.
+pub fn image_dhash(path: &Path) -> Option<u64> {
+    Some(dhash(&load_image(path)?))
+}
+
+/// Decodes an image for hashing; RAWs through their embedded preview, HEIC isn't supported
+pub fn load_image(path: &Path) -> Option<DynamicImage> {
+    match FileType::from_path(path)? {
+        FileType::Image(ImageFormat::Heic) => None,
+        FileType::Image(format) if format.is_raw() => raw_preview(path),
+        FileType::Image(_) => image::open(path).ok(),
+        _ => None,
+    }
+}
+
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    dhash_from_luma(small.as_raw())
+}
+
+/// dHash of an already downscaled 9x8 grayscale frame (72 bytes, row major)
+pub fn dhash_from_luma(pixels: &[u8]) -> u64 {
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = pixels[y * 9 + x];
+            let right = pixels[y * 9 + x + 1];
+
+            hash = (hash << 1) | (left < right) as u64;
+        }
+    }
+
+    hash
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Edge {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+/// dHash of the strip covering `fraction` of the image along one edge, to compare the part
+/// two neighbouring frames of a panorama have in common
+pub fn edge_dhash(image: &DynamicImage, edge: Edge, fraction: f32) -> u64 {
+    let (width, height) = (image.width(), image.height());
+    let strip_width = ((width as f32 * fraction) as u32).clamp(1, width);
+    let strip_height = ((height as f32 * fraction) as u32).clamp(1, height);
+
+    let strip = match edge {
+        Edge::Left => image.crop_imm(0, 0, strip_width, height),
+        Edge::Right => image.crop_imm(width - strip_width, 0, strip_width, height),
+        Edge::Top => image.crop_imm(0, 0, width, strip_height),
+        Edge::Bottom => image.crop_imm(0, height - strip_height, width, strip_height),
+    };
+
+    dhash(&strip)
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// RAW files can't be decoded here, but they carry a JPEG preview in their thumbnail IFD
+fn raw_preview(path: &Path) -> Option<DynamicImage> {
+    let file = File::open(path).ok()?;
+    let exifreader = Reader::new()
+        .read_from_container(&mut BufReader::new(&file))
+        .ok()?;
+
+    let offset = exifreader
+        .get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    let length = exifreader
+        .get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+
+    let bytes = fs::read(path).ok()?;
+    let preview = bytes.get(offset..offset + length)?;
+
+    image::load_from_memory(preview).ok()
+}
+
+/// BK-tree over Hamming distance, so "everything within N bits" doesn't compare every pair
+struct BkTree {
+    nodes: Vec<(u64, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    fn insert(&mut self, hash: u64) {
+        if self.nodes.is_empty() {
+            self.nodes.push((hash, HashMap::new()));
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(hash, self.nodes[current].0);
+            if distance == 0 {
+                return;
+            }
+
+            match self.nodes[current].1.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push((hash, HashMap::new()));
+                    self.nodes[current].1.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn within(&self, hash: u64, threshold: u32) -> Vec<u64> {
+        let mut found = Vec::new();
+        if self.nodes.is_empty() {
+            return found;
+        }
+
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let (node_hash, children) = &self.nodes[current];
+            let distance = hamming_distance(hash, *node_hash);
+
+            if distance <= threshold {
+                found.push(*node_hash);
+            }
+
+            for (child_distance, child) in children {
+                if child_distance.abs_diff(distance) <= threshold {
+                    stack.push(*child);
+                }
+            }
+        }
+
+        found
+    }
+}
+
+/// Groups `(media_file_id, hash)` pairs whose hashes are within `threshold` bits,
+/// transitively (A~B and B~C puts A, B, C in one cluster). Singletons are dropped.
+/// Each cluster is a list of `(media_file_id, distance from its first member)`.
+pub fn cluster_by_hash(items: &[(i64, u64)], threshold: u32) -> Vec<Vec<(i64, u32)>> {
+    let mut tree = BkTree::new();
+    let mut ids_by_hash: HashMap<u64, Vec<i64>> = HashMap::new();
+
+    for (id, hash) in items {
+        tree.insert(*hash);
+        ids_by_hash.entry(*hash).or_default().push(*id);
+    }
+
+    let mut cluster_of_hash: HashMap<u64, usize> = HashMap::new();
+    let mut clusters: Vec<Vec<u64>> = Vec::new();
+
+    for &(_, hash) in items {
+        if cluster_of_hash.contains_key(&hash) {
+            continue;
+        }
+
+        // Flood fill from this hash through every hash within the threshold
+        let cluster_index = clusters.len();
+        let mut cluster = vec![hash];
+        cluster_of_hash.insert(hash, cluster_index);
+
+        let mut pending = vec![hash];
+        while let Some(current) = pending.pop() {
+            for neighbour in tree.within(current, threshold) {
+                if let Entry::Vacant(e) = cluster_of_hash.entry(neighbour) {
+                    e.insert(cluster_index);
+                    cluster.push(neighbour);
+                    pending.push(neighbour);
+                }
+            }
+        }
+
+        clusters.push(cluster);
+    }
+
+    clusters
+        .into_iter()
+        .filter_map(|hashes| {
+            let reference = hashes[0];
+            let members = hashes
+                .iter()
+                .flat_map(|hash| {
+                    let distance = hamming_distance(reference, *hash);
+                    ids_by_hash[hash].iter().map(move |id| (*id, distance))
+                })
+                .collect::<Vec<_>>();
+
+            (members.len() > 1).then_some(members)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic spread of 64-bit hashes (xorshift)
+    fn hashes(count: usize) -> Vec<u64> {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bk_tree_finds_what_comparing_every_pair_finds() {
+        let mut items = hashes(300);
+        // Near copies of a few of them, so there is something within the threshold
+        let near_copies = items[..20].iter().map(|h| h ^ 0b1011).collect::<Vec<_>>();
+        items.extend(near_copies);
+
+        let mut tree = BkTree::new();
+        for hash in &items {
+            tree.insert(*hash);
+        }
+
+        for threshold in [0, 3, 12] {
+            for query in &items {
+                let mut found = tree.within(*query, threshold);
+                found.sort();
+
+                let mut expected = items
+                    .iter()
+                    .copied()
+                    .filter(|h| hamming_distance(*query, *h) <= threshold)
+                    .collect::<Vec<_>>();
+                expected.sort();
+                expected.dedup();
+
+                assert_eq!(found, expected);
+            }
+        }
+
+        assert!(BkTree::new().within(0, 64).is_empty());
+    }
+
+    #[test]
+    fn clusters_transitively_and_drops_singletons() {
+        let a = 0u64;
+        let b = 0b111; // 3 bits from a
+        let c = 0b111_111; // 3 bits from b, 6 from a
+        let far = u64::MAX;
+
+        let clusters = cluster_by_hash(&[(1, a), (2, b), (3, c), (4, far)], 3);
+        assert_eq!(clusters, [vec![(1, 0), (2, 3), (3, 6)]]);
+
+        // Identical hashes are one node of the tree but every file is a member
+        let clusters = cluster_by_hash(&[(1, far), (2, a), (3, far)], 0);
+        assert_eq!(clusters, [vec![(1, 0), (3, 0)]]);
+
+        assert!(cluster_by_hash(&[(1, a), (2, c)], 3).is_empty());
+        assert!(cluster_by_hash(&[], 8).is_empty());
+    }
+
+    #[test]
+    fn dhash_sets_a_bit_where_the_right_pixel_is_brighter() {
+        let brighter_to_the_right = (0..72).map(|i| (i % 9) as u8 * 10).collect::<Vec<_>>();
+        assert_eq!(dhash_from_luma(&brighter_to_the_right), u64::MAX);
+
+        assert_eq!(dhash_from_luma(&[128; 72]), 0);
+    }
+}
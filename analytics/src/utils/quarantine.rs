@@ -0,0 +1,52 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+/// Where `original` goes inside a session's quarantine folder: its full path is mirrored
+/// below the folder, so two copies with the same name never collide
+pub fn quarantine_path_for(session_folder: &Path, original: &Path) -> PathBuf {
+    let relative = original
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect::<PathBuf>();
+
+    session_folder.join(relative)
+}
+
+/// Moves a file, creating the destination folders. Never overwrites. Falls back to
+/// copy + remove when the destination is on another volume, keeping the mtime.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", to),
+        ));
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let modified = fs::metadata(from)?.modified()?;
+            let copied = fs::copy(from, to)?;
+
+            if copied != fs::metadata(from)?.len() {
+                fs::remove_file(to)?;
+                return Err(io::Error::other("Copy is shorter than the original"));
+            }
+
+            File::options()
+                .write(true)
+                .open(to)?
+                .set_modified(modified)?;
+
+            fs::remove_file(from)
+        }
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,174 @@
+use std::{fs, path::Path, time::UNIX_EPOCH};
+
+use crate::config::{DuplicatesConfig, RankingRule};
+use crate::database::models::SimilarityMemberRow;
+use crate::utils::core::{Media, Rendition};
+
+/// What the ranking rules look at in a copy: a file just scanned, or a catalog row for one
+/// scanned before
+pub trait Ranked: Clone {
+    fn path(&self) -> &Path;
+    fn is_raw(&self) -> bool;
+    /// Width x height in pixels
+    fn resolution(&self) -> Option<(u32, u32)>;
+    /// EXIF fields present, plus the sidecar and keywords
+    fn metadata_fields(&self) -> i64;
+    /// Unix seconds
+    fn modified_at(&self) -> Option<i64>;
+}
+
+impl Ranked for Media {
+    fn path(&self) -> &Path {
+        &self.file_path
+    }
+
+    fn is_raw(&self) -> bool {
+        self.rendition == Rendition::Raw
+    }
+
+    fn resolution(&self) -> Option<(u32, u32)> {
+        self.resolution
+    }
+
+    fn metadata_fields(&self) -> i64 {
+        metadata_richness(self)
+    }
+
+    fn modified_at(&self) -> Option<i64> {
+        fs::metadata(&self.file_path)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64)
+    }
+}
+
+/// Near-duplicate cluster members are ranked from what the catalog recorded when they were
+/// scanned, without reading the files again
+impl Ranked for SimilarityMemberRow {
+    fn path(&self) -> &Path {
+        Path::new(&self.path)
+    }
+
+    fn is_raw(&self) -> bool {
+        self.rendition.as_deref() == Some(Rendition::Raw.as_str())
+    }
+
+    fn resolution(&self) -> Option<(u32, u32)> {
+        self.resolution
+    }
+
+    fn metadata_fields(&self) -> i64 {
+        self.metadata_fields
+    }
+
+    fn modified_at(&self) -> Option<i64> {
+        Some(self.date_modified)
+    }
+}
+
+/// Sorts the copies of one duplicate group or near-duplicate cluster best first and explains
+/// why the first one won. Identical copies only differ in where they are, their sidecars and
+/// mtimes; the format and resolution rules decide between the members of a cluster.
+/// Every rule scores a copy (higher is better), copies are compared rule by rule in the
+/// configured order and anything still tied falls back to the path, so the pick is stable.
+pub fn rank_copies<T: Ranked>(copies: &mut [T], config: &DuplicatesConfig) -> String {
+    let mut scored = copies
+        .iter()
+        .map(|media| {
+            let scores = config
+                .ranking
+                .iter()
+                .map(|rule| rule_score(*rule, media, config))
+                .collect::<Vec<_>>();
+            (scores, media.clone())
+        })
+        .collect::<Vec<_>>();
+
+    scored.sort_by(|(a_scores, a), (b_scores, b)| {
+        b_scores.cmp(a_scores).then_with(|| a.path().cmp(b.path()))
+    });
+
+    let reason = match scored.as_slice() {
+        [] | [_] => "only copy".to_string(),
+        [(best, kept), (runner_up, _), ..] => config
+            .ranking
+            .iter()
+            .zip(best.iter().zip(runner_up))
+            .find(|(_, (a, b))| a != b)
+            .map(|(rule, _)| explain(*rule, kept))
+            .unwrap_or_else(|| "tied on every rule, first by path".to_string()),
+    };
+
+    for (slot, (_, media)) in copies.iter_mut().zip(scored) {
+        *slot = media;
+    }
+
+    reason
+}
+
+fn rule_score(rule: RankingRule, copy: &impl Ranked, config: &DuplicatesConfig) -> i64 {
+    match rule {
+        RankingRule::NotInTrash => !in_folder(copy.path(), &config.trash_folders) as i64,
+        RankingRule::RawOverJpeg => copy.is_raw() as i64,
+        RankingRule::OriginalFolder => in_folder(copy.path(), &config.original_folders) as i64,
+        RankingRule::Resolution => copy
+            .resolution()
+            .map(|(width, height)| width as i64 * height as i64)
+            .unwrap_or(0),
+        RankingRule::Metadata => copy.metadata_fields(),
+        // Older is better, so negate; unreadable mtimes rank last
+        RankingRule::OldestMtime => copy.modified_at().map_or(i64::MIN, |secs| -secs),
+    }
+}
+
+fn explain(rule: RankingRule, kept: &impl Ranked) -> String {
+    match rule {
+        RankingRule::NotInTrash => "not in trash".to_string(),
+        RankingRule::RawOverJpeg => "RAW over JPEG".to_string(),
+        RankingRule::OriginalFolder => "in an original folder".to_string(),
+        RankingRule::Resolution => {
+            let (width, height) = kept.resolution().unwrap_or_default();
+            format!("largest resolution ({}x{})", width, height)
+        }
+        RankingRule::Metadata => format!("richest metadata ({} fields)", kept.metadata_fields()),
+        RankingRule::OldestMtime => "oldest modification time".to_string(),
+    }
+}
+
+/// True when any folder in the path matches one of `names` (case-insensitive)
+fn in_folder(path: &Path, names: &[String]) -> bool {
+    path.parent().is_some_and(|parent| {
+        parent.components().any(|component| {
+            let component = component.as_os_str().to_string_lossy();
+            names
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(&component))
+        })
+    })
+}
+
+/// Number of EXIF fields present, plus the sidecar and its keywords
+fn metadata_richness(media: &Media) -> i64 {
+    let exif_fields = media.exif_data.as_ref().map_or(0, |e| {
+        [
+            &e.camera_make,
+            &e.camera_model,
+            &e.lens_model,
+            &e.date_taken,
+            &e.iso,
+            &e.aperture,
+            &e.shutter_speed,
+            &e.focal_length,
+            &e.software,
+            &e.date_time_original,
+            &e.camera_serial,
+            &e.image_unique_id,
+        ]
+        .iter()
+        .filter(|field| field.is_some())
+        .count()
+    });
+
+    (exif_fields + media.sidecar.is_some() as usize + media.keywords.len()) as i64
+}
@@ -0,0 +1,894 @@
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use quick_xml::{Reader, events::Event};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::S3Config,
+    utils::{core::write_atomic, storage::StorageBackend},
+};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MB: u64 = 1024 * 1024;
+
+/// S3 rejects multipart parts smaller than this, except the last one
+const MIN_PART_SIZE: u64 = 5 * MB;
+
+/// A bucket on any S3-compatible server, addressed path style (`<endpoint>/<bucket>/<key>`)
+/// so MinIO and friends work without DNS set up for every bucket
+pub struct S3Backend {
+    /// `scheme://host[:port]`, what requests are sent to
+    origin: String,
+    /// As sent in the Host header, without a default port
+    host: String,
+    /// Path of the endpoint (`/s3` of `https://example.com/s3`), empty for most servers
+    base_path: String,
+    region: String,
+    bucket: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    multipart_threshold: u64,
+    part_size: u64,
+    transport: Box<dyn HttpTransport>,
+}
+
+/// Sends HTTP requests for `S3Backend`; ureq in practice, a double in tests
+pub trait HttpTransport {
+    /// A status of 404 is an `io::ErrorKind::NotFound` error, any other non-2xx an error too
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse>;
+}
+
+pub struct HttpResponse {
+    /// Names lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Box<dyn Read>,
+}
+
+impl HttpResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn into_string(mut self) -> io::Result<String> {
+        let mut body = String::new();
+        self.body.read_to_string(&mut body)?;
+        Ok(body)
+    }
+}
+
+pub struct UreqTransport {
+    agent: ureq::Agent,
+}
+
+impl HttpTransport for UreqTransport {
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        let request = headers
+            .iter()
+            .fold(self.agent.request(method, url), |request, (name, value)| {
+                request.set(name, value)
+            });
+
+        let response = request.send_bytes(body).map_err(s3_error)?;
+
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = response.header(&name)?.to_string();
+                Some((name.to_lowercase(), value))
+            })
+            .collect();
+
+        Ok(HttpResponse {
+            headers,
+            body: Box::new(response.into_reader()),
+        })
+    }
+}
+
+impl S3Backend {
+    pub fn new(config: &S3Config) -> io::Result<Self> {
+        let transport = UreqTransport {
+            agent: ureq::Agent::new(),
+        };
+
+        Self::with_transport(config, Box::new(transport))
+    }
+
+    pub fn with_transport(
+        config: &S3Config,
+        transport: Box<dyn HttpTransport>,
+    ) -> io::Result<Self> {
+        if config.endpoint.is_empty() || config.bucket.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "No [s3] endpoint and bucket in the config",
+            ));
+        }
+
+        let access_key_id = credential(&config.access_key_id, "AWS_ACCESS_KEY_ID")?;
+        let secret_access_key = credential(&config.secret_access_key, "AWS_SECRET_ACCESS_KEY")?;
+
+        let (origin, host, base_path) = split_endpoint(&config.endpoint);
+
+        Ok(S3Backend {
+            origin,
+            host,
+            base_path,
+            region: config.region.clone(),
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.trim_matches('/').to_string(),
+            access_key_id,
+            secret_access_key,
+            multipart_threshold: config.multipart_threshold_mb * MB,
+            part_size: (config.part_size_mb * MB).max(MIN_PART_SIZE),
+            transport,
+        })
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        match self.prefix.as_str() {
+            "" => key.to_string(),
+            prefix => format!("{}/{}", prefix, key),
+        }
+    }
+
+    fn object_path(&self, key: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.base_path,
+            self.bucket,
+            uri_encode(&self.full_key(key), false)
+        )
+    }
+
+    /// Sends a request for an object, see `send_to`
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        self.send_to(method, &self.object_path(key), query, headers, body)
+    }
+
+    /// Sends a request signed with AWS Signature Version 4. `path` is the canonical URI,
+    /// already encoded; `headers` are extra `x-amz-*` headers, all signed.
+    fn send_to(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> io::Result<HttpResponse> {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let payload_hash = hex_sha256(body);
+        let query = canonical_query(query);
+
+        let mut signed = vec![
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", &payload_hash),
+            ("x-amz-date", &amz_date),
+        ];
+        signed.extend_from_slice(headers);
+        signed.sort();
+
+        let canonical_request = canonical_request(method, path, &query, &signed, &payload_hash);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let signature = signature(
+            &self.secret_access_key,
+            &date,
+            &self.region,
+            "s3",
+            &string_to_sign(&amz_date, &scope, &canonical_request),
+        );
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            scope,
+            signed_headers(&signed),
+            signature
+        );
+
+        let url = match query.as_str() {
+            "" => format!("{}{}", self.origin, path),
+            query => format!("{}{}?{}", self.origin, path, query),
+        };
+
+        // ureq sends the Host header itself, from the same origin
+        let mut sent = signed
+            .into_iter()
+            .filter(|(name, _)| *name != "host")
+            .collect::<Vec<_>>();
+        sent.push(("Authorization", &authorization));
+
+        self.transport.send(method, &url, &sent, body)
+    }
+
+    /// Uploads a file, one request or in parts, each with its SHA-256 for S3 to check.
+    /// With a `content_hash`, what was read from the file has to match it too. True when
+    /// the server confirmed every checksum, which servers that ignore them don't.
+    fn put_object(&self, key: &str, path: &Path, content_hash: Option<&str>) -> io::Result<bool> {
+        if fs::metadata(path)?.len() > self.multipart_threshold {
+            return self.put_multipart(key, path, content_hash);
+        }
+
+        let body = fs::read(path)?;
+        check_content_hash(&Sha256::digest(&body), content_hash, path)?;
+
+        let checksum = STANDARD.encode(Sha256::digest(&body));
+        let response = self.send(
+            "PUT",
+            key,
+            &[],
+            &[("x-amz-checksum-sha256", &checksum)],
+            &body,
+        )?;
+
+        Ok(response.header("x-amz-checksum-sha256") == Some(checksum.as_str()))
+    }
+
+    /// Create, upload every part, complete. Aborted on the first failure so the
+    /// bucket isn't left holding orphaned parts.
+    fn put_multipart(
+        &self,
+        key: &str,
+        path: &Path,
+        content_hash: Option<&str>,
+    ) -> io::Result<bool> {
+        let response = self.send(
+            "POST",
+            key,
+            &[("uploads", "")],
+            &[("x-amz-checksum-algorithm", "SHA256")],
+            &[],
+        )?;
+        let upload_id = xml_value(&response.into_string()?, "UploadId")
+            .ok_or_else(|| io::Error::other("No UploadId in CreateMultipartUpload response"))?;
+
+        let result = self.upload_parts(key, path, &upload_id, content_hash);
+
+        if result.is_err() {
+            let _ = self.send("DELETE", key, &[("uploadId", &upload_id)], &[], &[]);
+        }
+
+        result
+    }
+
+    fn upload_parts(
+        &self,
+        key: &str,
+        path: &Path,
+        upload_id: &str,
+        content_hash: Option<&str>,
+    ) -> io::Result<bool> {
+        let mut file = File::open(path)?;
+        let mut completed = String::from("<CompleteMultipartUpload>");
+        let mut hasher = Sha256::new();
+        let mut confirmed = true;
+
+        for part_number in 1.. {
+            let mut part = Vec::with_capacity(self.part_size as usize);
+            file.by_ref().take(self.part_size).read_to_end(&mut part)?;
+
+            if part.is_empty() {
+                break;
+            }
+
+            hasher.update(&part);
+            let checksum = STANDARD.encode(Sha256::digest(&part));
+
+            let part_number = part_number.to_string();
+            let response = self.send(
+                "PUT",
+                key,
+                &[("partNumber", &part_number), ("uploadId", upload_id)],
+                &[("x-amz-checksum-sha256", &checksum)],
+                &part,
+            )?;
+
+            let etag = response
+                .header("ETag")
+                .ok_or_else(|| io::Error::other("No ETag in UploadPart response"))?;
+            confirmed &= response.header("x-amz-checksum-sha256") == Some(checksum.as_str());
+
+            completed.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag><ChecksumSHA256>{}</ChecksumSHA256></Part>",
+                part_number, etag, checksum
+            ));
+        }
+
+        // The file changed while it was being read
+        check_content_hash(&hasher.finalize(), content_hash, path)?;
+
+        completed.push_str("</CompleteMultipartUpload>");
+
+        let response = self.send(
+            "POST",
+            key,
+            &[("uploadId", upload_id)],
+            &[],
+            completed.as_bytes(),
+        )?;
+
+        // CompleteMultipartUpload can fail with a 200 and an error document
+        let body = response.into_string()?;
+        if body.contains("<Error>") {
+            return Err(io::Error::other(format!(
+                "CompleteMultipartUpload failed: {}",
+                body
+            )));
+        }
+
+        Ok(confirmed)
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn name(&self) -> String {
+        match self.prefix.as_str() {
+            "" => format!("s3://{}", self.bucket),
+            prefix => format!("s3://{}/{}", self.bucket, prefix),
+        }
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.send("HEAD", key, &[], &[], &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, path: &Path) -> io::Result<()> {
+        self.put_object(key, path, None)?;
+
+        Ok(())
+    }
+
+    fn put_checked(&self, key: &str, path: &Path, content_hash: &str) -> io::Result<bool> {
+        self.put_object(key, path, Some(content_hash))
+    }
+
+    fn get(&self, key: &str, path: &Path) -> io::Result<()> {
+        let mut response = self.send("GET", key, &[], &[], &[])?;
+
+        write_atomic(path, &mut response.body)
+    }
+
+    /// ListObjectsV2, a page of up to 1000 keys at a time
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let bucket_path = format!("{}/{}", self.base_path, self.bucket);
+        let full_prefix = self.full_key(prefix);
+        let root = self.full_key("");
+
+        let mut keys = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+            if let Some(token) = &continuation_token {
+                query.push(("continuation-token", token.as_str()));
+            }
+
+            let body = self
+                .send_to("GET", &bucket_path, &query, &[], &[])?
+                .into_string()?;
+
+            keys.extend(
+                xml_values(&body, "Key")
+                    .into_iter()
+                    .map(|key| key.strip_prefix(&root).unwrap_or(&key).to_string()),
+            );
+
+            continuation_token = match xml_value(&body, "IsTruncated").as_deref() {
+                Some("true") => xml_value(&body, "NextContinuationToken"),
+                _ => None,
+            };
+
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.send("DELETE", key, &[], &[], &[])?;
+
+        Ok(())
+    }
+}
+
+fn check_content_hash(digest: &[u8], content_hash: Option<&str>, path: &Path) -> io::Result<()> {
+    match content_hash {
+        Some(content_hash) if hex(digest) != content_hash => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} no longer hashes to {}", path, content_hash),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn credential(configured: &str, variable: &str) -> io::Result<String> {
+    if !configured.is_empty() {
+        return Ok(configured.to_string());
+    }
+
+    env::var(variable).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("No S3 credentials, set them in [s3] or {}", variable),
+        )
+    })
+}
+
+/// `https://example.com:9000/s3/` -> (`https://example.com:9000`, `example.com:9000`, `/s3`).
+/// The path goes into the canonical URI, never the Host header.
+fn split_endpoint(endpoint: &str) -> (String, String, String) {
+    let endpoint = endpoint.trim_end_matches('/');
+    let (scheme, rest) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+
+    let (authority, base_path) = match rest.find('/') {
+        Some(at) => rest.split_at(at),
+        None => (rest, ""),
+    };
+
+    let default_port = if scheme == "http" { ":80" } else { ":443" };
+    let host = authority.strip_suffix(default_port).unwrap_or(authority);
+
+    (
+        format!("{}://{}", scheme, authority),
+        host.to_string(),
+        base_path.to_string(),
+    )
+}
+
+/// Sorted `name=value` pairs, both encoded, `&`-joined
+fn canonical_query(query: &[(&str, &str)]) -> String {
+    let mut query = query
+        .iter()
+        .map(|(k, v)| format!("{}={}", uri_encode(k, true), uri_encode(v, true)))
+        .collect::<Vec<_>>();
+    query.sort();
+    query.join("&")
+}
+
+/// `headers` are lowercase and sorted by name, their values trimmed
+fn canonical_request(
+    method: &str,
+    path: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload_hash: &str,
+) -> String {
+    let canonical_headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect::<String>();
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        path,
+        query,
+        canonical_headers,
+        signed_headers(headers),
+        payload_hash
+    )
+}
+
+fn signed_headers(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex_sha256(canonical_request.as_bytes())
+    )
+}
+
+fn signature(
+    secret_access_key: &str,
+    date: &str,
+    region: &str,
+    service: &str,
+    string_to_sign: &str,
+) -> String {
+    let key = format!("AWS4{}", secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    let key = hmac_sha256(&key, b"aws4_request");
+
+    hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent encoding as SigV4 wants it: everything but `A-Za-z0-9-_.~` (and `/` in paths)
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !encode_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_values(xml, tag).into_iter().next()
+}
+
+/// Text of every `<tag>` element, unescaped
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut values = Vec::new();
+    let mut inside = false;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => inside = e.local_name().as_ref() == tag.as_bytes(),
+            Ok(Event::Text(text)) if inside => {
+                if let Ok(text) = text.unescape() {
+                    values.push(text.into_owned());
+                }
+            }
+            Ok(Event::End(_)) => inside = false,
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    values
+}
+
+fn s3_error(e: ureq::Error) -> io::Error {
+    match e {
+        ureq::Error::Status(404, _) => io::Error::from(io::ErrorKind::NotFound),
+        ureq::Error::Status(status, response) => io::Error::other(format!(
+            "S3 returned {}: {}",
+            status,
+            response.into_string().unwrap_or_default()
+        )),
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use super::*;
+
+    const EMPTY_PAYLOAD_HASH: &str =
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    /// `get-vanilla` from the AWS Signature Version 4 test suite
+    #[test]
+    fn signs_the_get_vanilla_test_vector() {
+        let canonical_request = canonical_request(
+            "GET",
+            "/",
+            "",
+            &[
+                ("host", "example.amazonaws.com"),
+                ("x-amz-date", "20150830T123600Z"),
+            ],
+            EMPTY_PAYLOAD_HASH,
+        );
+
+        assert_eq!(
+            canonical_request,
+            "GET\n/\n\nhost:example.amazonaws.com\nx-amz-date:20150830T123600Z\n\nhost;x-amz-date\n\
+             e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let string_to_sign = string_to_sign(
+            "20150830T123600Z",
+            "20150830/us-east-1/service/aws4_request",
+            &canonical_request,
+        );
+
+        assert_eq!(
+            string_to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n\
+             bb579772317eb040ac9ed261061d46c1f17a8133879d6129b6e1c25292927e63"
+        );
+
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20150830",
+                "us-east-1",
+                "service",
+                &string_to_sign
+            ),
+            "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    /// The GET Object example of the S3 SigV4 documentation
+    #[test]
+    fn signs_the_s3_get_object_example() {
+        let canonical_request = canonical_request(
+            "GET",
+            "/test.txt",
+            "",
+            &[
+                ("host", "examplebucket.s3.amazonaws.com"),
+                ("range", "bytes=0-9"),
+                ("x-amz-content-sha256", EMPTY_PAYLOAD_HASH),
+                ("x-amz-date", "20130524T000000Z"),
+            ],
+            EMPTY_PAYLOAD_HASH,
+        );
+
+        let string_to_sign = string_to_sign(
+            "20130524T000000Z",
+            "20130524/us-east-1/s3/aws4_request",
+            &canonical_request,
+        );
+
+        assert!(
+            string_to_sign
+                .ends_with("7344ae5b7ee6c3e7e6b0fe0640412a37625d1fbfff95c48bbb2dc43964946972")
+        );
+
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "20130524",
+                "us-east-1",
+                "s3",
+                &string_to_sign
+            ),
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+    }
+
+    /// The ListObjects example of the S3 SigV4 documentation, signed like `send_to` does
+    #[test]
+    fn signs_the_s3_list_objects_example() {
+        let query = canonical_query(&[("prefix", "J"), ("max-keys", "2")]);
+        assert_eq!(query, "max-keys=2&prefix=J");
+
+        let canonical_request = canonical_request(
+            "GET",
+            "/",
+            &query,
+            &[
+                ("host", "examplebucket.s3.amazonaws.com"),
+                ("x-amz-content-sha256", EMPTY_PAYLOAD_HASH),
+                ("x-amz-date", "20130524T000000Z"),
+            ],
+            EMPTY_PAYLOAD_HASH,
+        );
+
+        let string_to_sign = string_to_sign(
+            "20130524T000000Z",
+            "20130524/us-east-1/s3/aws4_request",
+            &canonical_request,
+        );
+
+        assert_eq!(
+            signature(
+                "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+                "20130524",
+                "us-east-1",
+                "s3",
+                &string_to_sign
+            ),
+            "34b48302e7b5fa45bde8084f4b7868a86f0a534bc59db6670ed5711ef69dc6f7"
+        );
+    }
+
+    #[test]
+    fn keeps_the_endpoint_path_out_of_the_host() {
+        assert_eq!(
+            split_endpoint("https://example.com/s3/"),
+            (
+                "https://example.com".to_string(),
+                "example.com".to_string(),
+                "/s3".to_string()
+            )
+        );
+        assert_eq!(
+            split_endpoint("http://localhost:9000"),
+            (
+                "http://localhost:9000".to_string(),
+                "localhost:9000".to_string(),
+                String::new()
+            )
+        );
+        assert_eq!(
+            split_endpoint("https://s3.amazonaws.com:443").1,
+            "s3.amazonaws.com"
+        );
+    }
+
+    type Sent = Rc<RefCell<Vec<(String, String)>>>;
+
+    /// Answers requests from a queue of (status, body), recording method and URL
+    struct FakeTransport {
+        sent: Sent,
+        responses: RefCell<VecDeque<(u16, &'static str)>>,
+    }
+
+    impl HttpTransport for FakeTransport {
+        fn send(
+            &self,
+            method: &str,
+            url: &str,
+            headers: &[(&str, &str)],
+            _body: &[u8],
+        ) -> io::Result<HttpResponse> {
+            assert!(headers.iter().any(|(name, _)| *name == "Authorization"));
+            self.sent
+                .borrow_mut()
+                .push((method.to_string(), url.to_string()));
+
+            // Like S3, confirms the checksum it was sent
+            let mut response_headers = vec![("etag".to_string(), "\"etag\"".to_string())];
+            response_headers.extend(
+                headers
+                    .iter()
+                    .filter(|(name, _)| *name == "x-amz-checksum-sha256")
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            );
+
+            match self.responses.borrow_mut().pop_front() {
+                Some((404, _)) => Err(io::Error::from(io::ErrorKind::NotFound)),
+                Some((200, body)) => Ok(HttpResponse {
+                    headers: response_headers,
+                    body: Box::new(body.as_bytes()),
+                }),
+                Some((status, _)) => Err(io::Error::other(format!("S3 returned {}", status))),
+                None => panic!("Unexpected request {} {}", method, url),
+            }
+        }
+    }
+
+    fn backend(endpoint: &str, responses: &[(u16, &'static str)]) -> (S3Backend, Sent) {
+        let sent = Sent::default();
+        let transport = FakeTransport {
+            sent: sent.clone(),
+            responses: RefCell::new(responses.iter().copied().collect()),
+        };
+
+        let config = S3Config {
+            endpoint: endpoint.to_string(),
+            bucket: "photos".to_string(),
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "secret".to_string(),
+            ..S3Config::default()
+        };
+
+        (
+            S3Backend::with_transport(&config, Box::new(transport)).unwrap(),
+            sent,
+        )
+    }
+
+    #[test]
+    fn sends_objects_under_the_endpoint_path() {
+        let (s3, sent) = backend("https://example.com/s3", &[(200, ""), (404, "")]);
+
+        assert!(s3.exists("objects/ab cd").unwrap());
+        assert!(!s3.exists("objects/missing").unwrap());
+
+        assert_eq!(
+            sent.borrow()[0],
+            (
+                "HEAD".to_string(),
+                "https://example.com/s3/photos/photo_app_rs/objects/ab%20cd".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn lists_every_page_without_the_prefix() {
+        let (s3, sent) = backend(
+            "http://localhost:9000",
+            &[
+                (
+                    200,
+                    "<ListBucketResult><IsTruncated>true</IsTruncated>\
+                     <Contents><Key>photo_app_rs/objects/b</Key></Contents>\
+                     <NextContinuationToken>next/1</NextContinuationToken></ListBucketResult>",
+                ),
+                (
+                    200,
+                    "<ListBucketResult><IsTruncated>false</IsTruncated>\
+                     <Contents><Key>photo_app_rs/objects/a</Key></Contents></ListBucketResult>",
+                ),
+            ],
+        );
+
+        assert_eq!(s3.list("objects/").unwrap(), ["objects/a", "objects/b"]);
+
+        let sent = sent.borrow();
+        assert_eq!(
+            sent[1].1,
+            "http://localhost:9000/photos?continuation-token=next%2F1&list-type=2&prefix=photo_app_rs%2Fobjects%2F"
+        );
+    }
+
+    #[test]
+    fn uploads_are_checked_by_the_server() {
+        let file = env::temp_dir().join(format!("photo_app_rs_test_{}_s3", std::process::id()));
+        fs::write(&file, b"photo").unwrap();
+        let content_hash = hex_sha256(b"photo");
+
+        let (s3, sent) = backend("http://localhost:9000", &[(200, "")]);
+        assert!(s3.put_checked("a.jpg", &file, &content_hash).unwrap());
+
+        // A file that changed since it was hashed is never sent
+        let error = s3
+            .put_checked("a.jpg", &file, EMPTY_PAYLOAD_HASH)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(sent.borrow().len(), 1);
+
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs, io,
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{config::SftpConfig, utils::storage::StorageBackend};
+
+/// A folder on an SSH server. Every operation is a batch run of the system `sftp`
+/// client, so it authenticates however `ssh` does (agent, keys, `~/.ssh/config`)
+/// and works on SFTP-only accounts that have no shell.
+pub struct SftpBackend {
+    host: String,
+    port: Option<u16>,
+    root: String,
+}
+
+impl SftpBackend {
+    pub fn new(config: &SftpConfig) -> Self {
+        SftpBackend {
+            host: config.host.clone(),
+            port: config.port,
+            root: config.path.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn remote_path(&self, key: &str) -> String {
+        match key {
+            "" => self.root.clone(),
+            key => format!("{}/{}", self.root, key),
+        }
+    }
+
+    /// Runs the commands in one session and returns what they printed. Batch mode stops
+    /// at the first failing command, except those prefixed with `-`.
+    fn batch(&self, commands: &[String]) -> io::Result<String> {
+        let mut sftp = Command::new("sftp");
+        sftp.args(["-q", "-b", "-", "-o", "BatchMode=yes"]);
+
+        if let Some(port) = self.port {
+            sftp.args(["-P", &port.to_string()]);
+        }
+
+        let mut child = sftp
+            .arg(&self.host)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(commands.join("\n").as_bytes())?;
+        }
+
+        let output = child.wait_with_output()?;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        if !output.status.success() {
+            let kind = if stderr.contains("not found") || stderr.contains("No such file") {
+                io::ErrorKind::NotFound
+            } else {
+                io::ErrorKind::Other
+            };
+
+            return Err(io::Error::new(
+                kind,
+                format!("sftp to {} failed: {}", self.host, stderr.trim()),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl StorageBackend for SftpBackend {
+    fn name(&self) -> String {
+        format!("sftp://{}{}", self.host, self.root)
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.batch(&[format!("ls {}", quote(&self.remote_path(key)))]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn put(&self, key: &str, path: &Path) -> io::Result<()> {
+        let remote_path = self.remote_path(key);
+        let partial = format!("{}.part", remote_path);
+
+        // Every folder down from the root, as `mkdir -p` isn't a thing in sftp.
+        // mkdir fails on folders that exist already, hence `-`
+        let mut commands = remote_path
+            .match_indices('/')
+            .filter(|(i, _)| *i > 0)
+            .map(|(i, _)| format!("-mkdir {}", quote(&remote_path[..i])))
+            .collect::<Vec<_>>();
+
+        commands.push(format!(
+            "put {} {}",
+            quote(&path.to_string_lossy()),
+            quote(&partial)
+        ));
+        commands.push(format!("-rm {}", quote(&remote_path)));
+        commands.push(format!(
+            "rename {} {}",
+            quote(&partial),
+            quote(&remote_path)
+        ));
+
+        self.batch(&commands)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let partial = path.with_file_name(format!(
+            "{}.part",
+            path.file_name().and_then(|n| n.to_str()).unwrap_or("")
+        ));
+
+        self.batch(&[format!(
+            "get {} {}",
+            quote(&self.remote_path(key)),
+            quote(&partial.to_string_lossy())
+        )])?;
+
+        fs::rename(&partial, path)
+    }
+
+    /// Lists one level of folders per session
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut folders = vec![String::new()];
+
+        while !folders.is_empty() {
+            let commands = folders
+                .iter()
+                .map(|folder| format!("ls -la {}", quote(&self.remote_path(folder))))
+                .collect::<Vec<_>>();
+
+            folders.clear();
+
+            let listing = match self.batch(&commands) {
+                Ok(listing) => listing,
+                // Nothing was ever put
+                Err(e) if e.kind() == io::ErrorKind::NotFound && keys.is_empty() => break,
+                Err(e) => return Err(e),
+            };
+
+            for (remote_path, is_folder) in parse_listing(&listing) {
+                let Some(key) = remote_path
+                    .strip_prefix(&self.root)
+                    .map(|key| key.trim_start_matches('/').to_string())
+                else {
+                    continue;
+                };
+
+                if is_folder {
+                    let folder_prefix = format!("{}/", key);
+                    if folder_prefix.starts_with(prefix) || prefix.starts_with(&folder_prefix) {
+                        folders.push(key);
+                    }
+                } else if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.batch(&[format!("rm {}", quote(&self.remote_path(key)))])?;
+
+        Ok(())
+    }
+}
+
+/// Paths in `ls -la <folder>` output, which sftp prints as `<folder>/<name>`, and
+/// whether each one is a folder. `.` and `..` are skipped.
+fn parse_listing(output: &str) -> Vec<(String, bool)> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with("sftp>"))
+        .filter_map(|line| {
+            // permissions, links, owner, group, size, month, day, time/year, then the path
+            let mut rest = line.trim_start();
+            for _ in 0..8 {
+                let end = rest.find(char::is_whitespace)?;
+                rest = rest[end..].trim_start();
+            }
+
+            let name = rest.rsplit('/').next().unwrap_or(rest);
+            if name == "." || name == ".." {
+                return None;
+            }
+
+            Some((rest.to_string(), line.starts_with('d')))
+        })
+        .collect()
+}
+
+/// Quotes a path for an sftp batch file
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,257 @@
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const SIDECAR_EXTENSIONS: &[&str] = &["xmp", "XMP"];
+
+/// XMP sidecar written by Lightroom (`IMG_0001.xmp`) or darktable (`IMG_0001.ARW.xmp`)
+#[derive(Debug, Clone, Serialize)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    pub rating: Option<i32>,
+    pub label: Option<String>,
+    pub keywords: Vec<String>,
+    pub title: Option<String>,
+}
+
+impl Sidecar {
+    /// Looks for a sidecar next to the media file and parses it if found
+    pub fn from_media_path(media_path: &Path) -> Option<Self> {
+        let sidecar_path = find_sidecar(media_path)?;
+
+        let xml = match fs::read_to_string(&sidecar_path) {
+            Ok(xml) => xml,
+            Err(e) => {
+                println!(
+                    "Error reading sidecar; Path : {:?}; Error : {:?}",
+                    sidecar_path.to_str(),
+                    e
+                );
+                return None;
+            }
+        };
+
+        Some(Self::parse(sidecar_path, &xml))
+    }
+
+    pub fn parse(path: PathBuf, xml: &str) -> Self {
+        Sidecar {
+            path,
+            rating: xmp_value(xml, "xmp:Rating").and_then(|r| r.trim().parse().ok()),
+            label: xmp_value(xml, "xmp:Label").filter(|l| !l.is_empty()),
+            keywords: xmp_keywords(xml),
+            title: xmp_list(xml, "dc:title").into_iter().next(),
+        }
+    }
+
+    /// Where the sidecar should land when its media file is exported to `media_final_path`.
+    ///
+    /// darktable style sidecars keep the full media file name (`name.ARW.xmp`),
+    /// Lightroom style ones replace the extension (`name.xmp`).
+    pub fn final_path_for(&self, media_path: &Path, media_final_path: &Path) -> PathBuf {
+        let sidecar_name = self.path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let sidecar_ext = self
+            .path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("xmp");
+        let media_name = media_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("");
+
+        if sidecar_name.eq_ignore_ascii_case(&format!("{}.{}", media_name, sidecar_ext)) {
+            let final_name = media_final_path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("");
+            media_final_path.with_file_name(format!("{}.{}", final_name, sidecar_ext))
+        } else {
+            media_final_path.with_extension(sidecar_ext)
+        }
+    }
+}
+
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| SIDECAR_EXTENSIONS.contains(&e))
+}
+
+fn find_sidecar(media_path: &Path) -> Option<PathBuf> {
+    let file_name = media_path.file_name()?.to_str()?;
+
+    for ext in SIDECAR_EXTENSIONS {
+        let candidates = [
+            media_path.with_file_name(format!("{}.{}", file_name, ext)),
+            media_path.with_extension(ext),
+        ];
+
+        if let Some(found) = candidates.into_iter().find(|c| c.is_file()) {
+            return Some(found);
+        }
+    }
+
+    None
+}
+
+/// Reads a simple property, written either as an attribute (`xmp:Rating="3"`)
+/// or as an element (`<xmp:Rating>3</xmp:Rating>`)
+pub fn xmp_value(xml: &str, name: &str) -> Option<String> {
+    let attr = format!("{}=\"", name);
+    if let Some(start) = xml.find(&attr) {
+        let rest = &xml[start + attr.len()..];
+        let end = rest.find('"')?;
+        return Some(decode_entities(&rest[..end]));
+    }
+
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+
+    Some(decode_entities(xml[start..end].trim()))
+}
+
+/// Flat keywords (`dc:subject`) plus Lightroom's hierarchical ones (`lr:hierarchicalSubject`),
+/// the latter converted from `trips|2025|japan` to `trips/2025/japan`
+pub fn xmp_keywords(xml: &str) -> Vec<String> {
+    let mut keywords = xmp_list(xml, "dc:subject");
+
+    keywords.extend(
+        xmp_list(xml, "lr:hierarchicalSubject")
+            .into_iter()
+            .map(|k| k.replace('|', "/")),
+    );
+
+    keywords
+}
+
+/// Reads the `rdf:li` items of a Bag/Seq/Alt property such as `dc:subject`
+pub fn xmp_list(xml: &str, name: &str) -> Vec<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+
+    let Some(start) = xml.find(&open).map(|s| s + open.len()) else {
+        return Vec::new();
+    };
+    let Some(end) = xml[start..].find(&close).map(|e| e + start) else {
+        return Vec::new();
+    };
+
+    let mut items = Vec::new();
+    let mut rest = &xml[start..end];
+
+    while let Some(li_start) = rest.find("<rdf:li") {
+        rest = &rest[li_start..];
+        let Some(content_start) = rest.find('>').map(|i| i + 1) else {
+            break;
+        };
+        let Some(content_end) = rest.find("</rdf:li>") else {
+            break;
+        };
+
+        let item = decode_entities(rest[content_start..content_end].trim());
+        if !item.is_empty() {
+            items.push(item);
+        }
+
+        rest = &rest[content_end + "</rdf:li>".len()..];
+    }
+
+    items
+}
+
+fn decode_entities(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTROOM_XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmp:Rating="4" xmp:Label="Red">
+   <dc:title>
+    <rdf:Alt>
+     <rdf:li xml:lang="x-default">Fish &amp; chips</rdf:li>
+    </rdf:Alt>
+   </dc:title>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>japan</rdf:li>
+     <rdf:li></rdf:li>
+     <rdf:li>food</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <lr:hierarchicalSubject>
+    <rdf:Bag>
+     <rdf:li>trips|2025|japan</rdf:li>
+    </rdf:Bag>
+   </lr:hierarchicalSubject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn reads_attributes_elements_and_lists() {
+        let sidecar = Sidecar::parse(PathBuf::from("IMG_0001.xmp"), LIGHTROOM_XMP);
+
+        assert_eq!(sidecar.rating, Some(4));
+        assert_eq!(sidecar.label.as_deref(), Some("Red"));
+        assert_eq!(sidecar.title.as_deref(), Some("Fish & chips"));
+        assert_eq!(sidecar.keywords, ["japan", "food", "trips/2025/japan"]);
+
+        let darktable = "<xmp:Rating>2</xmp:Rating><xmp:Label></xmp:Label>";
+        assert_eq!(xmp_value(darktable, "xmp:Rating").as_deref(), Some("2"));
+        assert_eq!(
+            Sidecar::parse(PathBuf::from("a.ARW.xmp"), darktable).label,
+            None
+        );
+    }
+
+    #[test]
+    fn empty_or_broken_lists_have_no_items() {
+        assert!(xmp_list("<dc:subject><rdf:Bag/></dc:subject>", "dc:subject").is_empty());
+        assert!(xmp_list("<dc:subject></dc:subject>", "dc:subject").is_empty());
+        assert!(xmp_list("", "dc:subject").is_empty());
+
+        // Never closed: nothing is read past what the file holds
+        assert!(xmp_list("<dc:subject><rdf:Bag><rdf:li>a", "dc:subject").is_empty());
+        assert_eq!(
+            xmp_list(
+                "<dc:subject><rdf:li>a</rdf:li><rdf:li>b</dc:subject>",
+                "dc:subject"
+            ),
+            ["a"]
+        );
+        assert_eq!(xmp_value(r#"xmp:Rating="3"#, "xmp:Rating"), None);
+        assert_eq!(xmp_value("<xmp:Rating>3", "xmp:Rating"), None);
+    }
+
+    #[test]
+    fn sidecar_names_follow_the_media_file() {
+        let lightroom = Sidecar::parse(PathBuf::from("/card/IMG_0001.xmp"), "");
+        let darktable = Sidecar::parse(PathBuf::from("/card/IMG_0001.ARW.xmp"), "");
+
+        let media = Path::new("/card/IMG_0001.ARW");
+        let final_path = Path::new("/library/2025/raw/2025-01-02_0001.ARW");
+
+        assert_eq!(
+            lightroom.final_path_for(media, final_path),
+            Path::new("/library/2025/raw/2025-01-02_0001.xmp")
+        );
+        assert_eq!(
+            darktable.final_path_for(media, final_path),
+            Path::new("/library/2025/raw/2025-01-02_0001.ARW.xmp")
+        );
+    }
+}
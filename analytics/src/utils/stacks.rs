@@ -0,0 +1,752 @@
+use chrono::NaiveDateTime;
+use image::DynamicImage;
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    database::operations,
+    utils::{
+        core::{Media, Rendition},
+        perceptual::{Edge, dhash, edge_dhash, hamming_distance, load_image},
+    },
+};
+
+pub const PANORAMA_TAG: &str = "panorama";
+
+/// Max time between two frames for them to be part of one sequence
+const MAX_FRAME_GAP_SECONDS: f64 = 1.0;
+/// Two frames in a row is someone pressing the shutter twice, not a burst
+const MIN_BURST_FRAMES: usize = 3;
+/// Panning to the next frame of a panorama takes longer than a burst
+const MAX_PANORAMA_GAP_SECONDS: f64 = 5.0;
+const MIN_PANORAMA_FRAMES: usize = 3;
+/// Max differing bits between the edge strips two neighbouring panorama frames share
+const PANORAMA_EDGE_THRESHOLD: u32 = 10;
+/// Overlaps between panorama frames tried, as a fraction of the frame
+const PANORAMA_OVERLAPS: [f32; 8] = [0.15, 0.2, 0.25, 0.3, 0.35, 0.4, 0.45, 0.5];
+/// Whole frames closer than this show the same view, not the next part of a panorama
+const SAME_VIEW_THRESHOLD: u32 = 10;
+
+/// One shot: every file of its asset (a RAW + JPEG pair is one frame), RAW first
+type Frame<'a> = Vec<&'a Media>;
+
+/// A burst, AE bracket, focus bracket or panorama, frames in capture order
+pub struct Stack<'a> {
+    pub kind: &'static str,
+    pub camera: String,
+    pub frames: Vec<Frame<'a>>,
+    /// Index into `frames` of the designated pick
+    pub pick: usize,
+}
+
+/// Finds burst, bracket and panorama sequences among the images of one folder.
+///
+/// Panoramas first: frames from the same camera a few seconds apart with the same exposure
+/// and focal length, where an edge strip of each frame matches the opposite edge of the next
+/// while the frames as a whole differ. In what's left, frames less than a second apart form
+/// runs. Runs where the exposure bias changes (or that EXIF marks as "auto bracket") are AE
+/// brackets, split every time the bias pattern starts over. Runs where the subject distance
+/// moves the same way on every frame are focus brackets, the rest of the runs long enough are
+/// bursts. Drive mode lives in the maker notes, which aren't read.
+///
+/// `overlaps` tells whether frame `b` carries on the panorama of frame `a`, see
+/// `frames_overlap`.
+pub fn find_stacks<'a>(
+    media_items: &'a [Media],
+    overlaps: &mut dyn FnMut(&Media, &Media) -> bool,
+) -> Vec<Stack<'a>> {
+    let mut shots: HashMap<&str, Frame> = HashMap::new();
+
+    for media in media_items {
+        if !media.file_type.is_image() || media.rendition == Rendition::Edited {
+            continue;
+        }
+
+        let key = media.asset_key.as_deref().unwrap_or(&media.hash);
+        shots.entry(key).or_default().push(media);
+    }
+
+    let mut timed = shots
+        .into_values()
+        .filter_map(|mut files| {
+            // RAW first, it carries the EXIF we look at
+            files.sort_by_key(|m| m.rendition != Rendition::Raw);
+            let time = capture_seconds(files[0])?;
+            Some((camera(files[0]), time, files))
+        })
+        .collect::<Vec<_>>();
+
+    timed.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+    let mut stacks = Vec::new();
+
+    for (camera, frames) in split_runs(timed, MAX_PANORAMA_GAP_SECONDS) {
+        for (is_panorama, part) in split_panoramas(frames, overlaps) {
+            if is_panorama {
+                let frames = part.into_iter().map(|(_, files)| files).collect();
+                stacks.push(new_stack("panorama", camera.clone(), frames));
+                continue;
+            }
+
+            let part = part
+                .into_iter()
+                .map(|(time, files)| (camera.clone(), time, files))
+                .collect();
+
+            for (_, frames) in split_runs(part, MAX_FRAME_GAP_SECONDS) {
+                let frames = frames.into_iter().map(|(_, files)| files).collect();
+                stacks.extend(classify_run(camera.clone(), frames));
+            }
+        }
+    }
+
+    stacks
+}
+
+/// Splits time sorted frames into runs of one camera with at most `max_gap` between frames
+fn split_runs(
+    timed: Vec<(String, f64, Frame<'_>)>,
+    max_gap: f64,
+) -> Vec<(String, Vec<(f64, Frame<'_>)>)> {
+    let mut runs: Vec<(String, Vec<(f64, Frame)>)> = Vec::new();
+
+    for (camera, time, files) in timed {
+        match runs.last_mut() {
+            Some((run_camera, frames))
+                if *run_camera == camera
+                    && frames
+                        .last()
+                        .is_some_and(|(last, _)| time - last <= max_gap) =>
+            {
+                frames.push((time, files))
+            }
+            _ => runs.push((camera, vec![(time, files)])),
+        }
+    }
+
+    runs
+}
+
+/// Cuts a run into parts that are panoramas and parts that aren't, keeping the order
+fn split_panoramas<'a>(
+    frames: Vec<(f64, Frame<'a>)>,
+    overlaps: &mut dyn FnMut(&Media, &Media) -> bool,
+) -> Vec<(bool, Vec<(f64, Frame<'a>)>)> {
+    // continues[i]: frame i + 1 carries on the panorama of frame i
+    let continues = frames
+        .windows(2)
+        .map(|pair| {
+            let (a, b) = (pair[0].1[0], pair[1].1[0]);
+            same_exposure(a, b) && overlaps(a, b)
+        })
+        .collect::<Vec<_>>();
+
+    let mut parts: Vec<(bool, Vec<(f64, Frame)>)> = Vec::new();
+    let mut current: Vec<(f64, Frame)> = Vec::new();
+
+    for (index, frame) in frames.into_iter().enumerate() {
+        current.push(frame);
+
+        // Close the current stretch of linked frames at the end of the chain
+        if !continues.get(index).copied().unwrap_or(false) {
+            let chain = std::mem::take(&mut current);
+            let is_panorama = chain.len() >= MIN_PANORAMA_FRAMES;
+
+            match parts.last_mut() {
+                Some((false, rest)) if !is_panorama => rest.extend(chain),
+                _ => parts.push((is_panorama, chain)),
+            }
+        }
+    }
+
+    parts
+}
+
+/// An edge strip of `a` matches the opposite edge of `b` for one of the usual overlaps,
+/// but the frames as a whole don't
+fn frames_overlap(a: &DynamicImage, b: &DynamicImage) -> bool {
+    if hamming_distance(dhash(a), dhash(b)) <= SAME_VIEW_THRESHOLD {
+        return false;
+    }
+
+    let directions = [
+        (Edge::Right, Edge::Left),
+        (Edge::Left, Edge::Right),
+        (Edge::Bottom, Edge::Top),
+        (Edge::Top, Edge::Bottom),
+    ];
+
+    directions.iter().any(|(edge_a, edge_b)| {
+        PANORAMA_OVERLAPS.iter().any(|fraction| {
+            let distance = hamming_distance(
+                edge_dhash(a, *edge_a, *fraction),
+                edge_dhash(b, *edge_b, *fraction),
+            );
+            distance <= PANORAMA_EDGE_THRESHOLD
+        })
+    })
+}
+
+/// Same ISO, aperture, shutter speed, exposure bias and (known) focal length
+fn same_exposure(a: &Media, b: &Media) -> bool {
+    let (Some(a), Some(b)) = (a.exif_data.as_ref(), b.exif_data.as_ref()) else {
+        return false;
+    };
+
+    a.focal_length.is_some()
+        && a.focal_length == b.focal_length
+        && a.iso == b.iso
+        && a.aperture == b.aperture
+        && a.shutter_speed == b.shutter_speed
+        && a.exposure_bias == b.exposure_bias
+}
+
+fn classify_run<'a>(camera: String, frames: Vec<Frame<'a>>) -> Vec<Stack<'a>> {
+    if frames.len() < 2 {
+        return Vec::new();
+    }
+
+    if is_ae_bracket(&frames) {
+        split_brackets(frames)
+            .into_iter()
+            .filter(|bracket| bracket.len() > 1)
+            .map(|bracket| new_stack("ae_bracket", camera.clone(), bracket))
+            .collect()
+    } else if is_focus_bracket(&frames) {
+        vec![new_stack("focus_bracket", camera, frames)]
+    } else if frames.len() >= MIN_BURST_FRAMES {
+        vec![new_stack("burst", camera, frames)]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Stores the stacks of one folder and their frames, replacing what an earlier scan found
+/// there. Panorama frames are tagged "panorama" and get `panorama` set to the name of their
+/// first frame, so they're exported together.
+pub fn store_stacks(conn: &Connection, media_items: &mut [Media]) -> rusqlite::Result<()> {
+    let mut panoramas: HashMap<String, String> = HashMap::new();
+
+    let hashes = media_items
+        .iter()
+        .map(|media| media.hash.as_str())
+        .collect::<Vec<_>>();
+    operations::delete_stacks_with_members(conn, &hashes)?;
+
+    // Decoding every pair again on each scan is slow, so what was found is kept by hash.
+    // The last decoded frame is reused as the first of the next pair.
+    let mut last_image: Option<(PathBuf, Option<DynamicImage>)> = None;
+    let mut overlaps = |a: &Media, b: &Media| cached_overlap(conn, a, b, &mut last_image);
+
+    for stack in find_stacks(media_items, &mut overlaps) {
+        let first = stack.frames[0][0];
+        let date_taken = first
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.date_taken.as_deref());
+
+        let members = stack
+            .frames
+            .iter()
+            .enumerate()
+            .flat_map(|(position, files)| files.iter().map(move |m| (position, m.hash.as_str())))
+            .collect::<Vec<_>>();
+
+        operations::insert_stack(
+            conn,
+            &stack_key(&members),
+            stack.kind,
+            &stack.camera,
+            date_taken,
+            &stack.frames[stack.pick][0].hash,
+            &members,
+        )?;
+
+        if stack.kind == "panorama" {
+            let name = first
+                .file_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or(&first.hash)
+                .to_string();
+
+            let ids = members
+                .iter()
+                .map(|(_, hash)| operations::media_file_id_by_hash(conn, hash))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            operations::tag_media_files(conn, PANORAMA_TAG, &ids, "stack")?;
+
+            for (_, hash) in members {
+                panoramas.insert(hash.to_string(), name.clone());
+            }
+        }
+    }
+
+    for media in media_items.iter_mut() {
+        media.panorama = panoramas.get(&media.hash).cloned();
+    }
+
+    Ok(())
+}
+
+/// Whether `b` carries on the panorama of `a`, from the catalog when the pair was compared
+/// before. Pairs where a frame couldn't be decoded aren't recorded, so they're tried again.
+fn cached_overlap(
+    conn: &Connection,
+    a: &Media,
+    b: &Media,
+    last_image: &mut Option<(PathBuf, Option<DynamicImage>)>,
+) -> bool {
+    match operations::panorama_pair(conn, &a.hash, &b.hash) {
+        Ok(Some(overlaps)) => return overlaps,
+        Ok(None) => {}
+        Err(e) => println!(
+            "Error reading panorama pair; Path : {:?}; Error : {:?}",
+            b.file_path.to_str(),
+            e
+        ),
+    }
+
+    let image_a = match last_image.take() {
+        Some((path, image)) if path == a.file_path => image,
+        _ => load_image(&a.file_path),
+    };
+    let image_b = load_image(&b.file_path);
+
+    let overlaps = match (&image_a, &image_b) {
+        (Some(image_a), Some(image_b)) => {
+            let overlaps = frames_overlap(image_a, image_b);
+
+            if let Err(e) = operations::insert_panorama_pair(conn, &a.hash, &b.hash, overlaps) {
+                println!(
+                    "Error inserting panorama pair; Path : {:?}; Error : {:?}",
+                    b.file_path.to_str(),
+                    e
+                );
+            }
+
+            overlaps
+        }
+        _ => false,
+    };
+
+    *last_image = Some((b.file_path.clone(), image_b));
+
+    overlaps
+}
+
+/// SHA-256 of the sorted hashes of every file in the stack
+fn stack_key(members: &[(usize, &str)]) -> String {
+    let mut hashes = members.iter().map(|(_, hash)| *hash).collect::<Vec<_>>();
+    hashes.sort_unstable();
+
+    format!("{:x}", Sha256::digest(hashes.join(",")))
+}
+
+fn new_stack<'a>(kind: &'static str, camera: String, frames: Vec<Frame<'a>>) -> Stack<'a> {
+    let pick = pick_frame(kind, &frames);
+
+    Stack {
+        kind,
+        camera,
+        frames,
+        pick,
+    }
+}
+
+/// The highest rated frame if any was rated, otherwise the normal exposure of an AE bracket,
+/// the middle of a focus bracket or panorama and the first frame of a burst
+fn pick_frame(kind: &str, frames: &[Vec<&Media>]) -> usize {
+    let rating = |files: &Frame| {
+        files
+            .iter()
+            .filter_map(|m| m.sidecar.as_ref().and_then(|s| s.rating))
+            .max()
+    };
+
+    if let Some((index, _)) = frames
+        .iter()
+        .enumerate()
+        .filter_map(|(i, files)| rating(files).map(|r| (i, r)))
+        .max_by_key(|(i, r)| (*r, std::cmp::Reverse(*i)))
+    {
+        return index;
+    }
+
+    match kind {
+        "ae_bracket" => frames
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let bias = |files: &Frame| exposure_bias(files[0]).unwrap_or(0.0).abs();
+                bias(a).total_cmp(&bias(b))
+            })
+            .map(|(i, _)| i)
+            .unwrap_or(0),
+        "focus_bracket" | "panorama" => frames.len() / 2,
+        _ => 0,
+    }
+}
+
+fn is_ae_bracket(frames: &[Vec<&Media>]) -> bool {
+    let auto_bracket = frames.iter().any(|files| {
+        files[0]
+            .exif_data
+            .as_ref()
+            .and_then(|e| e.exposure_mode.as_deref())
+            == Some("auto bracket")
+    });
+
+    let mut biases = frames
+        .iter()
+        .filter_map(|files| exposure_bias(files[0]))
+        .collect::<Vec<_>>();
+    biases.sort_by(f64::total_cmp);
+    biases.dedup();
+
+    auto_bracket || biases.len() > 1
+}
+
+/// Consecutive brackets (0, -1, +1, 0, -1, +1) split wherever a bias repeats
+fn split_brackets(frames: Vec<Frame>) -> Vec<Vec<Frame>> {
+    let mut brackets: Vec<Vec<Frame>> = vec![Vec::new()];
+    let mut seen: Vec<Option<f64>> = Vec::new();
+
+    for files in frames {
+        let bias = exposure_bias(files[0]);
+
+        if seen.contains(&bias) {
+            brackets.push(Vec::new());
+            seen.clear();
+        }
+
+        seen.push(bias);
+        brackets.last_mut().unwrap().push(files);
+    }
+
+    brackets
+}
+
+fn is_focus_bracket(frames: &[Vec<&Media>]) -> bool {
+    let Some(distances) = frames
+        .iter()
+        .map(|files| subject_distance(files[0]))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return false;
+    };
+
+    let steps = distances
+        .windows(2)
+        .map(|w| w[1] - w[0])
+        .collect::<Vec<_>>();
+
+    steps.iter().all(|s| *s > 0.0) || steps.iter().all(|s| *s < 0.0)
+}
+
+fn camera(media: &Media) -> String {
+    let exif = media.exif_data.as_ref();
+    let field = |f: Option<&String>| {
+        f.map(|s| s.trim_matches('"').to_string())
+            .unwrap_or_default()
+    };
+
+    format!(
+        "{} {} {}",
+        field(exif.and_then(|e| e.camera_make.as_ref())),
+        field(exif.and_then(|e| e.camera_model.as_ref())),
+        field(exif.and_then(|e| e.camera_serial.as_ref()))
+    )
+    .trim()
+    .to_string()
+}
+
+/// Capture time in seconds, with the sub second part when the camera records it
+fn capture_seconds(media: &Media) -> Option<f64> {
+    let exif = media.exif_data.as_ref()?;
+    let time =
+        NaiveDateTime::parse_from_str(exif.date_taken.as_deref()?, "%Y-%m-%d %H:%M:%S").ok()?;
+
+    let fraction = exif
+        .sub_sec_time
+        .as_deref()
+        .map(|s| s.trim_matches('"').trim())
+        .filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|digits| format!("0.{}", digits).parse::<f64>().ok())
+        .unwrap_or(0.0);
+
+    Some(time.and_utc().timestamp() as f64 + fraction)
+}
+
+fn exposure_bias(media: &Media) -> Option<f64> {
+    media
+        .exif_data
+        .as_ref()?
+        .exposure_bias
+        .as_deref()?
+        .parse()
+        .ok()
+}
+
+fn subject_distance(media: &Media) -> Option<f64> {
+    // "unknown" and "infinity" don't parse, which is what we want
+    media
+        .exif_data
+        .as_ref()?
+        .subject_distance
+        .as_deref()?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::core::{ExifData, FileType, ImageFormat};
+
+    /// A JPEG shot at `time` + `sub_sec` of a second, named after `name`
+    fn shot(name: &str, time: &str, sub_sec: Option<&str>) -> Media {
+        Media {
+            file_path: PathBuf::from(format!("/photos/{}.jpg", name)),
+            file_name: format!("{}.jpg", name),
+            file_type: FileType::Image(ImageFormat::Jpg),
+            file_size: 1,
+            exif_data: Some(ExifData {
+                camera_make: Some("\"SONY\"".to_string()),
+                camera_model: Some("\"ILCE-7M3\"".to_string()),
+                lens_model: None,
+                date_taken: Some(time.to_string()),
+                iso: Some("100".to_string()),
+                aperture: Some("f/8".to_string()),
+                shutter_speed: Some("1/250 s".to_string()),
+                focal_length: Some("35 mm".to_string()),
+                software: None,
+                date_time_original: None,
+                camera_serial: None,
+                image_unique_id: None,
+                exposure_bias: None,
+                exposure_mode: None,
+                subject_distance: None,
+                sub_sec_time: sub_sec.map(str::to_string),
+            }),
+            hash: name.to_string(),
+            sidecar: None,
+            keywords: Vec::new(),
+            rendition: Rendition::Original,
+            asset_key: None,
+            derived_from_name: None,
+            content_identifier: None,
+            motion_photo: false,
+            resolution: None,
+            panorama: None,
+        }
+    }
+
+    fn with_exif(mut media: Media, edit: impl FnOnce(&mut ExifData)) -> Media {
+        edit(media.exif_data.as_mut().unwrap());
+        media
+    }
+
+    fn no_overlap(_: &Media, _: &Media) -> bool {
+        false
+    }
+
+    /// Kind and frame hashes of each stack, in a stable order
+    fn summary(stacks: &[Stack]) -> Vec<(&'static str, Vec<String>)> {
+        let mut summary = stacks
+            .iter()
+            .map(|s| {
+                let frames = s.frames.iter().map(|f| f[0].hash.clone()).collect();
+                (s.kind, frames)
+            })
+            .collect::<Vec<_>>();
+        summary.sort();
+        summary
+    }
+
+    fn hashes(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn frames_a_second_apart_or_closer_are_one_burst() {
+        // Gaps of exactly MAX_FRAME_GAP_SECONDS, then frames sharing one timestamp
+        let media = vec![
+            shot("a", "2024-05-01 10:00:00", None),
+            shot("b", "2024-05-01 10:00:01", None),
+            shot("c", "2024-05-01 10:00:02", None),
+            shot("d", "2024-05-01 10:00:02", None),
+        ];
+
+        let stacks = find_stacks(&media, &mut no_overlap);
+
+        // c and d come in either order
+        let mut frames = summary(&stacks);
+        frames.iter_mut().for_each(|(_, hashes)| hashes.sort());
+        assert_eq!(frames, vec![("burst", hashes(&["a", "b", "c", "d"]))]);
+        assert_eq!(stacks[0].frames[stacks[0].pick][0].hash, "a");
+    }
+
+    #[test]
+    fn a_gap_over_a_second_splits_bursts() {
+        let media = vec![
+            shot("a", "2024-05-01 10:00:00", Some("0")),
+            shot("b", "2024-05-01 10:00:00", Some("5")),
+            shot("c", "2024-05-01 10:00:01", Some("5")),
+            // 1.01 s after c
+            shot("d", "2024-05-01 10:00:02", Some("51")),
+            shot("e", "2024-05-01 10:00:03", None),
+            shot("f", "2024-05-01 10:00:03", Some("9")),
+        ];
+
+        let stacks = find_stacks(&media, &mut no_overlap);
+
+        assert_eq!(
+            summary(&stacks),
+            vec![
+                ("burst", hashes(&["a", "b", "c"])),
+                ("burst", hashes(&["d", "e", "f"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn two_frames_are_not_a_burst() {
+        let media = vec![
+            shot("a", "2024-05-01 10:00:00", None),
+            shot("b", "2024-05-01 10:00:00", Some("5")),
+        ];
+
+        assert!(find_stacks(&media, &mut no_overlap).is_empty());
+    }
+
+    #[test]
+    fn other_cameras_and_edits_stay_out_of_a_burst() {
+        let other_camera = |media| {
+            with_exif(media, |e| {
+                e.camera_model = Some("\"ILCE-7RM4\"".to_string())
+            })
+        };
+        let mut edit = shot("edit", "2024-05-01 10:00:00", Some("5"));
+        edit.rendition = Rendition::Edited;
+
+        let media = vec![
+            shot("a", "2024-05-01 10:00:00", None),
+            other_camera(shot("x", "2024-05-01 10:00:00", Some("2"))),
+            edit,
+            shot("b", "2024-05-01 10:00:01", None),
+            other_camera(shot("y", "2024-05-01 10:00:01", Some("2"))),
+            shot("c", "2024-05-01 10:00:02", None),
+        ];
+
+        assert_eq!(
+            summary(&find_stacks(&media, &mut no_overlap)),
+            vec![("burst", hashes(&["a", "b", "c"]))]
+        );
+    }
+
+    #[test]
+    fn raw_and_jpeg_of_one_shot_are_one_frame() {
+        let raw = |name: &str, time: &str| {
+            let mut media = shot(name, time, None);
+            media.rendition = Rendition::Raw;
+            media.file_type = FileType::Image(ImageFormat::Arw);
+            media.asset_key = Some(time.to_string());
+            media
+        };
+        let jpeg = |name: &str, time: &str| {
+            let mut media = shot(name, time, None);
+            media.asset_key = Some(time.to_string());
+            media
+        };
+
+        let media = vec![
+            jpeg("a.jpg", "2024-05-01 10:00:00"),
+            raw("a.arw", "2024-05-01 10:00:00"),
+            jpeg("b.jpg", "2024-05-01 10:00:01"),
+            raw("b.arw", "2024-05-01 10:00:01"),
+            raw("c.arw", "2024-05-01 10:00:02"),
+        ];
+
+        let stacks = find_stacks(&media, &mut no_overlap);
+
+        assert_eq!(
+            summary(&stacks),
+            vec![("burst", hashes(&["a.arw", "b.arw", "c.arw"]))]
+        );
+        assert_eq!(stacks[0].frames[0].len(), 2);
+    }
+
+    #[test]
+    fn repeated_bias_patterns_are_split_into_brackets() {
+        let biases = ["0", "-1", "1", "0", "-1", "1"];
+        let media = biases
+            .iter()
+            .enumerate()
+            .map(|(i, bias)| {
+                let media = shot(
+                    &format!("f{}", i),
+                    "2024-05-01 10:00:00",
+                    Some(&i.to_string()),
+                );
+                with_exif(media, |e| e.exposure_bias = Some(bias.to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        let stacks = find_stacks(&media, &mut no_overlap);
+
+        assert_eq!(
+            summary(&stacks),
+            vec![
+                ("ae_bracket", hashes(&["f0", "f1", "f2"])),
+                ("ae_bracket", hashes(&["f3", "f4", "f5"])),
+            ]
+        );
+        // The normal exposure
+        assert!(stacks.iter().all(|s| s.pick == 0));
+    }
+
+    #[test]
+    fn subject_distance_moving_one_way_is_a_focus_bracket() {
+        let media = ["0.5", "1", "2"]
+            .iter()
+            .enumerate()
+            .map(|(i, distance)| {
+                let media = shot(
+                    &format!("f{}", i),
+                    "2024-05-01 10:00:00",
+                    Some(&i.to_string()),
+                );
+                with_exif(media, |e| e.subject_distance = Some(distance.to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        let stacks = find_stacks(&media, &mut no_overlap);
+
+        assert_eq!(
+            summary(&stacks),
+            vec![("focus_bracket", hashes(&["f0", "f1", "f2"]))]
+        );
+        assert_eq!(stacks[0].pick, 1);
+    }
+
+    #[test]
+    fn overlapping_frames_seconds_apart_are_a_panorama() {
+        let media = vec![
+            shot("a", "2024-05-01 10:00:00", None),
+            shot("b", "2024-05-01 10:00:03", None),
+            shot("c", "2024-05-01 10:00:06", None),
+            // Same exposure but doesn't carry on from c
+            shot("d", "2024-05-01 10:00:08", None),
+        ];
+
+        let stacks = find_stacks(&media, &mut |a: &Media, _: &Media| a.hash != "c");
+
+        assert_eq!(
+            summary(&stacks),
+            vec![("panorama", hashes(&["a", "b", "c"]))]
+        );
+        assert_eq!(stacks[0].pick, 1);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    env, fs, io,
+    path::{Component, Path, PathBuf},
+    process,
+};
+
+use crate::{
+    config::{Config, StorageConfig},
+    utils::{
+        core::copy_file_atomic, duplicates::calculate_full_hash, encryption::EncryptedBackend,
+        s3::S3Backend, sftp::SftpBackend, webdav::WebDavBackend,
+    },
+};
+
+/// Somewhere files are written to (a local folder, an SSH server, a WebDAV share, a bucket),
+/// addressed by `/` separated keys relative to the backend's root. Export, sync and
+/// backup only talk to this, so a new kind of destination is a new implementation.
+pub trait StorageBackend {
+    /// Shown in messages and stored with what was written, e.g. `s3://bucket/prefix`
+    fn name(&self) -> String;
+
+    fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Stores the file at `path` under `key`, replacing whatever is there
+    fn put(&self, key: &str, path: &Path) -> io::Result<()>;
+
+    /// Like `put`, with the backend itself checking that what it stored hashes to
+    /// `content_hash` (an S3 checksum, ...). False when it can't, and the caller has to
+    /// read the copy back to know.
+    fn put_checked(&self, key: &str, path: &Path, _content_hash: &str) -> io::Result<bool> {
+        self.put(key, path)?;
+
+        Ok(false)
+    }
+
+    /// Downloads `key` to `path`, replacing whatever is there
+    fn get(&self, key: &str, path: &Path) -> io::Result<()>;
+
+    /// Every key starting with `prefix`, sorted
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// SHA-256 of what is stored under `key`. Backends that can't hash in place
+    /// download the object to a temporary file.
+    fn content_hash(&self, key: &str) -> io::Result<String> {
+        let temp = temp_path("download");
+
+        let hash = self
+            .get(key, &temp)
+            .and_then(|_| calculate_full_hash(&temp));
+        let _ = fs::remove_file(&temp);
+
+        hash
+    }
+}
+
+/// Puts a file and makes sure the stored copy hashes to `content_hash`, with the backend's
+/// own checksum where it has one and by reading it back otherwise. A bad copy is deleted
+/// again and reported as an error.
+pub fn put_verified(
+    backend: &dyn StorageBackend,
+    key: &str,
+    path: &Path,
+    content_hash: &str,
+) -> io::Result<()> {
+    if backend.put_checked(key, path, content_hash)? {
+        return Ok(());
+    }
+
+    let stored_hash = backend.content_hash(key)?;
+
+    if stored_hash != content_hash {
+        let _ = backend.delete(key);
+
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} stored {} with hash {}, expected {}",
+                backend.name(),
+                key,
+                stored_hash,
+                content_hash
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Scratch file for this process, for downloads and encrypted copies on their way out
+pub fn temp_path(purpose: &str) -> PathBuf {
+    env::temp_dir().join(format!("photo_app_rs_{}.{}", process::id(), purpose))
+}
+
+/// Content addressed key: a file always lands on the same object, whatever its name
+/// and however many copies there are
+pub fn object_key(content_hash: &str) -> String {
+    format!("objects/{}/{}", &content_hash[..2], content_hash)
+}
+
+/// Key of a file at `relative_path` from the backend's root
+pub fn key_for(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Key of `path` in a backend whose root `root` stands for. A path outside `root` has no
+/// key, and would otherwise turn into one pointing outside the backend.
+pub fn key_under(root: &Path, path: &Path) -> io::Result<String> {
+    match path.strip_prefix(root) {
+        Ok(relative_path) if is_relative_key(relative_path) => Ok(key_for(relative_path)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not inside {:?}", path, root),
+        )),
+    }
+}
+
+/// Only plain names, no root, `..` or `.`
+fn is_relative_key(relative_path: &Path) -> bool {
+    relative_path.components().next().is_some()
+        && relative_path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// `s3` for the `[s3]` bucket, the name of a `[storage.<name>]` backend, or a local folder
+pub fn open_backend(spec: &str, config: &Config) -> io::Result<Box<dyn StorageBackend>> {
+    if spec == "s3" {
+        return Ok(Box::new(S3Backend::new(&config.s3)?));
+    }
+
+    let Some(storage) = config.storage.get(spec) else {
+        if spec.contains('/') || Path::new(spec).is_dir() {
+            return Ok(Box::new(LocalBackend::new(spec)));
+        }
+
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "No [storage.{}] in the config, and no folder {:?}",
+                spec, spec
+            ),
+        ));
+    };
+
+    Ok(match storage {
+        StorageConfig::Local { path } => Box::new(LocalBackend::new(path)),
+        StorageConfig::Sftp(sftp) => Box::new(SftpBackend::new(sftp)),
+        StorageConfig::Webdav(webdav) => Box::new(WebDavBackend::new(webdav)?),
+        StorageConfig::S3(s3) => Box::new(S3Backend::new(s3)?),
+        StorageConfig::Encrypted(encrypted) => {
+            // Only one layer, which also rules out loops
+            if let Some(StorageConfig::Encrypted(_)) = config.storage.get(&encrypted.storage) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("[storage.{}] wraps another encrypted storage", spec),
+                ));
+            }
+
+            let inner = open_backend(&encrypted.storage, config)?;
+            Box::new(EncryptedBackend::new(inner, encrypted)?)
+        }
+    })
+}
+
+/// A folder on a mounted volume
+pub struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        LocalBackend {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Where `key` is stored; keys that would land outside the root are refused
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        if !is_relative_key(Path::new(key)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Key {:?} points outside {:?}", key, self.root),
+            ));
+        }
+
+        Ok(self.root.join(key))
+    }
+
+    fn collect_keys(&self, folder: &Path, keys: &mut Vec<String>) -> io::Result<()> {
+        for entry in fs::read_dir(folder)? {
+            let path = entry?.path();
+
+            if path.is_dir() {
+                self.collect_keys(&path, keys)?;
+            } else if let Ok(relative_path) = path.strip_prefix(&self.root) {
+                keys.push(key_for(relative_path));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn name(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.path(key)?.is_file())
+    }
+
+    fn put(&self, key: &str, path: &Path) -> io::Result<()> {
+        copy_file_atomic(path, &self.path(key)?)
+    }
+
+    fn get(&self, key: &str, path: &Path) -> io::Result<()> {
+        copy_file_atomic(&self.path(key)?, path)
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+
+        if self.root.is_dir() {
+            self.collect_keys(&self.root, &mut keys)?;
+        }
+
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        fs::remove_file(self.path(key)?)
+    }
+
+    fn content_hash(&self, key: &str) -> io::Result<String> {
+        calculate_full_hash(&self.path(key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty folder for one test
+    fn scratch(name: &str) -> PathBuf {
+        let folder = env::temp_dir().join(format!("photo_app_rs_test_{}_{}", process::id(), name));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    /// What every backend has to do; run against each one that can be tested here
+    fn storage_contract(backend: &dyn StorageBackend, scratch: &Path) {
+        let file = scratch.join("file.jpg");
+        fs::write(&file, b"first version").unwrap();
+        let content_hash = calculate_full_hash(&file).unwrap();
+
+        assert!(!backend.exists("2024/01/file.jpg").unwrap());
+        assert_eq!(backend.list("").unwrap(), Vec::<String>::new());
+
+        put_verified(backend, "2024/01/file.jpg", &file, &content_hash).unwrap();
+        assert!(backend.exists("2024/01/file.jpg").unwrap());
+        assert_eq!(
+            backend.content_hash("2024/01/file.jpg").unwrap(),
+            content_hash
+        );
+
+        // Replaced, not appended to
+        fs::write(&file, b"second").unwrap();
+        backend.put("2024/01/file.jpg", &file).unwrap();
+        backend.put("2024/02/other.jpg", &file).unwrap();
+        backend.put("2025/01/file.jpg", &file).unwrap();
+
+        let downloaded = scratch.join("downloads/file.jpg");
+        backend.get("2024/01/file.jpg", &downloaded).unwrap();
+        assert_eq!(fs::read(&downloaded).unwrap(), b"second");
+
+        assert_eq!(
+            backend.list("2024/").unwrap(),
+            ["2024/01/file.jpg", "2024/02/other.jpg"]
+        );
+        assert_eq!(backend.list("").unwrap().len(), 3);
+
+        backend.delete("2024/01/file.jpg").unwrap();
+        assert!(!backend.exists("2024/01/file.jpg").unwrap());
+        assert!(backend.get("2024/01/file.jpg", &downloaded).is_err());
+        assert_eq!(backend.list("2024/").unwrap(), ["2024/02/other.jpg"]);
+
+        // A copy that doesn't match is refused and not left behind
+        assert!(put_verified(backend, "bad.jpg", &file, &content_hash).is_err());
+        assert!(!backend.exists("bad.jpg").unwrap());
+    }
+
+    #[test]
+    fn local_backend_keeps_the_storage_contract() {
+        let scratch = scratch("local_contract");
+        let backend = LocalBackend::new(scratch.join("root"));
+
+        storage_contract(&backend, &scratch);
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn local_backend_refuses_keys_outside_its_root() {
+        let scratch = scratch("local_escape");
+        let backend = LocalBackend::new(scratch.join("root"));
+        let file = scratch.join("file.jpg");
+        fs::write(&file, b"data").unwrap();
+
+        for key in ["/etc/file.jpg", "../file.jpg", "2024/../../file.jpg", ""] {
+            assert_eq!(
+                backend.put(key, &file).unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn only_paths_inside_the_root_have_a_key() {
+        let root = Path::new("/library");
+
+        assert_eq!(
+            key_under(root, Path::new("/library/2024/01/a.jpg")).unwrap(),
+            "2024/01/a.jpg"
+        );
+        assert!(key_under(root, Path::new("/elsewhere/a.jpg")).is_err());
+        assert!(key_under(root, Path::new("/library/../a.jpg")).is_err());
+        assert!(key_under(root, root).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::utils::{
+    embedded::{JpegSegments, iptc_keywords},
+    sidecar::{Sidecar, xmp_keywords},
+};
+
+pub const TAG_SEPARATOR: char = '/';
+
+/// Cleans up a tag name: `" trips / 2025/japan/ "` -> `"trips/2025/japan"`
+pub fn normalize_tag(name: &str) -> Option<String> {
+    let segments = name
+        .split(TAG_SEPARATOR)
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+
+    if segments.is_empty() {
+        None
+    } else {
+        Some(segments.join("/"))
+    }
+}
+
+/// Every ancestor of a hierarchical tag, root first, ending with the tag itself.
+/// `trips/2025/japan` -> `trips`, `trips/2025`, `trips/2025/japan`
+pub fn tag_ancestors(name: &str) -> Vec<String> {
+    let segments = name.split(TAG_SEPARATOR).collect::<Vec<_>>();
+
+    (1..=segments.len())
+        .map(|i| segments[..i].join("/"))
+        .collect()
+}
+
+/// Keywords from the embedded IPTC / XMP blocks plus the XMP sidecar, normalised and deduplicated
+pub fn collect_keywords(embedded: Option<&JpegSegments>, sidecar: Option<&Sidecar>) -> Vec<String> {
+    let mut raw_keywords: Vec<String> = Vec::new();
+
+    if let Some(segments) = embedded {
+        if let Some(iptc) = &segments.iptc {
+            raw_keywords.extend(iptc_keywords(iptc));
+        }
+        if let Some(xmp) = &segments.xmp {
+            raw_keywords.extend(xmp_keywords(xmp));
+        }
+    }
+
+    if let Some(sidecar) = sidecar {
+        raw_keywords.extend(sidecar.keywords.iter().cloned());
+    }
+
+    let mut keywords: Vec<String> = Vec::new();
+    for keyword in raw_keywords.iter().filter_map(|k| normalize_tag(k)) {
+        if !keywords.iter().any(|k| k.eq_ignore_ascii_case(&keyword)) {
+            keywords.push(keyword);
+        }
+    }
+
+    keywords
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_hierarchical_tags() {
+        assert_eq!(
+            normalize_tag(" trips / 2025//new   york/ ").as_deref(),
+            Some("trips/2025/new york")
+        );
+        assert_eq!(normalize_tag(" / / "), None);
+        assert_eq!(normalize_tag(""), None);
+
+        assert_eq!(
+            tag_ancestors("trips/2025/japan"),
+            ["trips", "trips/2025", "trips/2025/japan"]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::HashMap, io, path::Path, process::Command};
+
+use crate::utils::perceptual::{dhash_from_luma, hamming_distance};
+
+/// Where frames are sampled, as a fraction of the duration. Relative positions line up
+/// between a clip and its re-encoded / trimmed-by-a-frame copies.
+const SAMPLE_POSITIONS: &[f64] = &[0.1, 0.25, 0.4, 0.55, 0.7, 0.85];
+
+/// Durations may differ a little after re-encoding (container padding, dropped frames)
+const DURATION_TOLERANCE_SECONDS: f64 = 1.0;
+const DURATION_TOLERANCE_RATIO: f64 = 0.02;
+
+#[derive(Debug, Clone)]
+pub struct VideoFingerprint {
+    pub duration_seconds: f64,
+    pub frame_hashes: Vec<u64>,
+}
+
+impl VideoFingerprint {
+    /// Samples frames with the `ffmpeg` command line tool, scaled straight to the 9x8 grayscale dHash input.
+    /// The duration comes from `ffprobe` too, so a corrupt video is an error rather than a panic.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let duration_seconds = probe_duration(path)?;
+        if duration_seconds <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Video has no duration",
+            ));
+        }
+
+        let frame_hashes = SAMPLE_POSITIONS
+            .iter()
+            .map(|position| sample_frame(path, duration_seconds * position))
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        Ok(VideoFingerprint {
+            duration_seconds,
+            frame_hashes,
+        })
+    }
+
+    /// Stored as comma separated hex, one hash per sampled frame
+    pub fn frame_hashes_to_string(&self) -> String {
+        self.frame_hashes
+            .iter()
+            .map(|h| format!("{:016x}", h))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn from_stored(duration_seconds: f64, frame_hashes: &str) -> Option<Self> {
+        let frame_hashes = frame_hashes
+            .split(',')
+            .map(|h| u64::from_str_radix(h, 16).ok())
+            .collect::<Option<Vec<u64>>>()?;
+
+        Some(VideoFingerprint {
+            duration_seconds,
+            frame_hashes,
+        })
+    }
+
+    pub fn durations_match(&self, other: &VideoFingerprint) -> bool {
+        let tolerance = DURATION_TOLERANCE_SECONDS
+            .max(self.duration_seconds.max(other.duration_seconds) * DURATION_TOLERANCE_RATIO);
+
+        (self.duration_seconds - other.duration_seconds).abs() <= tolerance
+    }
+
+    /// Mean Hamming distance between the frames sampled at the same positions
+    pub fn distance(&self, other: &VideoFingerprint) -> u32 {
+        let frames = self.frame_hashes.len().min(other.frame_hashes.len()).max(1);
+
+        let total: u32 = self
+            .frame_hashes
+            .iter()
+            .zip(&other.frame_hashes)
+            .map(|(a, b)| hamming_distance(*a, *b))
+            .sum();
+
+        total / frames as u32
+    }
+}
+
+fn probe_duration(path: &Path) -> io::Result<f64> {
+    let mut command = Command::new("ffprobe");
+    command
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(path);
+
+    let output = run_tool(&mut command, "ffprobe")?;
+
+    String::from_utf8_lossy(&output)
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "ffprobe found no duration"))
+}
+
+fn sample_frame(path: &Path, at_seconds: f64) -> io::Result<u64> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-v", "error", "-ss", &format!("{:.3}", at_seconds), "-i"])
+        .arg(path)
+        .args([
+            "-frames:v",
+            "1",
+            "-vf",
+            "scale=9:8,format=gray",
+            "-f",
+            "rawvideo",
+            "-",
+        ]);
+
+    let output = run_tool(&mut command, "ffmpeg")?;
+
+    if output.len() < 72 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No frame at {:.3}s", at_seconds),
+        ));
+    }
+
+    Ok(dhash_from_luma(&output[..72]))
+}
+
+/// Runs an ffmpeg tool and returns its stdout. A missing binary is `NotFound`, so callers
+/// can stop instead of failing every video the same way.
+fn run_tool(command: &mut Command, name: &str) -> io::Result<Vec<u8>> {
+    let output = command.output().map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "{} not found; fingerprinting videos needs the ffmpeg command line tools on PATH",
+                    name
+                ),
+            )
+        } else {
+            e
+        }
+    })?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output.stdout)
+}
+
+/// Groups videos whose durations match and whose frames are on average within
+/// `threshold` bits. Same shape as `cluster_by_hash`: `(media_file_id, distance from first member)`.
+pub fn cluster_videos(items: &[(i64, VideoFingerprint)], threshold: u32) -> Vec<Vec<(i64, u32)>> {
+    let mut sorted = items.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| a.1.duration_seconds.total_cmp(&b.1.duration_seconds));
+
+    // Union-find over indexes of `sorted`
+    let mut parent = (0..sorted.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..sorted.len() {
+        // Sorted by duration, so only the following videos with a matching duration are candidates
+        for j in i + 1..sorted.len() {
+            if !sorted[i].1.durations_match(&sorted[j].1) {
+                break;
+            }
+
+            if sorted[i].1.distance(&sorted[j].1) <= threshold {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[b] = a;
+            }
+        }
+    }
+
+    let mut clusters: Vec<Vec<usize>> = Vec::new();
+    let mut cluster_of_root: HashMap<usize, usize> = HashMap::new();
+
+    for i in 0..sorted.len() {
+        let r = root(&mut parent, i);
+        let cluster = *cluster_of_root.entry(r).or_insert_with(|| {
+            clusters.push(Vec::new());
+            clusters.len() - 1
+        });
+        clusters[cluster].push(i);
+    }
+
+    clusters
+        .into_iter()
+        .filter(|c| c.len() > 1)
+        .map(|c| {
+            let reference = &sorted[c[0]].1;
+            c.iter()
+                .map(|&i| (sorted[i].0, reference.distance(&sorted[i].1)))
+                .collect()
+        })
+        .collect()
+}
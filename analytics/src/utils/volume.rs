@@ -0,0 +1,164 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Label used for files on the system disk
+pub const LOCAL_DEVICE: &str = "local";
+
+/// Name of the volume `path` lives on: the last component of its mount point
+/// (`/media/me/SANDISK` -> `SANDISK`, `/Volumes/T7` -> `T7`), or "local" for the system disk
+pub fn device_label(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    mount_for(&path)
+        .map(|(_, mount_point)| mount_point)
+        .filter(|mount_point| mount_point.parent().is_some())
+        .and_then(|mount_point| {
+            mount_point
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.to_string())
+        })
+        .unwrap_or_else(|| LOCAL_DEVICE.to_string())
+}
+
+/// Root of the volume `path` lives on, `/` when no mount contains it
+pub fn mount_point(path: &Path) -> PathBuf {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    mount_for(&path)
+        .map(|(_, mount_point)| mount_point)
+        .unwrap_or_else(|| PathBuf::from("/"))
+}
+
+/// Filesystem UUID of the volume `path` lives on (the FAT/exFAT serial on an SD card,
+/// which changes when the card is formatted). Looked up in /dev/disk/by-uuid, so Linux only.
+pub fn volume_uuid(path: &Path) -> Option<String> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let (device, _) = mount_for(&path)?;
+    let device = fs::canonicalize(device).ok()?;
+
+    fs::read_dir("/dev/disk/by-uuid")
+        .ok()?
+        .filter_map(|e| e.ok())
+        .find(|e| fs::canonicalize(e.path()).is_ok_and(|target| target == device))
+        .and_then(|e| e.file_name().to_str().map(|n| n.to_string()))
+}
+
+/// Whether the volume `path` lives on is one that gets plugged in and out: an SD card, a USB
+/// stick or disk (Linux sysfs says so), or anything under /Volumes on macOS
+pub fn is_removable(path: &Path) -> bool {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+
+    let Some((device, mount_point)) = mount_for(&path) else {
+        return false;
+    };
+
+    if device.is_empty() {
+        return mount_point.starts_with("/Volumes");
+    }
+
+    let Some(name) = fs::canonicalize(&device)
+        .ok()
+        .and_then(|device| device.file_name().map(|n| n.to_os_string()))
+    else {
+        return false;
+    };
+
+    // /sys/devices/.../usb2/.../block/sdb/sdb1
+    let Ok(sys_path) = fs::canonicalize(Path::new("/sys/class/block").join(name)) else {
+        return false;
+    };
+
+    let sys = sys_path.to_string_lossy();
+    if sys.contains("/usb") || sys.contains("/mmc") {
+        return true;
+    }
+
+    // Partitions don't carry the flag, their disk does
+    [Some(sys_path.as_path()), sys_path.parent()]
+        .into_iter()
+        .flatten()
+        .any(|dir| fs::read_to_string(dir.join("removable")).is_ok_and(|flag| flag.trim() == "1"))
+}
+
+/// Device and mount point of the deepest mount containing `path`
+fn mount_for(path: &Path) -> Option<(String, PathBuf)> {
+    mounts()
+        .into_iter()
+        .filter(|(_, mount_point)| path.starts_with(mount_point))
+        .max_by_key(|(_, mount_point)| mount_point.components().count())
+}
+
+/// (device, mount point) pairs from /proc/self/mounts on Linux; on macOS, where that
+/// doesn't exist, every volume is mounted under /Volumes and the device is left empty
+fn mounts() -> Vec<(String, PathBuf)> {
+    match fs::read_to_string("/proc/self/mounts") {
+        Ok(mounts) => mounts
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some((fields.next()?, fields.next()?))
+            })
+            .map(|(device, mount_point)| {
+                (
+                    unescape_mount_point(device),
+                    PathBuf::from(unescape_mount_point(mount_point)),
+                )
+            })
+            .collect(),
+        Err(_) => fs::read_dir("/Volumes")
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| (String::new(), e.path()))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    }
+}
+
+/// Everything mounted right now, from /proc/self/mountinfo on Linux or the volumes
+/// under /Volumes on macOS
+pub fn mount_points() -> Vec<PathBuf> {
+    match fs::read_to_string("/proc/self/mountinfo") {
+        // <id> <parent id> <major:minor> <root> <mount point> <options> ...
+        Ok(mountinfo) => mountinfo
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(4))
+            .map(|mount_point| PathBuf::from(unescape_mount_point(mount_point)))
+            .collect(),
+        Err(_) => mounts()
+            .into_iter()
+            .map(|(_, mount_point)| mount_point)
+            .collect(),
+    }
+}
+
+/// Spaces, tabs and backslashes in mount points are written as octal escapes (`\040`)
+fn unescape_mount_point(mount_point: &str) -> String {
+    let bytes = mount_point.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+
+        match escape {
+            Some(byte) if bytes[i] == b'\\' => {
+                unescaped.push(byte);
+                i += 4;
+            }
+            _ => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
@@ -0,0 +1,273 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use quick_xml::{Reader, events::Event};
+
+use crate::{
+    config::WebDavConfig,
+    utils::{core::write_atomic, s3::uri_encode, storage::StorageBackend},
+};
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/></d:prop></d:propfind>"#;
+
+/// A WebDAV collection. Folders are created with MKCOL as files are put, and uploads
+/// go to a `.part` resource that is then MOVEd over the final name.
+pub struct WebDavBackend {
+    url: String,
+    /// Path part of `url`, what hrefs in PROPFIND responses start with
+    base_path: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl WebDavBackend {
+    pub fn new(config: &WebDavConfig) -> io::Result<Self> {
+        let url = config.url.trim_end_matches('/').to_string();
+
+        let Some((_, rest)) = url.split_once("://") else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Not a WebDAV URL: {:?}", config.url),
+            ));
+        };
+
+        let base_path = rest
+            .find('/')
+            .map(|i| percent_decode(&rest[i..]))
+            .unwrap_or_default();
+
+        let authorization = (!config.username.is_empty()).then(|| {
+            let credentials = format!("{}:{}", config.username, config.password);
+            format!("Basic {}", STANDARD.encode(credentials))
+        });
+
+        Ok(WebDavBackend {
+            url,
+            base_path,
+            authorization,
+            agent: ureq::Agent::new(),
+        })
+    }
+
+    fn key_url(&self, key: &str) -> String {
+        format!("{}/{}", self.url, uri_encode(key, false))
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+
+    /// MKCOL every folder above `key`. Servers answer 405 for one that already exists.
+    fn create_parents(&self, key: &str) -> io::Result<()> {
+        let folders = key.split('/').collect::<Vec<_>>();
+
+        for depth in 1..folders.len() {
+            let url = format!("{}/", self.key_url(&folders[..depth].join("/")));
+
+            match self.request("MKCOL", &url).call() {
+                Ok(_) | Err(ureq::Error::Status(405, _)) => {}
+                Err(e) => return Err(webdav_error(e)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Entries right inside a folder, as keys, with whether each one is a folder
+    fn propfind(&self, folder: &str) -> io::Result<Vec<(String, bool)>> {
+        let url = match folder {
+            "" => format!("{}/", self.url),
+            folder => format!("{}/", self.key_url(folder)),
+        };
+
+        let body = self
+            .request("PROPFIND", &url)
+            .set("Depth", "1")
+            .set("Content-Type", "application/xml")
+            .send_string(PROPFIND_BODY)
+            .map_err(webdav_error)?
+            .into_string()?;
+
+        Ok(parse_multistatus(&body)
+            .into_iter()
+            .map(|(href, is_folder)| (self.href_key(&href), is_folder))
+            .filter(|(key, _)| key != folder)
+            .collect())
+    }
+
+    /// Hrefs are either absolute paths or full URLs, percent encoded
+    fn href_key(&self, href: &str) -> String {
+        let path = match href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map(|i| &rest[i..]).unwrap_or(""),
+            None => href,
+        };
+
+        let path = percent_decode(path);
+
+        path.strip_prefix(&self.base_path)
+            .unwrap_or(&path)
+            .trim_matches('/')
+            .to_string()
+    }
+}
+
+impl StorageBackend for WebDavBackend {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn exists(&self, key: &str) -> io::Result<bool> {
+        match self.request("HEAD", &self.key_url(key)).call() {
+            Ok(_) => Ok(true),
+            Err(ureq::Error::Status(404, _)) => Ok(false),
+            Err(e) => Err(webdav_error(e)),
+        }
+    }
+
+    fn put(&self, key: &str, path: &Path) -> io::Result<()> {
+        self.create_parents(key)?;
+
+        let partial_url = self.key_url(&format!("{}.part", key));
+        let file_size = fs::metadata(path)?.len();
+
+        self.request("PUT", &partial_url)
+            .set("Content-Length", &file_size.to_string())
+            .send(File::open(path)?)
+            .map_err(webdav_error)?;
+
+        self.request("MOVE", &partial_url)
+            .set("Destination", &self.key_url(key))
+            .set("Overwrite", "T")
+            .call()
+            .map_err(webdav_error)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str, path: &Path) -> io::Result<()> {
+        let response = self
+            .request("GET", &self.key_url(key))
+            .call()
+            .map_err(webdav_error)?;
+
+        write_atomic(path, &mut response.into_reader())
+    }
+
+    /// Walks the collection one PROPFIND (Depth: 1) per folder, as many servers
+    /// refuse Depth: infinity
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut folders = vec![String::new()];
+
+        while let Some(folder) = folders.pop() {
+            for (key, is_folder) in self.propfind(&folder)? {
+                if is_folder {
+                    // Only folders that can hold keys starting with `prefix`
+                    let folder_prefix = format!("{}/", key);
+                    if folder_prefix.starts_with(prefix) || prefix.starts_with(&folder_prefix) {
+                        folders.push(key);
+                    }
+                } else if key.starts_with(prefix) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        keys.sort();
+
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        self.request("DELETE", &self.key_url(key))
+            .call()
+            .map_err(webdav_error)?;
+
+        Ok(())
+    }
+}
+
+/// Href of every `<response>` in a 207 Multi-Status body, and whether its
+/// resourcetype is a collection
+fn parse_multistatus(xml: &str) -> Vec<(String, bool)> {
+    let mut reader = Reader::from_str(xml);
+    let mut entries = Vec::new();
+
+    let mut href = String::new();
+    let mut is_folder = false;
+    let mut in_href = false;
+
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => match e.local_name().as_ref() {
+                b"response" => {
+                    href.clear();
+                    is_folder = false;
+                }
+                b"href" => in_href = true,
+                b"collection" => is_folder = true,
+                _ => {}
+            },
+            Ok(Event::Empty(e)) if e.local_name().as_ref() == b"collection" => is_folder = true,
+            Ok(Event::Text(text)) if in_href => {
+                if let Ok(text) = text.unescape() {
+                    href.push_str(text.trim());
+                }
+            }
+            Ok(Event::End(e)) => match e.local_name().as_ref() {
+                b"href" => in_href = false,
+                b"response" => entries.push((href.clone(), is_folder)),
+                _ => {}
+            },
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+
+    entries
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = value.get(i + 1..i + 3);
+
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn webdav_error(e: ureq::Error) -> io::Error {
+    match e {
+        ureq::Error::Status(404, _) => io::Error::from(io::ErrorKind::NotFound),
+        ureq::Error::Status(status, response) => io::Error::other(format!(
+            "WebDAV server returned {} for {}",
+            status,
+            response.get_url()
+        )),
+        e => io::Error::other(e),
+    }
+}
@@ -1,17 +1,33 @@
 // Model
 
 use iced::{
-    widget::{button, column, text},
+    widget::{button, column, scrollable, text, Column},
     Element,
 };
+use rusqlite::{Connection, OpenFlags};
+
+/// The catalog `analytics` writes, read from the same folder it is run in
+const DATABASE_PATH: &str = "./.photo_app_rs/sqlite.db";
 
 struct Counter {
     value: i32,
+    /// Edited exports with the original each was derived from, or why they couldn't be read
+    relations: Result<Vec<Relation>, String>,
+}
+
+/// An edited export and its original, as `analytics relations` lists them
+struct Relation {
+    path: String,
+    related_path: String,
+    matched_by: String,
 }
 
 impl Default for Counter {
     fn default() -> Self {
-        Counter { value: 0 }
+        Counter {
+            value: 0,
+            relations: load_relations(),
+        }
     }
 }
 
@@ -20,6 +36,7 @@ impl Default for Counter {
 enum Messages {
     Increment,
     Decrement,
+    ReloadRelations,
 }
 
 impl Counter {
@@ -27,19 +44,66 @@ impl Counter {
         match message {
             Messages::Increment => self.value += 1,
             Messages::Decrement => self.value -= 1,
+            Messages::ReloadRelations => self.relations = load_relations(),
         }
     }
 
-    fn view(&self) -> Element<Messages> {
+    fn view(&self) -> Element<'_, Messages> {
+        let relations: Element<'_, Messages> = match &self.relations {
+            Ok(relations) if relations.is_empty() => {
+                text("No edited exports linked to an original yet").into()
+            }
+            Ok(relations) => scrollable(Column::with_children(relations.iter().map(|r| {
+                text(format!(
+                    "{}  derived from  {}  ({})",
+                    r.path, r.related_path, r.matched_by
+                ))
+                .into()
+            })))
+            .into(),
+            Err(e) => text(format!("Could not read the catalog: {}", e)).into(),
+        };
+
         column![
             button("Increment").on_press(Messages::Increment),
             text(self.value),
-            button("Decrement").on_press(Messages::Decrement)
+            button("Decrement").on_press(Messages::Decrement),
+            text("Edited exports"),
+            button("Reload").on_press(Messages::ReloadRelations),
+            relations
         ]
         .into()
     }
 }
 
+fn load_relations() -> Result<Vec<Relation>, String> {
+    let conn = Connection::open_with_flags(DATABASE_PATH, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT m.path, o.path, r.matched_by FROM media_relations r
+               JOIN media_files m ON m.id = r.media_file_id
+               JOIN media_files o ON o.id = r.related_media_file_id
+              WHERE r.relation = 'derived_from'
+              ORDER BY o.path, m.path",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let relations = stmt
+        .query_map([], |row| {
+            Ok(Relation {
+                path: row.get(0)?,
+                related_path: row.get(1)?,
+                matched_by: row.get(2)?,
+            })
+        })
+        .and_then(|rows| rows.collect())
+        .map_err(|e| e.to_string());
+
+    relations
+}
+
 fn main() -> iced::Result {
     iced::run(Counter::update, Counter::view)
 }